@@ -16,6 +16,10 @@ enum GifskiWrapperError: UInt32, LocalizedError {
 	case unexpectedEof
 	case aborted
 	case other
+	case noFrames
+	case wrongSize
+	case decode
+	case inputStalled
 
 	var errorDescription: String? {
 		switch self {
@@ -43,6 +47,14 @@ enum GifskiWrapperError: UInt32, LocalizedError {
 			return "Progress callback returned 0, writing aborted"
 		case .other:
 			return "Should not happen, file a bug: https://github.com/ImageOptim/gifski"
+		case .noFrames:
+			return "None of the frames could be used"
+		case .wrongSize:
+			return "Frames have different sizes"
+		case .decode:
+			return "An input image could not be decoded"
+		case .inputStalled:
+			return "No frames were added for too long"
 		}
 	}
 }
@@ -11,11 +11,17 @@ extern "C" {
 struct gifski;
 typedef struct gifski gifski;
 
+struct gifski_settings;
+typedef struct gifski_settings gifski_settings;
+
 /**
 How to use from C
 
 ```c
-gifski *g = gifski_new(&(GifskiSettings){});
+gifski_settings *s = gifski_settings_new();
+gifski_settings_set_quality(s, 90);
+gifski *g = gifski_new_with(s);
+gifski_settings_free(s);
 gifski_set_file_output(g, "file.gif");
 
 for(int i=0; i < frames; i++) {
@@ -56,6 +62,9 @@ typedef struct GifskiSettings {
   int16_t repeat;
 } GifskiSettings;
 
+/**
+ * Values of these codes will never change, but new codes may be added.
+ */
 enum GifskiError {
   GIFSKI_OK = 0,
   /** one of input arguments was NULL */
@@ -88,6 +97,14 @@ enum GifskiError {
   ABORTED,
   /** should not happen, file a bug */
   GIFSKI_OTHER,
+  /** none of the frames could be used */
+  GIFSKI_NO_FRAMES,
+  /** frames have different sizes */
+  GIFSKI_WRONG_SIZE,
+  /** an input image couldn't be decoded */
+  GIFSKI_DECODE,
+  /** no frames were added for too long */
+  GIFSKI_INPUT_STALLED,
 };
 
 typedef enum GifskiError GifskiError;
@@ -98,9 +115,62 @@ typedef enum GifskiError GifskiError;
  * See `gifski_add_frame_png_file` and `gifski_end_adding_frames`
  *
  * Returns a handle for the other functions, or `NULL` on error (if the settings are invalid).
+ *
+ * New options are only added to `gifski_settings_new()`/`gifski_new_with()`. This struct won't change.
  */
 gifski *gifski_new(const GifskiSettings *settings);
 
+/**
+ * Same as `gifski_new`, but takes settings made with `gifski_settings_new()`.
+ *
+ * The settings object is only read, and can be freed with `gifski_settings_free()` right after this call.
+ *
+ * Returns a handle for the other functions, or `NULL` on error (if the settings are invalid).
+ */
+gifski *gifski_new_with(const gifski_settings *settings);
+
+/**
+ * Creates settings with default values, to be changed with `gifski_settings_set_*` and passed to `gifski_new_with()`.
+ *
+ * Must be freed with `gifski_settings_free()`.
+ */
+gifski_settings *gifski_settings_new(void);
+
+/**
+ * Frees settings created with `gifski_settings_new()`. `NULL` is ignored.
+ */
+void gifski_settings_free(gifski_settings *settings);
+
+/**
+ * Resize to max this width if non-0. Height is used only if width is non-0. Note that aspect ratio is not preserved.
+ */
+GifskiError gifski_settings_set_size(gifski_settings *settings, uint32_t width, uint32_t height);
+
+/**
+ * 1-100, but useful range is 50-100. Recommended to set to 90.
+ */
+GifskiError gifski_settings_set_quality(gifski_settings *settings, uint8_t quality);
+
+/**
+ * 1-100. Lower values allow more motion artifacts in exchange for smaller files. 0 to use the same value as quality.
+ */
+GifskiError gifski_settings_set_motion_quality(gifski_settings *settings, uint8_t quality);
+
+/**
+ * Lower quality, but faster encode.
+ */
+GifskiError gifski_settings_set_fast(gifski_settings *settings, bool fast);
+
+/**
+ * Slower encode for slightly smaller files.
+ */
+GifskiError gifski_settings_set_extra_effort(gifski_settings *settings, bool extra);
+
+/**
+ * If negative, looping is disabled. The number of times the sequence is repeated. 0 to loop forever.
+ */
+GifskiError gifski_settings_set_repeat(gifski_settings *settings, int16_t repeat);
+
 /**
  * Adds a frame to the animation. This function is asynchronous.
  *
@@ -115,6 +185,8 @@ gifski *gifski_new(const GifskiSettings *settings);
  *
  * The first frame should have PTS=0. If the first frame has PTS > 0, it'll be used as a delay after the last frame.
  *
+ * Not available if the library has been built without the `png` feature.
+ *
  * Returns 0 (`GIFSKI_OK`) on success, and non-0 `GIFSKI_*` constant on error.
  */
 GifskiError gifski_add_frame_png_file(gifski *handle,
@@ -157,8 +229,23 @@ GifskiError gifski_add_frame_argb(gifski *handle,
                                   const unsigned char *pixels,
                                   double presentation_timestamp);
 
+/** Same as `gifski_add_frame_rgba`, except it expects components in BGRA order,
+as in `kCVPixelFormatType_32BGRA` and Windows bitmaps.
+
+Bytes per row must be multiple of 4, and greater or equal width×4.
+*/
+GifskiError gifski_add_frame_bgra(gifski *handle,
+                                  uint32_t frame_number,
+                                  uint32_t width,
+                                  uint32_t bytes_per_row,
+                                  uint32_t height,
+                                  const unsigned char *pixels,
+                                  double presentation_timestamp);
+
 /** Same as `gifski_add_frame_rgba`, except it expects RGB components (3 bytes per pixel)
 
+It's faster than adding the same frame as RGBA, because there's no transparency to process.
+
 Bytes per row must be multiple of 3, and greater or equal width×3.
 If the bytes per row value is invalid (not multiple of 3), frames may look sheared/skewed.
 */
@@ -187,6 +274,26 @@ GifskiError gifski_add_frame_rgb(gifski *handle,
  */
 void gifski_set_progress_callback(gifski *handle, int (*progress_callback)(void *user_data), void *user_data);
 
+/**
+ * Same as `gifski_set_progress_callback`, but the callback also gets the size of the output.
+ *
+ * The callback receives 4 arguments:
+ *  - number of input frames processed so far,
+ *  - number of bytes written so far,
+ *  - estimated size of the finished file in bytes, extrapolated from frames done so far. It's 0 if `expected_frames` is 0.
+ *  - `user_data`, same as passed in to this function.
+ *
+ * The estimate is rough, and tends to be too high at first, because the first frame is usually the largest.
+ *
+ * The callback must return `1` to continue processing, or `0` to abort.
+ *
+ * This function must be called before `gifski_set_file_output()` to take effect.
+ */
+GifskiError gifski_set_size_progress_callback(gifski *handle,
+                                              uint32_t expected_frames,
+                                              int (*progress_callback)(uint32_t frames_done, uint64_t bytes_written, uint64_t estimated_total_bytes, void *user_data),
+                                              void *user_data);
+
 /**
  * Start writing to the file at `destination_path` (overwrites if needed).
  * The file path must be ASCII or valid UTF-8.
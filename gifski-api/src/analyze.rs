@@ -0,0 +1,187 @@
+//! Suggesting settings from a look at the frames
+
+use crate::{colordiff, DitherKernel, Dithering, Settings};
+use imgref::*;
+use rgb::*;
+use std::collections::HashSet;
+
+/// Frames checked at most. They're picked evenly from the whole sequence.
+const MAX_SAMPLES: usize = 16;
+/// Larger frames make large files, and are rarely needed in a GIF
+const COMFORTABLE_WIDTH: u32 = 800;
+
+/// Settings recommended by `analyze()`, and what they're based on
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct SuggestedSettings {
+    pub quality: u8,
+    /// Frames shown shorter than 1/fps can be dropped without losing much. `None` if the frame rate is fine already.
+    pub max_fps: Option<f32>,
+    /// Number of palette colors that are worth having (2-256).
+    pub max_colors: u16,
+    pub dithering: Option<Dithering>,
+    /// Width to downscale to, if frames are larger than needed for a GIF.
+    pub width: Option<u32>,
+
+    /// Distinct colors found in the sampled frames (counting stops at a few thousand).
+    pub colors: usize,
+    /// Average percentage of pixels that change between frames.
+    pub motion: f32,
+    /// How grainy the frames are. 0 for flat graphics, a few units for clean video, 10+ for noisy video.
+    pub noise: f32,
+}
+
+impl SuggestedSettings {
+    /// Settings to use with `new()` or `encode()`
+    pub fn to_settings(&self) -> Settings {
+        Settings {
+            quality: self.quality,
+            max_colors: self.max_colors,
+            width: self.width,
+            dithering: self.dithering,
+            max_fps: self.max_fps,
+            ..Settings::default()
+        }
+    }
+}
+
+/// Looks at colors, noise, motion and size of the frames to recommend settings.
+///
+/// Frames are in the same format as for `encode()`, with presentation timestamps in seconds.
+/// Only a sample of them is checked, so it's quick even for long sequences.
+pub fn analyze(frames: &[(ImgVec<RGBA8>, f64)]) -> SuggestedSettings {
+    let step = (frames.len() / MAX_SAMPLES).max(1);
+    let samples: Vec<_> = frames.iter().step_by(step).take(MAX_SAMPLES).map(|(img, _)| img.as_ref()).collect();
+
+    let mut colors = HashSet::new();
+    let mut noise = 0.;
+    for img in &samples {
+        count_colors(*img, &mut colors);
+        noise += noise_level(*img);
+    }
+    let noise = if samples.is_empty() { 0. } else { noise / samples.len() as f32 };
+    let colors = colors.len();
+
+    let motion = if samples.len() > 1 {
+        samples.windows(2).map(|w| changed_percent(w[0], w[1])).sum::<f32>() / (samples.len() - 1) as f32
+    } else {
+        0.
+    };
+
+    let width = frames.first().map_or(0, |(img, _)| img.width() as u32);
+    let large = width > COMFORTABLE_WIDTH;
+    let few_colors = colors <= 256;
+
+    let quality = if few_colors {
+        100
+    } else if noise > 10. {
+        // grain is expensive, and hard to see in an animation anyway
+        70
+    } else if large && motion > 20. {
+        80
+    } else {
+        90
+    };
+
+    let fps = frame_rate(frames);
+    let max_fps = match fps {
+        Some(fps) if fps > 30. => Some(if motion > 20. { 25. } else { 20. }),
+        Some(fps) if fps > 20. && motion < 5. => Some(15.),
+        _ => None,
+    };
+
+    // smooth gradients band without dithering, and serpentine order doesn't crawl in pans
+    let dithering = if !few_colors && noise < 4. {
+        Some(Dithering { kernel: DitherKernel::FloydSteinberg, serpentine: true })
+    } else {
+        None
+    };
+
+    SuggestedSettings {
+        quality,
+        max_fps,
+        max_colors: colors.clamp(2, 256) as u16,
+        dithering,
+        width: if large { Some(COMFORTABLE_WIDTH) } else { None },
+        colors,
+        motion,
+        noise,
+    }
+}
+
+/// Stops counting after this many colors, since anything over 256 needs quantization anyway
+const COLOR_COUNT_LIMIT: usize = 4096;
+
+fn count_colors(img: ImgRef<'_, RGBA8>, colors: &mut HashSet<RGBA8>) {
+    for px in img.pixels() {
+        if colors.len() >= COLOR_COUNT_LIMIT {
+            return;
+        }
+        colors.insert(if px.a == 0 { RGBA8::default() } else { px });
+    }
+}
+
+/// Average difference of pixels from the average of their horizontal neighbors.
+/// It's high for grain, and low for both flat areas and gradients.
+fn noise_level(img: ImgRef<'_, RGBA8>) -> f32 {
+    let mut sum = 0u64;
+    let mut n = 0u64;
+    for row in img.rows() {
+        for w in row.windows(3) {
+            let luma = |px: RGBA8| i32::from(px.r) * 2 + i32::from(px.g) * 3 + i32::from(px.b);
+            sum += (luma(w[1]) * 2 - luma(w[0]) - luma(w[2])).unsigned_abs() as u64;
+            n += 1;
+        }
+    }
+    if n == 0 { 0. } else { sum as f32 / n as f32 / 12. }
+}
+
+fn changed_percent(a: ImgRef<'_, RGBA8>, b: ImgRef<'_, RGBA8>) -> f32 {
+    if a.width() != b.width() || a.height() != b.height() {
+        return 100.;
+    }
+    let total = a.width() * a.height();
+    if total == 0 {
+        return 0.;
+    }
+    let changed = a.pixels().zip(b.pixels()).filter(|&(a, b)| colordiff(a, b) > 500).count();
+    changed as f32 * 100. / total as f32
+}
+
+/// Median frame rate, which isn't thrown off by a few long pauses
+fn frame_rate(frames: &[(ImgVec<RGBA8>, f64)]) -> Option<f32> {
+    let mut durations: Vec<_> = frames.windows(2).map(|w| w[1].1 - w[0].1).filter(|&d| d > 0.).collect();
+    if durations.is_empty() {
+        return None;
+    }
+    durations.sort_by(|a, b| a.total_cmp(b));
+    Some((1. / durations[durations.len() / 2]) as f32)
+}
+
+#[test]
+fn suggests_settings() {
+    let flat: Vec<_> = (0..60usize).map(|i| {
+        let mut img = ImgVec::new(vec![RGBA8::new(255, 255, 255, 255); 32 * 32], 32, 32);
+        img[(i % 32, 0usize)] = RGBA8::new(255, 0, 0, 255);
+        (img, i as f64 / 60.)
+    }).collect();
+    let s = analyze(&flat);
+    assert_eq!(100, s.quality);
+    assert_eq!(2, s.max_colors);
+    assert_eq!(None, s.dithering);
+    assert_eq!(Some(20.), s.max_fps);
+    assert_eq!(Some(20.), s.to_settings().max_fps);
+    assert!(s.to_settings().adaptive_frame_rate.is_none());
+    assert!(s.motion < 1.);
+
+    let gradient: Vec<_> = (0..10).map(|i| {
+        let img = ImgVec::new((0..900 * 16).map(|x| RGBA8::new((x % 900 / 4) as u8, i * 10, (x / 900) as u8, 255)).collect(), 900, 16);
+        (img, f64::from(i) / 10.)
+    }).collect();
+    let s = analyze(&gradient);
+    assert!(s.colors > 256);
+    assert!(s.noise < 1., "{}", s.noise);
+    assert!(s.dithering.is_some());
+    assert_eq!(None, s.max_fps);
+    assert_eq!(Some(COMFORTABLE_WIDTH), s.to_settings().width);
+}
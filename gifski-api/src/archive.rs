@@ -0,0 +1,200 @@
+//! Reading frames from `.zip`, `.tar` and `.tar.gz` archives
+
+use crate::error::*;
+use crate::framefiles::{decode_image, is_frame_file};
+use crate::Collector;
+use crossbeam_channel::Sender;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Frame index, file name, and the undecoded file
+type Entry = (usize, String, Vec<u8>);
+
+impl Collector {
+    /// Add all PNG and JPEG files (and WebP, QOI, BMP and TGA with the features of the same name) from a `.zip`, `.tar` or `.tar.gz` archive as frames.
+    ///
+    /// Files are sorted in natural order (`frame2.png` before `frame10.png`), and
+    /// their presentation timestamps are computed from `fps`. Other files and directories are ignored.
+    ///
+    /// Files are read one by one and decoded on a few threads, so the archive is never in memory all at once.
+    /// Tar archives are read twice: first for the file names, and then for the files.
+    /// Frames are added out of order, so this can't be used with `Settings::realtime`.
+    ///
+    /// Frames are added starting from frame index 0. Returns the number of frames added.
+    pub fn add_frames_from_archive(&self, path: &Path, fps: f32) -> CatResult<usize> {
+        if fps.is_nan() || fps <= 0. {
+            return Err(Error::InvalidInput(format!("Invalid frame rate {}", fps)));
+        }
+        let mut file = File::open(path)
+            .map_err(|err| Error::Archive(format!("Can't open {}: {}", path.display(), err)))?;
+
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get()).min(8);
+        let (sender, receiver) = crossbeam_channel::bounded::<Entry>(threads * 2);
+        std::thread::scope(|s| {
+            let decoders: Vec<_> = (0..threads).map(|_| {
+                let receiver = receiver.clone();
+                s.spawn(move || -> CatResult<()> {
+                    for (frame_index, name, data) in receiver {
+                        let image = decode_image(&name, &data, self.tone_mapping).map_err(|err| match err {
+                            Error::Decode(msg) => Error::Archive(msg),
+                            err => err,
+                        })?;
+                        self.add_frame_rgba(frame_index, image, frame_index as f64 / f64::from(fps))?;
+                    }
+                    Ok(())
+                })
+            }).collect();
+            drop(receiver);
+
+            let read = read_entries(&mut file, path, &sender);
+            drop(sender);
+            // a decoder's error explains the send error that it causes when reading
+            for decoder in decoders {
+                decoder.join().map_err(|_| Error::ThreadSend)??;
+            }
+            read
+        })
+    }
+}
+
+/// Sends frame files to the decoders in natural order of their names. Returns the number of files.
+fn read_entries(file: &mut File, path: &Path, sender: &Sender<Entry>) -> CatResult<usize> {
+    let mut magic = [0; 4];
+    let magic_len = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    match &magic[..magic_len] {
+        [b'P', b'K', 3, 4] => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(file))
+                .map_err(|err| Error::Archive(format!("Can't read {}: {}", path.display(), err)))?;
+            let mut names: Vec<_> = zip.file_names().filter(|n| is_frame_file(n)).map(String::from).collect();
+            names.sort_by(|a, b| natord::compare(a, b));
+            let count = names.len();
+            for (frame_index, name) in names.into_iter().enumerate() {
+                let mut data = Vec::new();
+                zip.by_name(&name)
+                    .map_err(|err| Error::Archive(format!("Can't read {} in {}: {}", name, path.display(), err)))?
+                    .read_to_end(&mut data)?;
+                sender.send((frame_index, name, data))?;
+            }
+            Ok(count)
+        },
+        [0x1f, 0x8b, ..] => {
+            let mut indices = tar_frame_indices(flate2::read::GzDecoder::new(BufReader::new(&mut *file)))?;
+            let count = indices.len();
+            file.seek(SeekFrom::Start(0))?;
+            send_tar_entries(flate2::read::GzDecoder::new(BufReader::new(file)), &mut indices, sender)?;
+            Ok(count)
+        },
+        _ => {
+            let mut indices = tar_frame_indices(BufReader::new(&mut *file))?;
+            let count = indices.len();
+            file.seek(SeekFrom::Start(0))?;
+            send_tar_entries(BufReader::new(file), &mut indices, sender)?;
+            Ok(count)
+        },
+    }
+}
+
+/// Tar can only be read sequentially, so the names are read first to number the files in natural order
+fn tar_frame_indices(reader: impl Read) -> CatResult<HashMap<String, usize>> {
+    let mut names = Vec::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if is_frame_file(&name) {
+            names.push(name);
+        }
+    }
+    names.sort_by(|a, b| natord::compare(a, b));
+    names.dedup();
+    Ok(names.into_iter().enumerate().map(|(frame_index, name)| (name, frame_index)).collect())
+}
+
+/// Sends the files numbered by `tar_frame_indices()`. Only the first of files with the same name is used.
+fn send_tar_entries(reader: impl Read, indices: &mut HashMap<String, usize>, sender: &Sender<Entry>) -> CatResult<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if let Some(frame_index) = indices.remove(&name) {
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data)?;
+            sender.send((frame_index, name, data))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn encode_archive(path: &Path) -> (usize, Vec<u8>) {
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    let added = collector.add_frames_from_archive(path, 10.);
+    drop(collector);
+    let mut out = Vec::new();
+    writer.write(&mut out, &mut crate::progress::NoProgress {}).unwrap();
+    std::fs::remove_file(path).unwrap();
+    (added.unwrap(), out)
+}
+
+#[cfg(test)]
+fn test_frames() -> Vec<(&'static str, Vec<u8>)> {
+    use rgb::RGBA8;
+    vec![
+        ("frames/frame10.png", lodepng::encode32(&[RGBA8::new(0, 0, 255, 255); 4], 2, 2).unwrap()),
+        ("notes.txt", b"not a frame".to_vec()),
+        ("frames/frame2.png", lodepng::encode32(&[RGBA8::new(255, 0, 0, 255); 4], 2, 2).unwrap()),
+    ]
+}
+
+#[cfg(test)]
+fn first_pixel(gif: &[u8]) -> Vec<u8> {
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = decoder.read_info(gif).unwrap();
+    decoder.read_next_frame().unwrap().unwrap().buffer[..4].to_vec()
+}
+
+#[test]
+fn reads_zip_in_natural_order() {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(format!("gifski-frames-{}.zip", std::process::id()));
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    for (name, data) in test_frames() {
+        zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+        zip.write_all(&data).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let (added, gif) = encode_archive(&path);
+    assert_eq!(2, added);
+    assert_eq!(&[255, 0, 0, 255], &first_pixel(&gif)[..]);
+}
+
+#[test]
+fn reads_tar_gz_in_natural_order() {
+    let path = std::env::temp_dir().join(format!("gifski-frames-{}.tar.gz", std::process::id()));
+    let mut tar = tar::Builder::new(flate2::write::GzEncoder::new(File::create(&path).unwrap(), flate2::Compression::fast()));
+    for (name, data) in test_frames() {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, &data[..]).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+
+    let (added, gif) = encode_archive(&path);
+    assert_eq!(2, added);
+    assert_eq!(&[255, 0, 0, 255], &first_pixel(&gif)[..]);
+}
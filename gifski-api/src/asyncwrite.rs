@@ -0,0 +1,84 @@
+//! Writing to an async sink
+//!
+//! The encoder itself stays synchronous. It runs on its own thread and passes
+//! compressed data in chunks to the future, which awaits the sink.
+
+use crate::error::*;
+use crate::progress::ProgressReporter;
+use crate::{EncodeSummary, Writer};
+use futures::channel::{mpsc, oneshot};
+use futures::executor::block_on;
+use futures::{AsyncWrite, AsyncWriteExt, SinkExt, StreamExt};
+use std::io;
+use std::thread;
+
+/// Bytes collected before they're handed over to the async side
+const CHUNK_SIZE: usize = 1 << 16;
+
+impl Writer {
+    /// Same as `write()`, but for an async `writer`, such as a network socket.
+    ///
+    /// Encoding runs on separate threads, and only waits for the `writer` when a few chunks of output
+    /// are already waiting to be written. The future doesn't depend on any particular async runtime.
+    pub async fn write_async<W: AsyncWrite + Unpin>(self, mut writer: W, mut reporter: impl ProgressReporter + 'static) -> CatResult<EncodeSummary> {
+        let (chunks, mut chunks_recv) = mpsc::channel(4);
+        let (result, result_recv) = oneshot::channel();
+        thread::Builder::new().name("write".into()).spawn(move || {
+            let _ = result.send(self.write(ChunkWriter { chunks, buf: Vec::new() }, &mut reporter));
+        })?;
+
+        while let Some(chunk) = chunks_recv.next().await {
+            // dropping the receiver on error makes the encoder fail and stop too
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        result_recv.await.map_err(|_| Error::ThreadSend)?
+    }
+}
+
+struct ChunkWriter {
+    chunks: mpsc::Sender<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl ChunkWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        block_on(self.chunks.send(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "async writer has stopped"))
+    }
+}
+
+impl io::Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+#[test]
+fn writes_to_async_sink() {
+    use crate::progress::NoProgress;
+
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    for i in 0..3u8 {
+        let (image, pts) = crate::test_frame(i, 4, 4);
+        collector.add_frame_rgba(i.into(), image, pts).unwrap();
+    }
+    drop(collector);
+    let mut out = Vec::new();
+    let summary = block_on(writer.write_async(&mut out, NoProgress {})).unwrap();
+    assert_eq!(3, summary.frames_written);
+    assert!(out.starts_with(b"GIF89a"));
+    assert_eq!(Some(&b';'), out.last());
+}
@@ -0,0 +1,140 @@
+//! Cropping of single-color borders that are the same in every frame, like black bars
+
+use crate::{cropped, FinishFrame, FrameOptions, InputFrame, QueuedFrame, Rect};
+use imgref::{ImgRef, ImgVec};
+use rgb::RGBA8;
+
+/// Per-channel difference still seen as the same color, for noise from video compression
+const TOLERANCE: u8 = 8;
+
+/// Borders are looked for in this many first frames, so that not all frames have to be kept in memory
+const SAMPLE_FRAMES: usize = 30;
+
+/// Frame before it's cropped. Without `FinishFrame` it doesn't need resizing.
+type Sampled = (ImgVec<RGBA8>, Option<FinishFrame>, f64, FrameOptions);
+
+/// Reads the first frames, and crops all frames to where they're not borders in these frames.
+/// Then the frames are resized (`Collector` leaves `InputFrame::Uncropped` frames full-size).
+///
+/// Frames after an error aren't read. If `size_multiple` is above 1, some of the borders are kept
+/// to keep the size a multiple of it.
+pub(crate) fn autocropped(mut inputs: impl Iterator<Item = QueuedFrame>, size_multiple: u8) -> impl Iterator<Item = QueuedFrame> {
+    let mut sample = Vec::with_capacity(SAMPLE_FRAMES);
+    let mut error = None;
+    for frame in inputs.by_ref().take(SAMPLE_FRAMES) {
+        match frame.and_then(uncropped) {
+            Ok(frame) => sample.push(frame),
+            Err(err) => {
+                error = Some(err);
+                break;
+            },
+        }
+    }
+    let crop = border_crop(sample.iter().map(|(image, ..)| image.as_ref())).map(|crop| {
+        let (width, height) = sample.first().map(|(image, ..)| (image.width() as u32, image.height() as u32)).unwrap_or_default();
+        let (left, inner_width) = grow_to_multiple(crop.left, crop.width, width, size_multiple);
+        let (top, inner_height) = grow_to_multiple(crop.top, crop.height, height, size_multiple);
+        Rect { left, top, width: inner_width, height: inner_height }
+    });
+    let rest = if error.is_none() { Some(inputs.map(|frame| frame.and_then(uncropped))) } else { None };
+    sample.into_iter().map(Ok).chain(error.map(Err)).chain(rest.into_iter().flatten()).map(move |frame| {
+        let (image, finish, pts, mut options) = frame?;
+        let image = cropped(image, crop)?;
+        let importance_map = options.importance_map.take().map(|map| cropped(map, crop)).transpose()?;
+        let (image, importance_map) = match finish {
+            Some(finish) => finish(image, importance_map, pts)?,
+            None => (image, importance_map),
+        };
+        options.importance_map = importance_map;
+        Ok((InputFrame::Rgba(image), pts, options))
+    })
+}
+
+/// Full-size pixels of the frame
+fn uncropped((frame, pts, options): (InputFrame, f64, FrameOptions)) -> crate::CatResult<Sampled> {
+    match frame {
+        InputFrame::Uncropped(frame) => Ok(((frame.image)()?, Some(frame.finish), pts, options)),
+        frame => Ok((frame.into_rgba(pts)?.0, None, pts, options)),
+    }
+}
+
+/// Area inside the borders, or `None` if there's nothing to crop
+fn border_crop<'a>(frames: impl Iterator<Item = ImgRef<'a, RGBA8>> + Clone) -> Option<Rect> {
+    let first_frame = frames.clone().next()?;
+    let (width, height) = (first_frame.width(), first_frame.height());
+    if frames.clone().any(|frame| frame.width() != width || frame.height() != height) {
+        return None;
+    }
+
+    let row = |y: usize| move |frame: ImgRef<'a, RGBA8>| frame.sub_image(0, y, width, 1);
+    let is_border = |reference: RGBA8, line: &dyn Fn(ImgRef<'a, RGBA8>) -> ImgRef<'a, RGBA8>| {
+        frames.clone().all(|frame| line(frame).pixels().all(|px| same_color(px, reference)))
+    };
+
+    let top = (0..height).take_while(|&y| is_border(first_frame[(0, y)], &row(y))).count();
+    if top == height {
+        // it's all one color
+        return None;
+    }
+    let bottom = (top..height).rev().take_while(|&y| is_border(first_frame[(0, y)], &row(y))).count();
+    let inner_height = height - top - bottom;
+
+    let column = |x: usize| move |frame: ImgRef<'a, RGBA8>| frame.sub_image(x, top, 1, inner_height);
+    let left = (0..width).take_while(|&x| is_border(first_frame[(x, top)], &column(x))).count();
+    let right = (left..width).rev().take_while(|&x| is_border(first_frame[(x, top)], &column(x))).count();
+
+    if top == 0 && bottom == 0 && left == 0 && right == 0 {
+        return None;
+    }
+    Some(Rect {
+        left: left as u32,
+        top: top as u32,
+        width: (width - left - right) as u32,
+        height: inner_height as u32,
+    })
+}
+
+/// Makes the `(start, len)` span a multiple in size, if it can grow within the `total` size.
+fn grow_to_multiple(start: u32, len: u32, total: u32, multiple: u8) -> (u32, u32) {
+    let multiple = u32::from(multiple.max(1));
+    let grown = len + (multiple - len % multiple) % multiple;
+    if grown > total {
+        return (start, len);
+    }
+    let extra = grown - len;
+    // keep it centered on the content, but within the frame
+    let start = start.saturating_sub(extra / 2).min(total - grown);
+    (start, grown)
+}
+
+fn same_color(a: RGBA8, b: RGBA8) -> bool {
+    if a.a < 128 || b.a < 128 {
+        return a.a < 128 && b.a < 128;
+    }
+    a.r.abs_diff(b.r) <= TOLERANCE && a.g.abs_diff(b.g) <= TOLERANCE && a.b.abs_diff(b.b) <= TOLERANCE
+}
+
+#[test]
+fn finds_bars() {
+    use imgref::ImgVec;
+
+    let black = RGBA8::new(0, 0, 0, 255);
+    let frames: Vec<_> = (0..3u8).map(|i| {
+        let mut frame = ImgVec::new(vec![black; 6 * 5], 6, 5);
+        // content in rows 1-2, columns 2-3, with a noisy bar on the right
+        frame[(2usize, 1usize)] = RGBA8::new(200, i * 20, 0, 255);
+        frame[(3usize, 2usize)] = RGBA8::new(0, 100, i * 20, 255);
+        frame[(5usize, 3usize)] = RGBA8::new(3, 3, 3, 255);
+        frame
+    }).collect();
+    let crop = border_crop(frames.iter().map(|f| f.as_ref()));
+    assert_eq!(Some(Rect { left: 2, top: 1, width: 2, height: 2 }), crop);
+
+    assert_eq!((1, 4), grow_to_multiple(2, 2, 6, 4));
+    assert_eq!((2, 4), grow_to_multiple(3, 3, 6, 4));
+    assert_eq!((4, 4), grow_to_multiple(5, 3, 8, 4));
+    assert_eq!((0, 3), grow_to_multiple(0, 3, 3, 4));
+
+    let plain = ImgVec::new(vec![black; 4], 2, 2);
+    assert_eq!(None, border_crop(std::iter::once(plain.as_ref())));
+}
@@ -1,7 +1,7 @@
 #[macro_use] extern crate clap;
 
 use std::ffi::OsStr;
-use gifski::{Settings, Repeat};
+use gifski::{Settings, Repeat, ResizeFit};
 
 #[cfg(feature = "video")]
 mod ffmpeg_source;
@@ -14,6 +14,7 @@ use gifski::progress::{NoProgress, ProgressBar, ProgressReporter};
 pub type BinResult<T, E = Box<dyn std::error::Error + Send + Sync>> = Result<T, E>;
 
 use clap::{App, AppSettings, Arg};
+use rgb::RGBA8;
 
 use std::env;
 use std::fmt;
@@ -75,12 +76,20 @@ fn bin_main() -> BinResult<()> {
                         .arg(Arg::with_name("fast")
                             .long("fast")
                             .help("3 times faster encoding, but 10% lower quality and \nlarger file size"))
+                        .arg(Arg::with_name("extra")
+                            .long("extra")
+                            .help("Much slower encoding, for slightly better quality \nand smaller file size"))
                         .arg(Arg::with_name("quality")
                             .long("quality")
                             .short("Q")
                             .value_name("1-100")
                             .takes_value(true)
                             .help("Lower quality may give smaller file"))
+                        .arg(Arg::with_name("motion-quality")
+                            .long("motion-quality")
+                            .value_name("1-100")
+                            .takes_value(true)
+                            .help("Lower values skip small changes between frames.\nSame as --quality by default"))
                         .arg(Arg::with_name("width")
                             .long("width")
                             .short("W")
@@ -92,7 +101,12 @@ fn bin_main() -> BinResult<()> {
                             .short("H")
                             .takes_value(true)
                             .value_name("px")
-                            .help("Maximum height (stretches if the width is also set)"))
+                            .help("Maximum height (stretches if the width is also set, see --fit)"))
+                        .arg(Arg::with_name("fit")
+                            .long("fit")
+                            .takes_value(true)
+                            .possible_values(&["exact", "contain", "cover", "crop", "letterbox"])
+                            .help("How to resize when both width and height are set.\ncontain keeps aspect ratio and fits inside, \ncover keeps aspect ratio and fills the whole size, \ncrop is cover cut to exactly the size, \nletterbox is contain padded with transparency to exactly the size"))
                         .arg(Arg::with_name("nosort")
                             .long("nosort")
                             .help("Use files exactly in the order given, rather than \nsorted"))
@@ -122,21 +136,29 @@ fn bin_main() -> BinResult<()> {
     let output_path = DestPath::new(matches.value_of_os("output").ok_or("Missing output")?);
     let width = parse_opt(matches.value_of("width")).map_err(|_| "Invalid width")?;
     let height = parse_opt(matches.value_of("height")).map_err(|_| "Invalid height")?;
+    let fit = match matches.value_of("fit") {
+        Some("contain") => ResizeFit::Contain,
+        Some("cover") => ResizeFit::Cover,
+        Some("crop") => ResizeFit::Crop,
+        Some("letterbox") => ResizeFit::Letterbox(RGBA8::new(0, 0, 0, 0)),
+        _ => ResizeFit::Exact,
+    };
     let repeat_int = parse_opt(matches.value_of("repeat")).map_err(|_| "Invalid repeat count")?.unwrap_or(0) as i16;
-    let repeat;
-    match repeat_int {
-        -1 => repeat = Repeat::Finite(0),
-        0 => repeat = Repeat::Infinite,
-        _ => repeat = Repeat::Finite(repeat_int as u16),
-    }
-
-    let settings = Settings {
-        width,
-        height,
-        quality: parse_opt(matches.value_of("quality")).map_err(|_| "Invalid quality")?.unwrap_or(100),
-        fast: matches.is_present("fast"),
-        repeat,
+    let repeat = match repeat_int {
+        -1 => Repeat::None,
+        0 => Repeat::Infinite,
+        _ => Repeat::Finite(repeat_int as u16),
     };
+
+    let mut settings = Settings::default();
+    settings.width = width;
+    settings.height = height;
+    settings.fit = fit;
+    settings.quality = parse_opt(matches.value_of("quality")).map_err(|_| "Invalid quality")?.unwrap_or(100);
+    settings.fast = matches.is_present("fast");
+    settings.extra_effort = matches.is_present("extra");
+    settings.repeat = repeat;
+    settings.motion_quality = parse_opt(matches.value_of("motion-quality")).map_err(|_| "Invalid motion quality")?;
     let quiet = matches.is_present("quiet") || matches!(output_path, DestPath::Stdout);
     let fps: f32 = matches.value_of("fps").ok_or("Missing fps")?.parse().map_err(|_| "FPS must be a number")?;
     let speed: f32 = matches.value_of("fast-forward").ok_or("Missing speed")?.parse().map_err(|_| "Speed must be a number")?;
@@ -153,6 +175,12 @@ fn bin_main() -> BinResult<()> {
         Err("Quality 100 is maximum")?;
     }
 
+    if let Some(motion_quality) = settings.motion_quality {
+        if !(1..=100).contains(&motion_quality) {
+            Err("Motion quality must be between 1 and 100")?;
+        }
+    }
+
     if fps > 100.0 {
         Err("100 fps is maximum")?;
     }
@@ -163,7 +191,7 @@ fn bin_main() -> BinResult<()> {
     check_if_paths_exist(&frames)?;
 
     let mut decoder = if frames.len() == 1 {
-        get_video_decoder(&frames[0], rate, settings)?
+        get_video_decoder(&frames[0], rate)?
     } else {
         if speed != 1.0 {
             Err("Speed doesn't apply to PNG files as input, use fps only")?;
@@ -256,13 +284,13 @@ impl fmt::Display for DestPath<'_> {
 }
 
 #[cfg(feature = "video")]
-fn get_video_decoder(path: &Path, fps: source::Fps, settings: Settings) -> BinResult<Box<dyn Source + Send>> {
-    Ok(Box::new(ffmpeg_source::FfmpegDecoder::new(path, fps, settings)?))
+fn get_video_decoder(path: &Path, fps: source::Fps) -> BinResult<Box<dyn Source + Send>> {
+    Ok(Box::new(ffmpeg_source::FfmpegDecoder::new(path, fps)?))
 }
 
 #[cfg(not(feature = "video"))]
 #[cold]
-fn get_video_decoder(_: &Path, _: source::Fps, _: Settings) -> BinResult<Box<dyn Source + Send>> {
+fn get_video_decoder(_: &Path, _: source::Fps) -> BinResult<Box<dyn Source + Send>> {
     Err(r"Video support is permanently disabled in this executable.
 
 To enable video decoding you need to recompile gifski from source with:
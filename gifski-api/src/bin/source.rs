@@ -11,5 +11,6 @@ pub struct Fps {
     /// output rate
     pub fps: f32,
     /// skip frames
+    #[cfg_attr(not(feature = "video"), allow(dead_code))]
     pub speed: f32,
 }
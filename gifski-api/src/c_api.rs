@@ -2,7 +2,10 @@
 //! How to use from C
 //!
 //! ```c
-//! gifski *g = gifski_new(&(GifskiSettings){});
+//! gifski_settings *s = gifski_settings_new();
+//! gifski_settings_set_quality(s, 90);
+//! gifski *g = gifski_new_with(s);
+//! gifski_settings_free(s);
 //! gifski_set_file_output(g, "file.gif");
 //!
 //! for(int i=0; i < frames; i++) {
@@ -67,10 +70,17 @@ pub struct ARGB8 {
 pub struct GifskiHandle {
     _opaque: usize,
 }
+
+/// Opaque settings object. See `gifski_settings_new`. It's actually `Box<Settings>`.
+#[repr(C)]
+pub struct GifskiSettingsBuilder {
+    _opaque: usize,
+}
+
 pub struct GifskiHandleInternal {
     writer: Mutex<Option<Writer>>,
     collector: Mutex<Option<Collector>>,
-    progress: Mutex<Option<ProgressCallback>>,
+    progress: Mutex<Option<Box<dyn ProgressReporter>>>,
     /// Bool set to true when the thread has been set up,
     /// prevents re-setting of the thread after finish()
     write_thread: Mutex<(bool, Option<thread::JoinHandle<GifskiError>>)>,
@@ -81,6 +91,8 @@ pub struct GifskiHandleInternal {
 /// See `gifski_add_frame_png_file` and `gifski_end_adding_frames`
 ///
 /// Returns a handle for the other functions, or `NULL` on error (if the settings are invalid).
+///
+/// New options are only added to `gifski_settings_new()`/`gifski_new_with()`. This struct won't change.
 #[no_mangle]
 pub unsafe extern "C" fn gifski_new(settings: *const GifskiSettings) -> *const GifskiHandle {
     let settings = if let Some(s) = settings.as_ref() {s} else {
@@ -91,9 +103,26 @@ pub unsafe extern "C" fn gifski_new(settings: *const GifskiSettings) -> *const G
         height: if settings.height > 0 { Some(settings.height) } else { None },
         quality: settings.quality,
         fast: settings.fast,
-        repeat: if settings.repeat == -1 { Repeat::Finite(0) } else if settings.repeat == 0 { Repeat::Infinite } else { Repeat::Finite(settings.repeat as u16) },
+        repeat: repeat_from_c(settings.repeat),
+        ..Settings::default()
     };
+    new_handle(s)
+}
+
+/// Same as `gifski_new`, but takes settings made with `gifski_settings_new()`.
+///
+/// The settings object is only read, and can be freed with `gifski_settings_free()` right after this call.
+///
+/// Returns a handle for the other functions, or `NULL` on error (if the settings are invalid).
+#[no_mangle]
+pub unsafe extern "C" fn gifski_new_with(settings: *const GifskiSettingsBuilder) -> *const GifskiHandle {
+    match (settings as *const Settings).as_ref() {
+        Some(s) => new_handle(*s),
+        None => ptr::null_mut(),
+    }
+}
 
+fn new_handle(s: Settings) -> *const GifskiHandle {
     if let Ok((collector, writer)) = new(s) {
         Arc::into_raw(Arc::new(GifskiHandleInternal {
             writer: Mutex::new(Some(writer)),
@@ -106,6 +135,103 @@ pub unsafe extern "C" fn gifski_new(settings: *const GifskiSettings) -> *const G
     }
 }
 
+fn repeat_from_c(repeat: i16) -> Repeat {
+    if repeat < 0 { Repeat::None } else if repeat == 0 { Repeat::Infinite } else { Repeat::Finite(repeat as u16) }
+}
+
+/// Creates settings with default values, to be changed with `gifski_settings_set_*` and passed to `gifski_new_with()`.
+///
+/// Must be freed with `gifski_settings_free()`.
+#[no_mangle]
+pub extern "C" fn gifski_settings_new() -> *mut GifskiSettingsBuilder {
+    Box::into_raw(Box::new(Settings::default())) as *mut GifskiSettingsBuilder
+}
+
+/// Frees settings created with `gifski_settings_new()`. `NULL` is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_free(settings: *mut GifskiSettingsBuilder) {
+    if !settings.is_null() {
+        drop(Box::from_raw(settings as *mut Settings));
+    }
+}
+
+unsafe fn borrow_settings<'a>(settings: *mut GifskiSettingsBuilder) -> Option<&'a mut Settings> {
+    (settings as *mut Settings).as_mut()
+}
+
+/// Resize to max this width if non-0. Height is used only if width is non-0. Note that aspect ratio is not preserved.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_set_size(settings: *mut GifskiSettingsBuilder, width: u32, height: u32) -> GifskiError {
+    let s = match borrow_settings(settings) {
+        Some(s) => s,
+        None => return GifskiError::NULL_ARG,
+    };
+    s.width = if width > 0 { Some(width) } else { None };
+    s.height = if height > 0 { Some(height) } else { None };
+    GifskiError::OK
+}
+
+/// 1-100, but useful range is 50-100. Recommended to set to 90.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_set_quality(settings: *mut GifskiSettingsBuilder, quality: u8) -> GifskiError {
+    let s = match borrow_settings(settings) {
+        Some(s) => s,
+        None => return GifskiError::NULL_ARG,
+    };
+    if !(1..=100).contains(&quality) {
+        return GifskiError::INVALID_INPUT;
+    }
+    s.quality = quality;
+    GifskiError::OK
+}
+
+/// 1-100. Lower values allow more motion artifacts in exchange for smaller files. 0 to use the same value as quality.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_set_motion_quality(settings: *mut GifskiSettingsBuilder, quality: u8) -> GifskiError {
+    let s = match borrow_settings(settings) {
+        Some(s) => s,
+        None => return GifskiError::NULL_ARG,
+    };
+    if quality > 100 {
+        return GifskiError::INVALID_INPUT;
+    }
+    s.motion_quality = if quality > 0 { Some(quality) } else { None };
+    GifskiError::OK
+}
+
+/// Lower quality, but faster encode.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_set_fast(settings: *mut GifskiSettingsBuilder, fast: bool) -> GifskiError {
+    let s = match borrow_settings(settings) {
+        Some(s) => s,
+        None => return GifskiError::NULL_ARG,
+    };
+    s.fast = fast;
+    GifskiError::OK
+}
+
+/// Slower encode for slightly smaller files.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_set_extra_effort(settings: *mut GifskiSettingsBuilder, extra: bool) -> GifskiError {
+    let s = match borrow_settings(settings) {
+        Some(s) => s,
+        None => return GifskiError::NULL_ARG,
+    };
+    s.extra_effort = extra;
+    GifskiError::OK
+}
+
+/// If negative, looping is disabled. The number of times the sequence is repeated. 0 to loop forever.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_settings_set_repeat(settings: *mut GifskiSettingsBuilder, repeat: i16) -> GifskiError {
+    let s = match borrow_settings(settings) {
+        Some(s) => s,
+        None => return GifskiError::NULL_ARG,
+    };
+    s.repeat = repeat_from_c(repeat);
+    GifskiError::OK
+}
+
 /// Adds a frame to the animation. This function is asynchronous.
 ///
 /// File path must be valid UTF-8.
@@ -121,6 +247,7 @@ pub unsafe extern "C" fn gifski_new(settings: *const GifskiSettings) -> *const G
 ///
 /// Returns 0 (`GIFSKI_OK`) on success, and non-0 `GIFSKI_*` constant on error.
 #[no_mangle]
+#[cfg(feature = "png")]
 pub unsafe extern "C" fn gifski_add_frame_png_file(handle: *const GifskiHandle, frame_number: u32, file_path: *const c_char, presentation_timestamp: f64) -> GifskiError {
     if file_path.is_null() {
         return GifskiError::NULL_ARG;
@@ -161,12 +288,16 @@ pub unsafe extern "C" fn gifski_add_frame_rgba(handle: *const GifskiHandle, fram
 }
 
 fn add_frame_rgba(handle: *const GifskiHandle, frame_number: u32, frame: ImgVec<RGBA8>, presentation_timestamp: f64) -> GifskiError {
+    add_frame(handle, |c| c.add_frame_rgba(frame_number as usize, frame, presentation_timestamp))
+}
+
+fn add_frame(handle: *const GifskiHandle, add: impl FnOnce(&Collector) -> CatResult<()>) -> GifskiError {
     let g = match unsafe { borrow(handle) } {
         Some(g) => g,
         None => return GifskiError::NULL_ARG,
     };
     if let Some(ref mut c) = *g.collector.lock().unwrap() {
-        c.add_frame_rgba(frame_number as usize, frame, presentation_timestamp).into()
+        add(c).into()
     } else {
         eprintln!("frames can't be added any more, because gifski_end_adding_frames has been called already");
         GifskiError::INVALID_STATE
@@ -192,7 +323,7 @@ pub unsafe extern "C" fn gifski_add_frame_argb(handle: *const GifskiHandle, fram
         g: p.g,
         b: p.b,
         a: p.a,
-    })).collect(), width as usize, height as usize), presentation_timestamp)
+    })).collect(), width, height as usize), presentation_timestamp)
 }
 
 /// Same as `gifski_add_frame_rgba`, except it expects RGB components (3 bytes per pixel).
@@ -209,7 +340,25 @@ pub unsafe extern "C" fn gifski_add_frame_rgb(handle: *const GifskiHandle, frame
         return GifskiError::INVALID_INPUT;
     }
     let pixels = slice::from_raw_parts(pixels, stride * height as usize);
-    add_frame_rgba(handle, frame_number, ImgVec::new(pixels.chunks(stride).flat_map(|r| r[0..width].iter().map(|&p| p.into())).collect(), width as usize, height as usize), presentation_timestamp)
+    let frame = ImgVec::new(pixels.chunks(stride).flat_map(|r| r[0..width].iter().copied()).collect(), width, height as usize);
+    add_frame(handle, |c| c.add_frame_rgb(frame_number as usize, frame, presentation_timestamp))
+}
+
+/// Same as `gifski_add_frame_rgba`, except it expects components in BGRA order.
+///
+/// Bytes per row must be multiple of 4 and greater or equal width×4.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_add_frame_bgra(handle: *const GifskiHandle, frame_number: u32, width: u32, bytes_per_row: u32, height: u32, pixels: *const BGRA8, presentation_timestamp: f64) -> GifskiError {
+    if pixels.is_null() {
+        return GifskiError::NULL_ARG;
+    }
+    let width = width as usize;
+    let stride = bytes_per_row as usize / mem::size_of_val(&*pixels);
+    if stride < width || height == 0 {
+        return GifskiError::INVALID_INPUT;
+    }
+    let pixels = slice::from_raw_parts(pixels, stride * (height as usize - 1) + width);
+    add_frame(handle, |c| c.add_frame_bgra(frame_number as usize, ImgRef::new_stride(pixels, width, height as usize, stride), presentation_timestamp))
 }
 
 /// Get a callback for frame processed, and abort processing if desired.
@@ -226,7 +375,35 @@ pub unsafe extern "C" fn gifski_add_frame_rgb(handle: *const GifskiHandle, frame
 ///
 /// This function must be called before `gifski_set_file_output()` to take effect.
 #[no_mangle]
-pub unsafe extern "C" fn gifski_set_progress_callback(handle: *const GifskiHandle, cb: unsafe extern fn(*mut c_void) -> c_int, user_data: *mut c_void) -> GifskiError {
+pub unsafe extern "C" fn gifski_set_progress_callback(handle: *const GifskiHandle, cb: unsafe extern "C" fn(*mut c_void) -> c_int, user_data: *mut c_void) -> GifskiError {
+    let g = match borrow(handle) {
+        Some(g) => g,
+        None => return GifskiError::NULL_ARG,
+    };
+    let t = g.write_thread.lock().unwrap();
+    if t.0 {
+        eprintln!("tried to set progress callback after writing has already started");
+        return GifskiError::INVALID_STATE;
+    }
+    *g.progress.lock().unwrap() = Some(Box::new(ProgressCallback::new(cb, user_data)));
+    GifskiError::OK
+}
+
+/// Same as `gifski_set_progress_callback`, but the callback also gets the size of the output.
+///
+/// The callback receives 4 arguments:
+///  - number of input frames processed so far,
+///  - number of bytes written so far,
+///  - estimated size of the finished file in bytes, extrapolated from frames done so far. It's 0 if `expected_frames` is 0.
+///  - `user_data`, same as passed in to this function.
+///
+/// The estimate is rough, and tends to be too high at first, because the first frame is usually the largest.
+///
+/// The callback must return `1` to continue processing, or `0` to abort.
+///
+/// This function must be called before `gifski_set_file_output()` to take effect.
+#[no_mangle]
+pub unsafe extern "C" fn gifski_set_size_progress_callback(handle: *const GifskiHandle, expected_frames: u32, cb: unsafe extern "C" fn(u32, u64, u64, *mut c_void) -> c_int, user_data: *mut c_void) -> GifskiError {
     let g = match borrow(handle) {
         Some(g) => g,
         None => return GifskiError::NULL_ARG,
@@ -236,7 +413,7 @@ pub unsafe extern "C" fn gifski_set_progress_callback(handle: *const GifskiHandl
         eprintln!("tried to set progress callback after writing has already started");
         return GifskiError::INVALID_STATE;
     }
-    *g.progress.lock().unwrap() = Some(ProgressCallback::new(cb, user_data));
+    *g.progress.lock().unwrap() = Some(Box::new(SizeProgressCallback::new(cb, expected_frames, user_data)));
     GifskiError::OK
 }
 
@@ -316,7 +493,7 @@ impl io::Write for CallbackWriter {
 ///
 /// Returns 0 (`GIFSKI_OK`) on success, and non-0 `GIFSKI_*` constant on error.
 #[no_mangle]
-pub unsafe extern "C" fn gifski_set_write_callback(handle: *const GifskiHandle, cb: Option<unsafe extern fn(usize, *const u8, *mut c_void) -> c_int>, user_data: *mut c_void) -> GifskiError {
+pub unsafe extern "C" fn gifski_set_write_callback(handle: *const GifskiHandle, cb: Option<unsafe extern "C" fn(usize, *const u8, *mut c_void) -> c_int>, user_data: *mut c_void) -> GifskiError {
     let g = match borrow(handle) {
         Some(g) => g,
         None => return GifskiError::NULL_ARG,
@@ -341,9 +518,9 @@ fn gifski_write_thread_start<W: 'static +  Write + Send>(g: &GifskiHandleInterna
         if let Some(writer) = writer {
             let mut progress: &mut dyn ProgressReporter = &mut NoProgress {};
             if let Some(cb) = &mut user_progress {
-                progress = &mut *cb;
+                progress = &mut **cb;
             }
-            match writer.write(file, progress).into() {
+            match writer.write(file, progress).map(drop).into() {
                 res @ GifskiError::OK |
                 res @ GifskiError::ALREADY_EXISTS => res,
                 err => {
@@ -437,6 +614,65 @@ fn c_cb() {
     assert_eq!(2, progress_called);
 }
 
+#[test]
+fn c_settings_builder() {
+    unsafe {
+        assert!(gifski_new_with(ptr::null()).is_null());
+        assert_eq!(GifskiError::NULL_ARG, gifski_settings_set_quality(ptr::null_mut(), 90));
+
+        let s = gifski_settings_new();
+        assert_eq!(GifskiError::OK, gifski_settings_set_size(s, 1, 0));
+        assert_eq!(GifskiError::INVALID_INPUT, gifski_settings_set_quality(s, 0));
+        assert_eq!(GifskiError::OK, gifski_settings_set_quality(s, 90));
+        assert_eq!(GifskiError::OK, gifski_settings_set_repeat(s, -1));
+        let settings = &*(s as *const Settings);
+        assert_eq!((Some(1), None, 90, Repeat::None), (settings.width, settings.height, settings.quality, settings.repeat));
+
+        let g = gifski_new_with(s);
+        gifski_settings_free(s);
+        assert!(!g.is_null());
+        unsafe extern "C" fn cb(_s: usize, _buf: *const u8, _user: *mut c_void) -> c_int {
+            0
+        }
+        assert_eq!(GifskiError::OK, gifski_set_write_callback(g, Some(cb), ptr::null_mut()));
+        assert_eq!(GifskiError::OK, gifski_add_frame_rgb(g, 0, 1, 3, 1, &RGB::new(0, 0, 0), 0.));
+        assert_eq!(GifskiError::OK, gifski_finish(g));
+    }
+}
+
+#[test]
+fn c_size_progress() {
+    let g = unsafe { gifski_new(&GifskiSettings {
+        width: 0, height: 0,
+        quality: 100,
+        fast: true,
+        repeat: 0,
+    })};
+    assert!(!g.is_null());
+    unsafe extern "C" fn cb(_s: usize, _buf: *const u8, _user: *mut c_void) -> c_int {
+        0
+    }
+    let mut calls = Vec::<(u32, u64, u64)>::new();
+    unsafe extern "C" fn pcb(frames: u32, bytes: u64, estimate: u64, user_data: *mut c_void) -> c_int {
+        (*(user_data as *mut Vec<(u32, u64, u64)>)).push((frames, bytes, estimate));
+        1
+    }
+    unsafe {
+        assert_eq!(GifskiError::OK, gifski_set_size_progress_callback(g, 4, pcb, (&mut calls) as *mut _ as _));
+        assert_eq!(GifskiError::OK, gifski_set_write_callback(g, Some(cb), ptr::null_mut()));
+        for i in 0..3 {
+            let px = RGBA8::new(i * 100, 0, 0, 255);
+            assert_eq!(GifskiError::OK, gifski_add_frame_rgba(g, i.into(), 1, 1, &px, f64::from(i)));
+        }
+        assert_eq!(GifskiError::OK, gifski_finish(g));
+    }
+    assert_eq!(3, calls.len());
+    let (frames, bytes, estimate) = calls[0];
+    assert_eq!(1, frames);
+    assert!(bytes > 0);
+    assert_eq!(bytes * 4, estimate);
+}
+
 #[test]
 fn cant_write_after_finish() {
     let g = unsafe { gifski_new(&GifskiSettings {
@@ -451,7 +687,7 @@ fn cant_write_after_finish() {
     }
     unsafe {
         assert_eq!(GifskiError::OK, gifski_set_write_callback(g, Some(cb), 0 as _));
-        assert_eq!(GifskiError::OTHER, gifski_finish(g));
+        assert_eq!(GifskiError::NO_FRAMES, gifski_finish(g));
     }
 }
 
@@ -464,7 +700,7 @@ fn c_write_failure_propagated() {
         repeat: -1,
     })};
     assert!(!g.is_null());
-    unsafe extern fn cb(_s: usize, _buf: *const u8, _user: *mut c_void) -> c_int {
+    unsafe extern "C" fn cb(_s: usize, _buf: *const u8, _user: *mut c_void) -> c_int {
         GifskiError::WRITE_ZERO as c_int
     }
     unsafe {
@@ -501,8 +737,7 @@ fn c_incomplete() {
         repeat: 0,
     })};
 
-    let rgb: *const RGB8 = ptr::null();
-    assert_eq!(3, mem::size_of_val(unsafe { &*rgb }));
+    assert_eq!(3, mem::size_of::<RGB8>());
 
     assert!(!g.is_null());
     unsafe {
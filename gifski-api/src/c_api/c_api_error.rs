@@ -1,4 +1,4 @@
-use crate::CatResult;
+use crate::{CatResult, ErrorCode};
 use std::fmt;
 use std::io;
 use std::os::raw::c_int;
@@ -6,6 +6,7 @@ use std::os::raw::c_int;
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum GifskiError {
     OK = 0,
     NULL_ARG,
@@ -23,14 +24,18 @@ pub enum GifskiError {
     UNEXPECTED_EOF,
     ABORTED,
     OTHER,
+    NO_FRAMES,
+    WRONG_SIZE,
+    DECODE,
+    INPUT_STALLED,
 }
 
-impl Into<io::Error> for GifskiError {
+impl From<GifskiError> for io::Error {
     #[cold]
-    fn into(self) -> io::Error {
+    fn from(g: GifskiError) -> Self {
         use std::io::ErrorKind as EK;
         use GifskiError::*;
-        match self {
+        match g {
             OK => panic!("wrong err code"),
             NOT_FOUND => EK::NotFound,
             PERMISSION_DENIED => EK::PermissionDenied,
@@ -40,7 +45,7 @@ impl Into<io::Error> for GifskiError {
             WRITE_ZERO => EK::WriteZero,
             INTERRUPTED => EK::Interrupted,
             UNEXPECTED_EOF => EK::UnexpectedEof,
-            _ => return io::Error::new(EK::Other, self),
+            _ => return io::Error::new(EK::Other, g),
         }.into()
     }
 }
@@ -65,6 +70,10 @@ impl From<c_int> for GifskiError {
             x if x == INTERRUPTED as c_int => INTERRUPTED,
             x if x == UNEXPECTED_EOF as c_int => UNEXPECTED_EOF,
             x if x == ABORTED as c_int => ABORTED,
+            x if x == NO_FRAMES as c_int => NO_FRAMES,
+            x if x == WRONG_SIZE as c_int => WRONG_SIZE,
+            x if x == DECODE as c_int => DECODE,
+            x if x == INPUT_STALLED as c_int => INPUT_STALLED,
             _ => OTHER,
         }
     }
@@ -73,35 +82,24 @@ impl From<c_int> for GifskiError {
 impl From<CatResult<()>> for GifskiError {
     #[cold]
     fn from(res: CatResult<()>) -> Self {
-        use crate::error::Error::*;
         match res {
             Ok(_) => GifskiError::OK,
-            Err(err) => match err {
-                Quant(_) => GifskiError::QUANT,
-                Pal(_) => GifskiError::GIF,
-                ThreadSend => GifskiError::THREAD_LOST,
-                Io(ref err) => err.kind().into(),
-                _ => GifskiError::OTHER,
-            },
+            Err(err) => err.code().into(),
         }
     }
 }
 
+impl From<ErrorCode> for GifskiError {
+    #[cold]
+    fn from(code: ErrorCode) -> Self {
+        (code as c_int).into()
+    }
+}
+
 impl From<io::ErrorKind> for GifskiError {
     #[cold]
     fn from(res: io::ErrorKind) -> Self {
-        use std::io::ErrorKind as EK;
-        match res {
-            EK::NotFound => GifskiError::NOT_FOUND,
-            EK::PermissionDenied => GifskiError::PERMISSION_DENIED,
-            EK::AlreadyExists => GifskiError::ALREADY_EXISTS,
-            EK::InvalidInput | EK::InvalidData => GifskiError::INVALID_INPUT,
-            EK::TimedOut => GifskiError::TIMED_OUT,
-            EK::WriteZero => GifskiError::WRITE_ZERO,
-            EK::Interrupted => GifskiError::INTERRUPTED,
-            EK::UnexpectedEof => GifskiError::UNEXPECTED_EOF,
-            _ => GifskiError::OTHER,
-        }
+        ErrorCode::from(res).into()
     }
 }
 
@@ -112,3 +110,12 @@ impl fmt::Display for GifskiError {
         fmt::Debug::fmt(self, f)
     }
 }
+
+#[test]
+fn codes_match_c() {
+    assert_eq!(GifskiError::QUANT as u32, ErrorCode::Quant as u32);
+    assert_eq!(GifskiError::OTHER as u32, ErrorCode::Other as u32);
+    assert_eq!(GifskiError::INPUT_STALLED as u32, ErrorCode::InputStalled as u32);
+    assert_eq!(GifskiError::NO_FRAMES, GifskiError::from(Err(crate::Error::NoFrames)));
+    assert_eq!(GifskiError::NOT_FOUND, GifskiError::from(Err(io::Error::from(io::ErrorKind::NotFound).into())));
+}
@@ -0,0 +1,39 @@
+//! Pixels copied from screen capture buffers, converted to RGBA later on the encoder's thread
+
+use crate::yuv::*;
+use imgref::*;
+use rgb::alt::BGRA8;
+use rgb::*;
+
+/// Contents of a capture buffer, in its original format
+pub(crate) enum CopiedPixels {
+    Bgra(ImgVec<BGRA8>),
+    Rgba(ImgVec<RGBA8>),
+    Nv12 {
+        luma: ImgVec<u8>,
+        /// Interleaved U and V, so the width is in bytes
+        chroma: ImgVec<u8>,
+        full_range: bool,
+    },
+}
+
+impl CopiedPixels {
+    pub fn nv12(luma: ImgRef<'_, u8>, chroma: ImgRef<'_, u8>, full_range: bool) -> Self {
+        Self::Nv12 {
+            luma: ImgVec::new(luma.pixels().collect(), luma.width(), luma.height()),
+            chroma: ImgVec::new(chroma.pixels().collect(), chroma.width(), chroma.height()),
+            full_range,
+        }
+    }
+
+    pub fn into_rgba(self) -> ImgVec<RGBA8> {
+        match self {
+            Self::Bgra(bgra) => crate::bgra_to_rgba(bgra.as_ref()),
+            Self::Rgba(rgba) => rgba,
+            Self::Nv12 { luma, chroma, full_range } => {
+                let matrix = YuvMatrix::for_size(luma.width(), luma.height());
+                nv12_to_rgba(luma.as_ref(), chroma.as_ref(), matrix, full_range)
+            },
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Reading frames from BMP and TGA files, which are common in older screen capture tools
+
+use crate::error::*;
+use crate::Collector;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+
+impl Collector {
+    /// Read and decode a BMP file from disk.
+    ///
+    /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
+    ///
+    /// If the first frame doesn't start at pts=0, the delay will be used for the last frame.
+    #[cfg(feature = "bmp")]
+    pub fn add_frame_bmp_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let image = decode_file(&path, ImageFormat::Bmp)?;
+        self.add_frame_image(frame_index, image, presentation_timestamp)
+    }
+
+    /// Read and decode a TGA (Targa) file from disk.
+    ///
+    /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
+    ///
+    /// If the first frame doesn't start at pts=0, the delay will be used for the last frame.
+    #[cfg(feature = "tga")]
+    pub fn add_frame_tga_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let image = decode_file(&path, ImageFormat::Tga)?;
+        self.add_frame_image(frame_index, image, presentation_timestamp)
+    }
+}
+
+fn decode_file(path: &Path, format: ImageFormat) -> CatResult<image::DynamicImage> {
+    let data = std::fs::read(path)?;
+    decode_bitmap(&data, format)
+        .map_err(|err| Error::Decode(format!("Can't load {}: {}", path.display(), err)))
+}
+
+pub(crate) fn decode_bitmap(data: &[u8], format: ImageFormat) -> Result<image::DynamicImage, image::ImageError> {
+    image::load_from_memory_with_format(data, format)
+}
+
+#[test]
+#[cfg(feature = "bmp")]
+fn decodes_bmp() {
+    let mut data = Vec::new();
+    image::RgbImage::from_pixel(3, 2, image::Rgb([255, 0, 0])).write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Bmp).unwrap();
+    let image = decode_bitmap(&data, ImageFormat::Bmp).unwrap();
+    assert_eq!((3, 2), (image.width(), image.height()));
+    assert_eq!(&[255, 0, 0], &image.into_rgb8().into_raw()[..3]);
+    assert!(decode_bitmap(b"BM", ImageFormat::Bmp).is_err());
+}
@@ -0,0 +1,54 @@
+//! Reading frames from OpenEXR files, such as renders from Blender or Houdini
+
+use crate::error::*;
+use crate::tonemap::hdr_to_8bit;
+use crate::Collector;
+use exr::prelude::{read, ReadChannels, ReadLayers};
+use imgref::*;
+use std::io::{BufReader, Read, Seek};
+use std::path::PathBuf;
+
+impl Collector {
+    /// Read and decode an OpenEXR file from disk. The first layer with RGB(A) channels is used.
+    ///
+    /// The HDR colors are converted to sRGB with the exposure and curve set with `set_tone_mapping()`.
+    ///
+    /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
+    ///
+    /// If the first frame doesn't start at pts=0, the delay will be used for the last frame.
+    pub fn add_frame_exr_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let file = std::fs::File::open(&path)
+            .map_err(|err| Error::Decode(format!("Can't load {}: {}", path.display(), err)))?;
+        let image = decode_exr(BufReader::new(file))
+            .map_err(|err| Error::Decode(format!("Can't load {}: {}", path.display(), err)))?;
+        let image = hdr_to_8bit(image.as_ref(), self.tone_mapping);
+        self.add_frame_rgba(frame_index, image, presentation_timestamp)
+    }
+}
+
+/// Linear RGBA, with premultiplied alpha
+fn decode_exr(reader: impl Read + Seek + Send) -> exr::error::Result<ImgVec<[f32; 4]>> {
+    let image = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |size, _| ImgVec::new(vec![[0.; 4]; size.area()], size.width(), size.height()),
+            |pixels: &mut ImgVec<[f32; 4]>, pos, (r, g, b, a): (f32, f32, f32, f32)| pixels[(pos.x(), pos.y())] = [r, g, b, a],
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(reader)?;
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+#[test]
+fn decodes_exr() {
+    use exr::prelude::{Image, SpecificChannels, Vec2, WritableImage};
+
+    let mut data = std::io::Cursor::new(Vec::new());
+    let channels = SpecificChannels::rgb(|pos: Vec2<usize>| (pos.x() as f32, 0.5f32, 0f32));
+    Image::from_channels((2, 1), channels).write().to_buffered(&mut data).unwrap();
+    data.set_position(0);
+    let image = decode_exr(data).unwrap();
+    assert_eq!(&[[0., 0.5, 0., 1.], [1., 0.5, 0., 1.]][..], image.buf());
+}
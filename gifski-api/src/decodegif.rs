@@ -0,0 +1,81 @@
+//! Reading frames from existing GIF files, to re-encode them
+
+use crate::error::*;
+use crate::Collector;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+impl Collector {
+    /// Add all frames of a GIF file, keeping their original timing.
+    ///
+    /// Frames are composited the way a GIF player would show them, so every frame added is a full screen image.
+    /// Frames with no delay (partial updates meant to be shown together) are merged with the frames after them.
+    ///
+    /// Frames are added starting from frame index 0. Returns the number of frames added.
+    pub fn add_frames_from_gif(&self, path: &Path) -> CatResult<usize> {
+        let file = File::open(path)?;
+        self.add_frames_from_gif_reader(BufReader::new(file))
+            .map_err(|err| match err {
+                Error::Decode(msg) => Error::Decode(format!("Can't load {}: {}", path.display(), msg)),
+                other => other,
+            })
+    }
+
+    fn add_frames_from_gif_reader(&self, reader: impl Read) -> CatResult<usize> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(reader).map_err(|err| Error::Decode(err.to_string()))?;
+        let mut screen = gif_dispose::Screen::new_decoder(&decoder);
+
+        let mut frame_index = 0;
+        // in centiseconds
+        let mut pts = 0u64;
+        let mut unsent_changes = false;
+        while let Some(frame) = decoder.read_next_frame().map_err(|err| Error::Decode(err.to_string()))? {
+            let delay = frame.delay;
+            screen.blit_frame(frame)?;
+            unsent_changes = true;
+            if delay > 0 {
+                self.add_frame_rgba(frame_index, screen.pixels.clone(), pts as f64 / 100.)?;
+                frame_index += 1;
+                pts += u64::from(delay);
+                unsent_changes = false;
+            }
+        }
+        if unsent_changes {
+            self.add_frame_rgba(frame_index, screen.pixels.clone(), pts as f64 / 100.)?;
+            frame_index += 1;
+        }
+        Ok(frame_index)
+    }
+}
+
+#[test]
+fn reads_gif_frames() {
+    let mut gif_data = Vec::new();
+    {
+        let mut enc = gif::Encoder::new(&mut gif_data, 2, 2, &[0, 0, 0, 255, 0, 0]).unwrap();
+        // the frame with no delay is merged into the next one
+        for &(row, delay) in &[(0u16, 10u16), (1, 0), (0, 20)] {
+            let mut frame = gif::Frame::from_indexed_pixels(2, 1, &[row as u8, 1], None);
+            frame.top = row;
+            frame.delay = delay;
+            enc.write_frame(&frame).unwrap();
+        }
+    }
+
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    let out = std::thread::spawn(move || {
+        let mut out = Vec::new();
+        writer.write(&mut out, &mut crate::progress::NoProgress {}).map(|_| out)
+    });
+    assert_eq!(2, collector.add_frames_from_gif_reader(&gif_data[..]).unwrap());
+    drop(collector);
+    let out = out.join().unwrap().unwrap();
+
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    assert_eq!(10, decoder.read_next_frame().unwrap().unwrap().delay);
+    assert!(decoder.read_next_frame().unwrap().is_some());
+    assert!(decoder.read_next_frame().unwrap().is_none());
+}
@@ -0,0 +1,37 @@
+//! Reading frames from QOI files
+
+use crate::error::*;
+use crate::Collector;
+use imgref::*;
+use rgb::*;
+use std::path::PathBuf;
+
+impl Collector {
+    /// Read and decode a QOI ("Quite OK Image") file from disk.
+    ///
+    /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
+    ///
+    /// If the first frame doesn't start at pts=0, the delay will be used for the last frame.
+    pub fn add_frame_qoi_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let data = std::fs::read(&path)?;
+        let image = decode_qoi(&data)
+            .map_err(|err| Error::Decode(format!("Can't load {}: {}", path.display(), err)))?;
+        self.add_frame_rgba(frame_index, image, presentation_timestamp)
+    }
+}
+
+pub(crate) fn decode_qoi(data: &[u8]) -> Result<ImgVec<RGBA8>, qoi::Error> {
+    let mut decoder = qoi::Decoder::new(data)?.with_channels(qoi::Channels::Rgba);
+    let header = *decoder.header();
+    let pixels = decoder.decode_to_vec()?;
+    Ok(ImgVec::new(pixels.as_rgba().to_vec(), header.width as usize, header.height as usize))
+}
+
+#[test]
+fn decodes_qoi() {
+    let pixels = [RGB8::new(255, 0, 0), RGB8::new(0, 0, 255)];
+    let data = qoi::encode_to_vec(pixels.as_bytes(), 2, 1).unwrap();
+    let image = decode_qoi(&data).unwrap();
+    assert_eq!(&[RGBA8::new(255, 0, 0, 255), RGBA8::new(0, 0, 255, 255)][..], image.buf());
+    assert!(decode_qoi(b"qoif").is_err());
+}
@@ -0,0 +1,117 @@
+//! Reading frames from multi-page TIFF files, such as image stacks from microscopes and other scientific cameras
+
+use crate::error::*;
+use crate::{Collector, ToneMapping};
+use imgref::*;
+use rgb::*;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+impl Collector {
+    /// Add every page (image file directory) of a TIFF file as a frame, starting from frame index 0.
+    ///
+    /// Presentation timestamps are computed from `fps`. Grayscale, RGB and RGBA pages with 8 or 16 bits per sample,
+    /// or 32-bit floats (0–1 range), are supported. 16-bit pages are reduced to 8 bits as configured with `set_tone_mapping()`.
+    ///
+    /// Returns the number of frames added.
+    pub fn add_frames_from_tiff(&self, path: &Path, fps: f32) -> CatResult<usize> {
+        if fps.is_nan() || fps <= 0. {
+            return Err(Error::InvalidInput(format!("Invalid frame rate {}", fps)));
+        }
+        let file = File::open(path)
+            .map_err(|err| Error::Decode(format!("Can't open {}: {}", path.display(), err)))?;
+        self.add_frames_from_tiff_reader(BufReader::new(file), fps)
+            .map_err(|err| match err {
+                Error::Decode(msg) => Error::Decode(format!("Can't load {}: {}", path.display(), msg)),
+                err => err,
+            })
+    }
+
+    fn add_frames_from_tiff_reader(&self, reader: impl Read + Seek, fps: f32) -> CatResult<usize> {
+        let mut decoder = Decoder::new(reader).map_err(tiff_error)?;
+        let mut frame_index = 0;
+        loop {
+            let image = read_page(&mut decoder, self.tone_mapping)?;
+            self.add_frame_rgba(frame_index, image, frame_index as f64 / f64::from(fps))?;
+            frame_index += 1;
+            if !decoder.more_images() {
+                break;
+            }
+            decoder.next_image().map_err(tiff_error)?;
+        }
+        Ok(frame_index)
+    }
+}
+
+fn read_page<R: Read + Seek>(decoder: &mut Decoder<R>, tone_mapping: ToneMapping) -> CatResult<ImgVec<RGBA8>> {
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let (width, height) = (width as usize, height as usize);
+    let color = decoder.colortype().map_err(tiff_error)?;
+    match decoder.read_image().map_err(tiff_error)? {
+        DecodingResult::U8(s) => to_rgba(color, &s, 255, width, height),
+        DecodingResult::U16(s) => {
+            let image = to_rgba(color, &s, u16::MAX, width, height)?;
+            Ok(crate::tonemap::to_8bit(image.as_ref(), tone_mapping))
+        },
+        DecodingResult::F32(s) => {
+            let s: Vec<u8> = s.into_iter().map(|v| (v.clamp(0., 1.) * 255. + 0.5) as u8).collect();
+            to_rgba(color, &s, 255, width, height)
+        },
+        _ => Err(Error::Decode(format!("Unsupported TIFF sample format in {:?} page", color))),
+    }
+}
+
+fn to_rgba<T: Copy>(color: ColorType, samples: &[T], opaque: T, width: usize, height: usize) -> CatResult<ImgVec<RGBA<T>>> {
+    let pixels: Vec<RGBA<T>> = match color {
+        ColorType::Gray(_) => samples.iter().map(|&l| RGBA::new(l, l, l, opaque)).collect(),
+        ColorType::GrayA(_) => samples.chunks_exact(2).map(|la| RGBA::new(la[0], la[0], la[0], la[1])).collect(),
+        ColorType::RGB(_) => samples.chunks_exact(3).map(|px| RGBA::new(px[0], px[1], px[2], opaque)).collect(),
+        ColorType::RGBA(_) => samples.chunks_exact(4).map(|px| RGBA::new(px[0], px[1], px[2], px[3])).collect(),
+        other => return Err(Error::Decode(format!("Unsupported TIFF color type {:?}", other))),
+    };
+    if pixels.len() < width * height {
+        return Err(Error::Decode("TIFF page has too little pixel data".into()));
+    }
+    Ok(ImgVec::new(pixels, width, height))
+}
+
+#[cold]
+fn tiff_error(err: tiff::TiffError) -> Error {
+    Error::Decode(err.to_string())
+}
+
+#[test]
+fn reads_tiff_pages() {
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    let mut data = std::io::Cursor::new(Vec::new());
+    {
+        let mut enc = TiffEncoder::new(&mut data).unwrap();
+        enc.write_image::<colortype::Gray16>(2, 1, &[0, 0xFFFF]).unwrap();
+        enc.write_image::<colortype::RGB8>(2, 1, &[255, 0, 0, 0, 0, 255]).unwrap();
+    }
+    data.set_position(0);
+    let mut decoder = Decoder::new(&mut data).unwrap();
+    assert_eq!(&[RGBA8::new(0, 0, 0, 255), RGBA8::new(255, 255, 255, 255)][..], read_page(&mut decoder, ToneMapping::default()).unwrap().buf());
+    decoder.next_image().unwrap();
+    assert_eq!(&[RGBA8::new(255, 0, 0, 255), RGBA8::new(0, 0, 255, 255)][..], read_page(&mut decoder, ToneMapping::default()).unwrap().buf());
+    assert!(!decoder.more_images());
+
+    // a dim 16-bit capture, which is nearly black without normalization
+    let mut dim = std::io::Cursor::new(Vec::new());
+    TiffEncoder::new(&mut dim).unwrap().write_image::<colortype::Gray16>(2, 1, &[100, 200]).unwrap();
+    dim.set_position(0);
+    let normalize = ToneMapping { normalize: true, dithering: false, ..ToneMapping::default() };
+    let page = read_page(&mut Decoder::new(&mut dim).unwrap(), normalize).unwrap();
+    assert_eq!(&[RGBA8::new(0, 0, 0, 255), RGBA8::new(255, 255, 255, 255)][..], page.buf());
+
+    data.set_position(0);
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    let out = std::thread::spawn(move || writer.write(Vec::new(), &mut crate::progress::NoProgress {}));
+    assert_eq!(2, collector.add_frames_from_tiff_reader(data, 10.).unwrap());
+    drop(collector);
+    assert_eq!(2, out.join().unwrap().unwrap().frames_written);
+}
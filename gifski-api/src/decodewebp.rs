@@ -0,0 +1,45 @@
+//! Reading frames from WebP files
+
+use crate::error::*;
+use crate::Collector;
+use imgref::*;
+use rgb::*;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+impl Collector {
+    /// Read and decode a lossy or lossless WebP file from disk.
+    ///
+    /// Only the first frame of animated WebP files is used.
+    ///
+    /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
+    pub fn add_frame_webp_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let data = std::fs::read(&path)?;
+        let image = decode_webp(&data)
+            .map_err(|err| Error::Decode(format!("Can't load {}: {}", path.display(), err)))?;
+        self.add_frame_rgba(frame_index, image, presentation_timestamp)
+    }
+}
+
+pub(crate) fn decode_webp(data: &[u8]) -> Result<ImgVec<RGBA8>, image_webp::DecodingError> {
+    let mut decoder = image_webp::WebPDecoder::new(Cursor::new(data))?;
+    let (width, height) = decoder.dimensions();
+    let mut buf = vec![0; decoder.output_buffer_size().ok_or(image_webp::DecodingError::ImageTooLarge)?];
+    decoder.read_image(&mut buf)?;
+    let pixels = if decoder.has_alpha() {
+        buf.as_rgba().to_vec()
+    } else {
+        buf.as_rgb().iter().map(|px| px.with_alpha(255)).collect()
+    };
+    Ok(ImgVec::new(pixels, width as usize, height as usize))
+}
+
+#[test]
+fn decodes_webp() {
+    let mut data = Vec::new();
+    let pixels = [RGB8::new(255, 0, 0), RGB8::new(0, 0, 255)];
+    image_webp::WebPEncoder::new(&mut data).encode(pixels.as_bytes(), 2, 1, image_webp::ColorType::Rgb8).unwrap();
+    let image = decode_webp(&data).unwrap();
+    assert_eq!(&[RGBA8::new(255, 0, 0, 255), RGBA8::new(0, 0, 255, 255)][..], image.buf());
+    assert!(decode_webp(b"RIFF\0\0\0\0WEBP").is_err());
+}
@@ -0,0 +1,55 @@
+//! Averaging of small changes between frames, like noise from camera sensors
+
+use crate::DecodedImage;
+use rgb::RGBA8;
+
+/// How much each new frame contributes to the average of pixels that stay the same
+const NEW_FRAME_WEIGHT: f32 = 0.25;
+
+/// Replaces opaque pixels that differ from their average in previous frames by at most `threshold` in every channel
+/// with a running average. Larger changes start a new average.
+///
+/// Frames added with their own palette are kept as they are.
+pub(crate) fn denoised(inputs: impl Iterator<Item = DecodedImage>, threshold: u8) -> impl Iterator<Item = DecodedImage> {
+    let threshold = f32::from(threshold);
+    let mut average: Vec<[f32; 3]> = Vec::new();
+    inputs.map(move |frame| {
+        let (mut image, pts, options, indexed, dropped_tags) = frame?;
+        if indexed.is_some() || average.len() != image.width() * image.height() {
+            average = image.pixels().map(channels).collect();
+            return Ok((image, pts, options, indexed, dropped_tags));
+        }
+        for (px, avg) in image.pixels_mut().zip(&mut average) {
+            let curr = channels(*px);
+            if px.a == 255 && curr.iter().zip(avg.iter()).all(|(c, a)| (c - a).abs() <= threshold) {
+                for (a, c) in avg.iter_mut().zip(curr) {
+                    *a += (c - *a) * NEW_FRAME_WEIGHT;
+                }
+                *px = RGBA8::new((avg[0] + 0.5) as u8, (avg[1] + 0.5) as u8, (avg[2] + 0.5) as u8, 255);
+            } else {
+                *avg = curr;
+            }
+        }
+        Ok((image, pts, options, indexed, dropped_tags))
+    })
+}
+
+fn channels(px: RGBA8) -> [f32; 3] {
+    [f32::from(px.r), f32::from(px.g), f32::from(px.b)]
+}
+
+#[test]
+fn averages_noise_but_not_motion() {
+    use crate::FrameOptions;
+    use imgref::ImgVec;
+
+    let frames = [[100, 200], [104, 30], [96, 30]].iter().enumerate().map(|(i, &[noisy, moving])| {
+        let image = ImgVec::new(vec![RGBA8::new(noisy, noisy, noisy, 255), RGBA8::new(moving, 0, 0, 255)], 2, 1);
+        Ok((image, i as f64, FrameOptions::default(), None, Vec::new()))
+    });
+    let frames: Vec<_> = denoised(frames, 8).map(|f| f.unwrap().0).collect();
+    assert_eq!(RGBA8::new(101, 101, 101, 255), frames[1].buf()[0]);
+    assert_eq!(RGBA8::new(100, 100, 100, 255), frames[2].buf()[0]);
+    assert_eq!(RGBA8::new(30, 0, 0, 255), frames[1].buf()[1]);
+    assert_eq!(RGBA8::new(30, 0, 0, 255), frames[2].buf()[1]);
+}
@@ -0,0 +1,111 @@
+//! Error diffusion with a choice of kernel and traversal, used instead of libimagequant's remapping
+//! when `Settings::dithering` is set
+
+use crate::{DitherKernel, Dithering};
+use imgref::*;
+use rgb::*;
+
+/// (x offset, y offset, weight), for left-to-right traversal
+fn kernel_weights(kernel: DitherKernel) -> &'static [(isize, usize, f32)] {
+    match kernel {
+        DitherKernel::FloydSteinberg => &[(1, 0, 7. / 16.), (-1, 1, 3. / 16.), (0, 1, 5. / 16.), (1, 1, 1. / 16.)],
+        DitherKernel::SierraLite => &[(1, 0, 2. / 4.), (-1, 1, 1. / 4.), (0, 1, 1. / 4.)],
+        // diffuses only 3/4 of the error, so it bleeds less
+        DitherKernel::Atkinson => &[(1, 0, 1. / 8.), (2, 0, 1. / 8.), (-1, 1, 1. / 8.), (0, 1, 1. / 8.), (1, 1, 1. / 8.), (0, 2, 1. / 8.)],
+    }
+}
+
+/// Maps pixels to the palette. Where `background` is given, a pixel closer to it than to any palette color
+/// gets the transparent index instead (if the palette has one).
+///
+/// `level` scales the diffused error, 0 disables dithering.
+pub(crate) fn remap(image: ImgRef<'_, RGBA8>, pal: &[RGBA8], background: Option<ImgRef<'_, RGBA8>>, dithering: Dithering, level: f32) -> ImgVec<u8> {
+    let width = image.width();
+    let height = image.height();
+    let transparent_index = pal.iter().position(|&c| is_transparent(c));
+    let weights = kernel_weights(dithering.kernel);
+    let mut error = vec![[0f32; 3]; width * height];
+    let mut out = vec![0u8; width * height];
+
+    for y in 0..height {
+        let reverse = dithering.serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let px = image[(x, y)];
+            if is_transparent(px) {
+                out[y * width + x] = transparent_index.unwrap_or(0) as u8;
+                continue;
+            }
+
+            let err = error[y * width + x];
+            let target = [
+                px.r as f32 + err[0] * level,
+                px.g as f32 + err[1] * level,
+                px.b as f32 + err[2] * level,
+            ];
+            let (mut index, mut chosen, dist) = nearest(pal, target);
+            if let (Some(bg), Some(transparent_index)) = (background, transparent_index) {
+                let bg = bg[(x, y)];
+                if !is_transparent(bg) {
+                    let bg_dist = distance(bg.rgb(), target);
+                    if bg_dist <= dist {
+                        index = transparent_index;
+                        chosen = bg.rgb();
+                    }
+                }
+            }
+            out[y * width + x] = index as u8;
+
+            let diff = [target[0] - chosen.r as f32, target[1] - chosen.g as f32, target[2] - chosen.b as f32];
+            for &(dx, dy, weight) in weights {
+                let dx = if reverse { -dx } else { dx };
+                let (nx, ny) = (x as isize + dx, y + dy);
+                if nx < 0 || nx as usize >= width || ny >= height {
+                    continue;
+                }
+                let e = &mut error[ny * width + nx as usize];
+                for c in 0..3 {
+                    e[c] += diff[c] * weight;
+                }
+            }
+        }
+    }
+    ImgVec::new(out, width, height)
+}
+
+fn nearest(pal: &[RGBA8], target: [f32; 3]) -> (usize, RGB8, f32) {
+    pal.iter().enumerate()
+        .filter(|&(_, &c)| !is_transparent(c))
+        .map(|(i, c)| (i, c.rgb(), distance(c.rgb(), target)))
+        .min_by(|a, b| a.2.total_cmp(&b.2))
+        .unwrap_or((0, RGB8::new(0, 0, 0), 0.))
+}
+
+/// Pixels have been made transparent or opaque with `Settings::alpha_threshold` (or `matte`) before they're remapped,
+/// so this only has to be the same cut-off for the pixels, the palette and the background.
+#[inline]
+fn is_transparent(c: RGBA8) -> bool {
+    c.a < 128
+}
+
+/// Same weights as `colordiff`
+#[inline]
+fn distance(c: RGB8, target: [f32; 3]) -> f32 {
+    let r = c.r as f32 - target[0];
+    let g = c.g as f32 - target[1];
+    let b = c.b as f32 - target[2];
+    r * r * 2. + g * g * 3. + b * b
+}
+
+#[test]
+fn serpentine_dithers_gradient() {
+    let pal = [RGBA8::new(0, 0, 0, 255), RGBA8::new(255, 255, 255, 255)];
+    let image = ImgVec::new(vec![RGBA8::new(128, 128, 128, 255); 8 * 8], 8, 8);
+    for &kernel in &[DitherKernel::FloydSteinberg, DitherKernel::SierraLite, DitherKernel::Atkinson] {
+        let out = remap(image.as_ref(), &pal, None, Dithering { kernel, serpentine: true }, 1.);
+        let white = out.pixels().filter(|&px| px == 1).count();
+        assert!((24..=40).contains(&white), "{:?} {}", kernel, white);
+    }
+    let out = remap(image.as_ref(), &pal, None, Dithering { kernel: DitherKernel::FloydSteinberg, serpentine: false }, 0.);
+    assert!(out.pixels().all(|px| px == out.buf()[0]));
+}
@@ -0,0 +1,219 @@
+//! Animated PNG output
+//!
+//! Frames are the same as in the GIF (including trimming, transparency and disposal), but stored as RGBA,
+//! because PNG can't have a separate palette for every frame.
+
+use crate::error::*;
+use crate::progress::ProgressReporter;
+use crate::{EncodeSummary, Encoder, GIFFrame, Repeat, Settings, Writer};
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use rgb::*;
+use std::io::Write;
+
+impl Writer {
+    /// Same as `write()`, but makes an animated PNG instead of a GIF.
+    ///
+    /// APNG has to begin with the number of frames, so the whole file is written at the end,
+    /// and `Settings::flush` has no effect.
+    pub fn write_apng<W: Write>(self, writer: W, reporter: &mut dyn ProgressReporter) -> CatResult<EncodeSummary> {
+        self.write_with_encoder(&mut ApngEncoder::new(writer), reporter)
+    }
+}
+
+pub(crate) struct ApngEncoder<W: Write> {
+    writer: W,
+    screen: Option<(u16, u16)>,
+    num_plays: u32,
+    /// fcTL and frame data of every frame, in chunks without their sequence numbers
+    frames: Vec<(FrameControl, Vec<u8>)>,
+    written: u64,
+}
+
+struct FrameControl {
+    left: u16,
+    top: u16,
+    width: u16,
+    height: u16,
+    delay: u16,
+    dispose_op: u8,
+}
+
+impl<W: Write> ApngEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            screen: None,
+            num_plays: 0,
+            frames: Vec::new(),
+            written: 0,
+        }
+    }
+
+    fn write_chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> CatResult<()> {
+        let mut crc = Crc::new();
+        crc.update(kind);
+        crc.update(data);
+        self.writer.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.writer.write_all(kind)?;
+        self.writer.write_all(data)?;
+        self.writer.write_all(&crc.sum().to_be_bytes())?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Encoder for ApngEncoder<W> {
+    fn write_frame(&mut self, f: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()> {
+        let rgba = f.to_rgba();
+        let GIFFrame {left, top, screen_width, screen_height, dispose, ..} = f;
+        self.screen.get_or_insert((screen_width, screen_height));
+        self.num_plays = match settings.repeat {
+            Repeat::Infinite => 0,
+            Repeat::None | Repeat::Finite(0) => 1,
+            // in GIF it's the number of repeats after the first play
+            Repeat::Finite(n) => u32::from(n) + 1,
+        };
+
+        let data = compress(rgba.buf(), rgba.width())?;
+        self.written += data.len() as u64;
+
+        self.frames.push((FrameControl {
+            left,
+            top,
+            width: rgba.width() as u16,
+            height: rgba.height() as u16,
+            delay,
+            dispose_op: match dispose {
+                gif::DisposalMethod::Background => 1,
+                gif::DisposalMethod::Previous => 2,
+                _ => 0,
+            },
+        }, data));
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    fn flush(&mut self) -> CatResult<()> {
+        // nothing can be written before the frame count is known
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CatResult<()> {
+        let (width, height) = self.screen.ok_or(Error::NoFrames)?;
+        self.writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&u32::from(width).to_be_bytes());
+        ihdr.extend_from_slice(&u32::from(height).to_be_bytes());
+        // 8-bit RGBA, deflate, adaptive filtering, not interlaced
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        self.write_chunk(b"IHDR", &ihdr)?;
+
+        let mut actl = Vec::with_capacity(8);
+        actl.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        actl.extend_from_slice(&self.num_plays.to_be_bytes());
+        self.write_chunk(b"acTL", &actl)?;
+
+        let mut sequence_number = 0u32;
+        for (i, (fc, data)) in std::mem::take(&mut self.frames).into_iter().enumerate() {
+            let mut fctl = Vec::with_capacity(26);
+            fctl.extend_from_slice(&sequence_number.to_be_bytes());
+            for n in [fc.width, fc.height, fc.left, fc.top] {
+                fctl.extend_from_slice(&u32::from(n).to_be_bytes());
+            }
+            fctl.extend_from_slice(&fc.delay.to_be_bytes());
+            fctl.extend_from_slice(&100u16.to_be_bytes());
+            // the first frame can't dispose to previous, because there's nothing before it
+            let dispose_op = if i == 0 && fc.dispose_op == 2 { 1 } else { fc.dispose_op };
+            // blend over, so that transparent pixels keep the previous frame, like in GIF
+            fctl.extend_from_slice(&[dispose_op, 1]);
+            self.write_chunk(b"fcTL", &fctl)?;
+            sequence_number += 1;
+
+            // the first frame is also the default image, which must be full-screen. GIF frames are.
+            if i == 0 {
+                self.write_chunk(b"IDAT", &data)?;
+            } else {
+                let mut fdat = Vec::with_capacity(4 + data.len());
+                fdat.extend_from_slice(&sequence_number.to_be_bytes());
+                fdat.extend_from_slice(&data);
+                self.write_chunk(b"fdAT", &fdat)?;
+                sequence_number += 1;
+            }
+        }
+        self.write_chunk(b"IEND", &[])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Filters rows (choosing the filter with the smallest sum of absolute values) and deflates them
+fn compress(rgba: &[RGBA8], width: usize) -> CatResult<Vec<u8>> {
+    let bytes = rgba.as_bytes();
+    let stride = width * 4;
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::best());
+    let empty = vec![0; stride];
+    let mut prev: &[u8] = &empty;
+    let mut candidates = [vec![0; stride], vec![0; stride], vec![0; stride], vec![0; stride], vec![0; stride]];
+    for row in bytes.chunks_exact(stride) {
+        for (filter, out) in candidates.iter_mut().enumerate() {
+            for x in 0..stride {
+                let a = if x >= 4 { row[x - 4] } else { 0 };
+                let b = prev[x];
+                let c = if x >= 4 { prev[x - 4] } else { 0 };
+                out[x] = row[x].wrapping_sub(match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                    _ => paeth(a, b, c),
+                });
+            }
+        }
+        let (best, filtered) = candidates.iter().enumerate()
+            .min_by_key(|(_, f)| f.iter().map(|&v| u32::from((v as i8).unsigned_abs())).sum::<u32>())
+            .expect("filters");
+        zlib.write_all(&[best as u8])?;
+        zlib.write_all(filtered)?;
+        prev = row;
+    }
+    Ok(zlib.finish()?)
+}
+
+#[inline]
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+#[test]
+#[cfg(feature = "png")]
+fn writes_apng() {
+    let frames = (0..3u8).map(|i| (imgref::ImgVec::new(vec![RGBA8::new(i * 80, 0, 0, 255); 4 * 4], 4, 4), f64::from(i) / 10.));
+    let (collector, writer) = crate::new(Settings::default()).unwrap();
+    let mut out = Vec::new();
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for (i, (img, pts)) in frames.enumerate() {
+                collector.add_frame_rgba(i, img, pts).unwrap();
+            }
+        });
+        writer.write_apng(&mut out, &mut crate::progress::NoProgress {}).unwrap();
+    });
+
+    assert!(out.starts_with(b"\x89PNG\r\n\x1a\n"));
+    let actl = out.windows(4).position(|w| w == b"acTL").unwrap();
+    assert_eq!([0, 0, 0, 3], out[actl + 4..actl + 8]);
+    assert!(out.ends_with(b"IEND\xae\x42\x60\x82"));
+
+    // the default image must decode as a normal PNG
+    let image = lodepng::decode32(&out).unwrap();
+    assert_eq!((4, 4), (image.width, image.height));
+    assert_eq!(RGBA8::new(0, 0, 0, 255), image.buffer[0]);
+}
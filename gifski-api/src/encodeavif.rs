@@ -0,0 +1,346 @@
+//! Animated AVIF output
+//!
+//! AV1 frames always cover the whole screen, so GIF frames are composited first.
+//! The frames are encoded with rav1e as 4:4:4 YUV, and stored as an `avis` image sequence.
+//! AVIF keeps alpha in a separate track, which isn't written, so transparent pixels become black.
+
+use crate::error::*;
+use crate::progress::ProgressReporter;
+use crate::screen::Screen;
+use crate::{EncodeSummary, Encoder, GIFFrame, Settings, Writer};
+use rav1e::color::{ColorDescription, ColorPrimaries, MatrixCoefficients, PixelRange, TransferCharacteristics};
+use rav1e::prelude::*;
+use rgb::*;
+use std::io::{self, Write};
+
+/// AVIF files don't need to be in centiseconds, but it's what GIF delays are in
+const TIMESCALE: u32 = 100;
+
+impl Writer {
+    /// Same as `write()`, but makes an animated AVIF instead of a GIF.
+    ///
+    /// The file index has to be before or after all the frames, so the whole file is written at the end,
+    /// and `Settings::flush` has no effect. `Settings::repeat` is ignored, since players loop AVIF as they like.
+    pub fn write_avif<W: Write>(self, writer: W, reporter: &mut dyn ProgressReporter) -> CatResult<EncodeSummary> {
+        self.write_with_encoder(&mut AvifEncoder::new(writer), reporter)
+    }
+}
+
+pub(crate) struct AvifEncoder<W: Write> {
+    writer: W,
+    screen: Option<Screen>,
+    context: Option<Context<u8>>,
+    /// Delay of every frame sent to the encoder
+    delays: Vec<u16>,
+    /// Encoded frames, in order, and whether each is a key frame
+    samples: Vec<(Vec<u8>, bool)>,
+    encoded_bytes: u64,
+}
+
+impl<W: Write> AvifEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            screen: None,
+            context: None,
+            delays: Vec::new(),
+            samples: Vec::new(),
+            encoded_bytes: 0,
+        }
+    }
+
+    fn new_context(width: usize, height: usize, settings: &Settings) -> CatResult<Context<u8>> {
+        let speed = if settings.fast { 10 } else if settings.extra_effort { 4 } else { 6 };
+        let config = EncoderConfig {
+            width,
+            height,
+            time_base: Rational::new(1, TIMESCALE.into()),
+            bit_depth: 8,
+            chroma_sampling: ChromaSampling::Cs444,
+            pixel_range: PixelRange::Full,
+            color_description: Some(ColorDescription {
+                color_primaries: ColorPrimaries::BT709,
+                transfer_characteristics: TransferCharacteristics::SRGB,
+                matrix_coefficients: MatrixCoefficients::BT601,
+            }),
+            quantizer: (255. * (1. - f32::from(settings.quality.clamp(1, 100)) / 100.)).round() as usize,
+            speed_settings: SpeedSettings::from_preset(speed),
+            ..EncoderConfig::default()
+        };
+        Config::new().with_encoder_config(config).new_context()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("AVIF encoder: {}", err)).into())
+    }
+
+    /// Moves encoded frames out of rav1e, until it wants more input
+    fn receive_packets(&mut self) -> CatResult<()> {
+        let context = match self.context.as_mut() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        loop {
+            match context.receive_packet() {
+                Ok(packet) => {
+                    self.encoded_bytes += packet.data.len() as u64;
+                    self.samples.push((packet.data, packet.frame_type == FrameType::KEY));
+                },
+                Err(EncoderStatus::Encoded) => {},
+                Err(EncoderStatus::NeedMoreData | EncoderStatus::LimitReached) => return Ok(()),
+                Err(err) => return Err(io::Error::new(io::ErrorKind::Other, format!("AVIF encoder: {}", err)).into()),
+            }
+        }
+    }
+}
+
+impl<W: Write> Encoder for AvifEncoder<W> {
+    fn write_frame(&mut self, f: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()> {
+        let screen = self.screen.get_or_insert_with(|| Screen::new(f.screen_width.into(), f.screen_height.into()));
+        let (width, height) = (screen.width(), screen.height());
+        let planes = screen.draw(&f, |pixels| {
+            let mut planes = [Vec::with_capacity(width * height), Vec::with_capacity(width * height), Vec::with_capacity(width * height)];
+            for px in pixels.pixels() {
+                let [y, u, v] = rgb_to_yuv(px);
+                planes[0].push(y);
+                planes[1].push(u);
+                planes[2].push(v);
+            }
+            planes
+        });
+
+        if self.context.is_none() {
+            self.context = Some(Self::new_context(width, height, settings)?);
+        }
+        let context = self.context.as_mut().unwrap();
+        let mut frame = context.new_frame();
+        for (dst, src) in frame.planes.iter_mut().zip(&planes) {
+            dst.copy_from_raw_u8(src, width, 1);
+        }
+        context.send_frame(frame)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("AVIF encoder: {}", err)))?;
+        // a frame shown for 0 would have no timestamp of its own
+        self.delays.push(delay.max(1));
+        self.receive_packets()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.encoded_bytes
+    }
+
+    fn flush(&mut self) -> CatResult<()> {
+        // nothing can be written before the file index is known
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CatResult<()> {
+        let (width, height) = match &self.screen {
+            Some(screen) => (screen.width(), screen.height()),
+            None => return Err(Error::NoFrames),
+        };
+        let context = self.context.as_mut().ok_or(Error::NoFrames)?;
+        context.flush();
+        self.receive_packets()?;
+        let context = self.context.take().ok_or(Error::NoFrames)?;
+        if self.samples.len() != self.delays.len() {
+            return Err(io::Error::new(io::ErrorKind::Other, "AVIF encoder lost frames").into());
+        }
+
+        let mut ftyp = Vec::new();
+        write_box(&mut ftyp, b"ftyp", |b| {
+            b.extend_from_slice(b"avis");
+            b.extend_from_slice(&0u32.to_be_bytes());
+            b.extend_from_slice(b"avismsf1iso8");
+        });
+
+        let mdat_len: usize = self.samples.iter().map(|(data, _)| data.len()).sum();
+        // the chunk offset is a fixed size field, so it can be filled in after the size of `moov` is known
+        let moov = movie_box(width as u32, height as u32, &self.delays, &self.samples, &context.container_sequence_header(), 0);
+        let mdat_start = ftyp.len() + moov.len() + 8;
+        if mdat_start + mdat_len > u32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::Other, "AVIF file is too large").into());
+        }
+        let moov = movie_box(width as u32, height as u32, &self.delays, &self.samples, &context.container_sequence_header(), mdat_start as u32);
+
+        self.writer.write_all(&ftyp)?;
+        self.writer.write_all(&moov)?;
+        self.writer.write_all(&((mdat_len + 8) as u32).to_be_bytes())?;
+        self.writer.write_all(b"mdat")?;
+        for (data, _) in self.samples.drain(..) {
+            self.writer.write_all(&data)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Full-range BT.601, premultiplied with black
+fn rgb_to_yuv(px: RGBA8) -> [u8; 3] {
+    let a = f32::from(px.a) / 255.;
+    let (r, g, b) = (f32::from(px.r) * a, f32::from(px.g) * a, f32::from(px.b) * a);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let u = (b - y) * 0.564 + 128.;
+    let v = (r - y) * 0.713 + 128.;
+    [y.round() as u8, u.round().clamp(0., 255.) as u8, v.round().clamp(0., 255.) as u8]
+}
+
+fn movie_box(width: u32, height: u32, delays: &[u16], samples: &[(Vec<u8>, bool)], av1_config: &[u8], data_offset: u32) -> Vec<u8> {
+    let duration: u32 = delays.iter().map(|&d| u32::from(d)).sum();
+    let mut moov = Vec::new();
+    write_box(&mut moov, b"moov", |b| {
+        write_full_box(b, b"mvhd", 0, |b| {
+            put_u32s(b, &[0, 0, TIMESCALE, duration, 0x0001_0000]);
+            b.extend_from_slice(&0x0100u16.to_be_bytes());
+            b.extend_from_slice(&[0; 10]);
+            put_u32s(b, &UNITY_MATRIX);
+            put_u32s(b, &[0; 6]);
+            put_u32s(b, &[2]); // next track ID
+        });
+        write_box(b, b"trak", |b| {
+            // enabled, in movie
+            write_full_box(b, b"tkhd", 3, |b| {
+                put_u32s(b, &[0, 0, 1, 0, duration, 0, 0, 0, 0]);
+                put_u32s(b, &UNITY_MATRIX);
+                put_u32s(b, &[width << 16, height << 16]);
+            });
+            write_box(b, b"mdia", |b| {
+                write_full_box(b, b"mdhd", 0, |b| {
+                    put_u32s(b, &[0, 0, TIMESCALE, duration]);
+                    // "und" language
+                    b.extend_from_slice(&[0x55, 0xC4, 0, 0]);
+                });
+                write_full_box(b, b"hdlr", 0, |b| {
+                    put_u32s(b, &[0]);
+                    b.extend_from_slice(b"pict");
+                    put_u32s(b, &[0; 3]);
+                    b.push(0);
+                });
+                write_box(b, b"minf", |b| {
+                    write_full_box(b, b"vmhd", 1, |b| b.extend_from_slice(&[0; 8]));
+                    write_box(b, b"dinf", |b| {
+                        write_full_box(b, b"dref", 0, |b| {
+                            put_u32s(b, &[1]);
+                            // data is in this file
+                            write_full_box(b, b"url ", 1, |_| {});
+                        });
+                    });
+                    write_box(b, b"stbl", |b| {
+                        sample_table(b, width, height, delays, samples, av1_config, data_offset);
+                    });
+                });
+            });
+        });
+    });
+    moov
+}
+
+fn sample_table(b: &mut Vec<u8>, width: u32, height: u32, delays: &[u16], samples: &[(Vec<u8>, bool)], av1_config: &[u8], data_offset: u32) {
+    write_full_box(b, b"stsd", 0, |b| {
+        put_u32s(b, &[1]);
+        write_box(b, b"av01", |b| {
+            b.extend_from_slice(&[0; 6]);
+            b.extend_from_slice(&1u16.to_be_bytes()); // data reference index
+            put_u32s(b, &[0; 4]);
+            b.extend_from_slice(&(width as u16).to_be_bytes());
+            b.extend_from_slice(&(height as u16).to_be_bytes());
+            put_u32s(b, &[0x0048_0000, 0x0048_0000, 0]); // 72 dpi
+            b.extend_from_slice(&1u16.to_be_bytes()); // frame count
+            b.extend_from_slice(&[0; 32]); // compressor name
+            b.extend_from_slice(&[0x00, 0x18, 0xFF, 0xFF]); // depth, pre-defined
+            write_box(b, b"av1C", |b| b.extend_from_slice(av1_config));
+            write_box(b, b"colr", |b| {
+                b.extend_from_slice(b"nclx");
+                // BT.709 primaries, sRGB transfer, BT.601 matrix, full range
+                b.extend_from_slice(&[0, 1, 0, 13, 0, 6, 0x80]);
+            });
+            // frames are not all intra, and may use any number of references
+            write_full_box(b, b"ccst", 0, |b| put_u32s(b, &[1 << 30 | 15 << 26]));
+        });
+    });
+
+    let mut time_to_sample: Vec<(u32, u32)> = Vec::new();
+    for &delay in delays {
+        match time_to_sample.last_mut() {
+            Some((count, d)) if *d == u32::from(delay) => *count += 1,
+            _ => time_to_sample.push((1, delay.into())),
+        }
+    }
+    write_full_box(b, b"stts", 0, |b| {
+        put_u32s(b, &[time_to_sample.len() as u32]);
+        for (count, delay) in time_to_sample {
+            put_u32s(b, &[count, delay]);
+        }
+    });
+
+    // without this box all frames would be key frames
+    write_full_box(b, b"stss", 0, |b| {
+        let key_frames: Vec<u32> = samples.iter().enumerate()
+            .filter(|(_, (_, key))| *key)
+            .map(|(i, _)| i as u32 + 1)
+            .collect();
+        put_u32s(b, &[key_frames.len() as u32]);
+        put_u32s(b, &key_frames);
+    });
+
+    // all frames are in one chunk
+    write_full_box(b, b"stsc", 0, |b| put_u32s(b, &[1, 1, samples.len() as u32, 1]));
+    write_full_box(b, b"stsz", 0, |b| {
+        put_u32s(b, &[0, samples.len() as u32]);
+        for (data, _) in samples {
+            put_u32s(b, &[data.len() as u32]);
+        }
+    });
+    write_full_box(b, b"stco", 0, |b| put_u32s(b, &[1, data_offset]));
+}
+
+const UNITY_MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+fn put_u32s(out: &mut Vec<u8>, values: &[u32]) {
+    for v in values {
+        out.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(kind);
+    body(out);
+    let len = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+/// Box with version 0 and `flags`
+fn write_full_box(out: &mut Vec<u8>, kind: &[u8; 4], flags: u32, body: impl FnOnce(&mut Vec<u8>)) {
+    write_box(out, kind, |b| {
+        put_u32s(b, &[flags & 0xFF_FFFF]);
+        body(b);
+    });
+}
+
+#[test]
+fn writes_avif_sequence() {
+    use imgref::ImgVec;
+
+    let settings = Settings { fast: true, ..Settings::default() };
+    let (collector, writer) = crate::new(settings).unwrap();
+    let mut out = Vec::new();
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..3u8 {
+                let mut img = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); 16 * 16], 16, 16);
+                img[(usize::from(i) + 1, 3usize)] = RGBA8::new(255, 0, 0, 255);
+                collector.add_frame_rgba(i.into(), img, f64::from(i) / 10.).unwrap();
+            }
+        });
+        writer.write_avif(&mut out, &mut crate::progress::NoProgress {}).unwrap();
+    });
+
+    assert_eq!(b"ftypavis", &out[4..12]);
+    let find = |kind: &[u8]| out.windows(4).position(|w| w == kind).unwrap();
+    let u32_at = |i: usize| u32::from_be_bytes([out[i], out[i + 1], out[i + 2], out[i + 3]]) as usize;
+    let stsz = find(b"stsz");
+    assert_eq!(3, u32_at(stsz + 12));
+    let stco = find(b"stco");
+    let mdat = find(b"mdat");
+    assert_eq!(mdat + 4, u32_at(stco + 12));
+    assert_eq!(out.len() - mdat + 4, u32_at(mdat - 4));
+    assert_eq!(0x81, out[find(b"av1C") + 4]);
+}
@@ -11,6 +11,7 @@ pub(crate) struct Gifsicle<'w> {
     gif_writer: *mut Gif_Writer,
     out: &'w mut dyn Write,
     info: Gif_CompressInfo,
+    written: u64,
 }
 
 impl<'w> Gifsicle<'w> {
@@ -21,6 +22,7 @@ impl<'w> Gifsicle<'w> {
                 gif_writer: ptr::null_mut(),
                 info: std::mem::zeroed(),
                 out,
+                written: 0,
             };
             Gif_InitCompressInfo(&mut g.info);
             g.info.loss = loss as _;
@@ -34,6 +36,7 @@ impl<'w> Gifsicle<'w> {
                 let buf_start = (*self.gif_writer).v.as_mut().ok_or(Error::Gifsicle)?;
                 let buf = std::slice::from_raw_parts(buf_start, (*self.gif_writer).pos as usize);
                 self.out.write_all(buf)?;
+                self.written += buf.len() as u64;
                 (*self.gif_writer).pos = 0;
             }
         }
@@ -59,11 +62,21 @@ impl Encoder for Gifsicle<'_> {
             // fun fact: can't flush after the last write, because the writer gets freed,
             // but the last write is literally just `;` (we don't use comments/extensions)
             self.out.write_all(std::slice::from_ref(&b';'))?;
+            self.written += 1;
             unsafe {
                 Gif_IncrementalWriteComplete(self.gif_writer, self.gfs);
             }
             self.gif_writer = ptr::null_mut();
         }
+        self.flush()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    fn flush(&mut self) -> CatResult<()> {
+        self.out.flush()?;
         Ok(())
     }
     fn write_frame(&mut self, frame: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()> {
@@ -77,9 +90,8 @@ impl Encoder for Gifsicle<'_> {
             gfs.screen_width = screen_width;
             gfs.screen_height = screen_height;
             // -1 is no looping, 0 is loop forever, else loop X number of times
-            // not sure the else will work.. I need to get gif::Repeat copy-able first to test.
             match settings.repeat {
-                Repeat::Finite(0) => gfs.loopcount = -1,
+                Repeat::None | Repeat::Finite(0) => gfs.loopcount = -1,
                 Repeat::Infinite => gfs.loopcount = 0,
                 Repeat::Finite(x) => gfs.loopcount = x as _,
             }
@@ -110,7 +122,7 @@ impl Encoder for Gifsicle<'_> {
         g.local = unsafe { Gif_NewFullColormap(0, pal.len() as _) }; // it's owned by the image
         for c in pal.iter() {
             unsafe {
-                Gif_AddColor((*g).local, &mut Gif_Color {
+                Gif_AddColor(g.local, &mut Gif_Color {
                     gfc_red: c.r,
                     gfc_green: c.g,
                     gfc_blue: c.b,
@@ -0,0 +1,186 @@
+//! H.264 MP4 made from the same frames as the GIF
+//!
+//! The frames are the GIF's frames composited onto the whole screen, so the video looks like the GIF
+//! (with its colors, and transparency shown as black). It's meant for `<video>` fallbacks on the web.
+
+use crate::error::*;
+use crate::progress::ProgressReporter;
+use crate::screen::Screen;
+use crate::{encoderust, EncodeSummary, Encoder, GIFFrame, Settings, Writer};
+use ffmpeg::format::Pixel;
+use ffmpeg::software::scaling;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// GIF delays are in centiseconds
+const TIME_BASE: (i32, i32) = (1, 100);
+
+impl Writer {
+    /// Same as `write()`, and also writes an MP4 video of the same frames to `mp4_path`.
+    ///
+    /// Requires ffmpeg with the libx264 encoder. Frames are encoded only once, and the video is written as they come.
+    #[allow(unused_mut)]
+    pub fn write_with_mp4<W: Write>(self, mut writer: W, mp4_path: &Path, reporter: &mut dyn ProgressReporter) -> CatResult<EncodeSummary> {
+        let mp4 = Mp4Encoder::new(mp4_path, &self.settings)?;
+
+        #[cfg(feature = "gifsicle")]
+        {
+            if self.settings.quality < 100 && !self.settings.pixel_art {
+                let mut gifsicle = crate::encodegifsicle::Gifsicle::new(self.settings.gifsicle_loss(), &mut writer);
+                return self.write_with_encoder(&mut WithMp4 { gif: &mut gifsicle, mp4 }, reporter);
+            }
+        }
+        self.write_with_encoder(&mut WithMp4 { gif: &mut encoderust::RustEncoder::new(writer), mp4 }, reporter)
+    }
+}
+
+/// Sends frames to both the GIF and the video
+struct WithMp4<'a> {
+    gif: &'a mut dyn Encoder,
+    mp4: Mp4Encoder,
+}
+
+impl Encoder for WithMp4<'_> {
+    fn write_frame(&mut self, f: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()> {
+        self.mp4.write_frame(&f, delay)?;
+        self.gif.write_frame(f, delay, settings)
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.gif.bytes_written()
+    }
+
+    fn flush(&mut self) -> CatResult<()> {
+        self.gif.flush()
+    }
+
+    fn finish(&mut self) -> CatResult<()> {
+        self.mp4.finish()?;
+        self.gif.finish()
+    }
+}
+
+struct Mp4Encoder {
+    output: ffmpeg::format::context::Output,
+    x264_options: Option<ffmpeg::Dictionary<'static>>,
+    encoder: Option<ffmpeg::encoder::Video>,
+    scaler: Option<scaling::Context>,
+    screen: Option<Screen>,
+    /// In centiseconds
+    pts: i64,
+    /// Shown again at the end, so that the last frame lasts for its delay
+    last_frame: Option<ffmpeg::frame::Video>,
+}
+
+impl Mp4Encoder {
+    pub fn new(path: &Path, settings: &Settings) -> CatResult<Self> {
+        ffmpeg::init().map_err(video_error)?;
+        let output = ffmpeg::format::output(&path).map_err(video_error)?;
+
+        // quality 100 is visually lossless, and quality 50 is as bad as a GIF at quality 50
+        let crf = 18. + f32::from(100 - settings.quality.min(100)) * 0.24;
+        let mut x264_options = ffmpeg::Dictionary::new();
+        x264_options.set("crf", &format!("{:.1}", crf));
+        x264_options.set("preset", if settings.fast { "veryfast" } else if settings.extra_effort { "slow" } else { "medium" });
+
+        Ok(Self {
+            output,
+            x264_options: Some(x264_options),
+            encoder: None,
+            scaler: None,
+            screen: None,
+            pts: 0,
+            last_frame: None,
+        })
+    }
+
+    /// The size is known only from the first frame
+    fn open(&mut self, width: u32, height: u32) -> CatResult<()> {
+        let codec = ffmpeg::encoder::find(ffmpeg::codec::Id::H264)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "ffmpeg has no H.264 encoder"))?;
+        let global_header = self.output.format().flags().contains(ffmpeg::format::flag::Flags::GLOBAL_HEADER);
+        // 4:2:0 needs even dimensions
+        let (video_width, video_height) = ((width + 1) & !1, (height + 1) & !1);
+
+        let mut stream = self.output.add_stream(codec).map_err(video_error)?;
+        let mut encoder = stream.codec().encoder().video().map_err(video_error)?;
+        encoder.set_width(video_width);
+        encoder.set_height(video_height);
+        encoder.set_format(Pixel::YUV420P);
+        encoder.set_time_base(TIME_BASE);
+        if global_header {
+            encoder.set_flags(ffmpeg::codec::flag::Flags::GLOBAL_HEADER);
+        }
+        let options = self.x264_options.take().unwrap_or_else(ffmpeg::Dictionary::new);
+        let encoder = encoder.open_as_with(codec, options).map_err(video_error)?;
+        stream.set_parameters(&encoder);
+
+        let mut format_options = ffmpeg::Dictionary::new();
+        // index at the start, so that browsers can play it before it's fully downloaded
+        format_options.set("movflags", "+faststart");
+        self.output.write_header_with(format_options).map_err(video_error)?;
+
+        self.scaler = Some(scaling::Context::get(Pixel::RGBA, width, height, Pixel::YUV420P, video_width, video_height, scaling::flag::Flags::BILINEAR)
+            .map_err(video_error)?);
+        self.encoder = Some(encoder);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, f: &GIFFrame, delay: u16) -> CatResult<()> {
+        if self.encoder.is_none() {
+            self.open(f.screen_width.into(), f.screen_height.into())?;
+        }
+        let screen = self.screen.get_or_insert_with(|| Screen::new(f.screen_width.into(), f.screen_height.into()));
+        let mut rgba = ffmpeg::frame::Video::new(Pixel::RGBA, screen.width() as u32, screen.height() as u32);
+        screen.draw(f, |pixels| {
+            let stride = rgba.stride(0);
+            let data = rgba.data_mut(0);
+            for (dst, src) in data.chunks_mut(stride).zip(pixels.rows()) {
+                for (dst, px) in dst.chunks_exact_mut(4).zip(src) {
+                    // transparency is black, as in other players that ignore alpha
+                    let a = u16::from(px.a);
+                    dst.copy_from_slice(&[(u16::from(px.r) * a / 255) as u8, (u16::from(px.g) * a / 255) as u8, (u16::from(px.b) * a / 255) as u8, 255]);
+                }
+            }
+        });
+
+        let mut yuv = ffmpeg::frame::Video::empty();
+        self.scaler.as_mut().expect("opened").run(&rgba, &mut yuv).map_err(video_error)?;
+        yuv.set_pts(Some(self.pts));
+        self.pts += i64::from(delay.max(1));
+        self.send(Some(&yuv))?;
+        self.last_frame = Some(yuv);
+        Ok(())
+    }
+
+    /// Encodes the frame, or flushes the encoder if it's `None`, and writes the compressed packets
+    fn send(&mut self, frame: Option<&ffmpeg::frame::Video>) -> CatResult<()> {
+        let encoder = self.encoder.as_mut().expect("opened");
+        match frame {
+            Some(frame) => encoder.send_frame(frame),
+            None => encoder.send_eof(),
+        }.map_err(video_error)?;
+
+        let stream_time_base = self.output.stream(0).expect("stream").time_base();
+        let mut packet = ffmpeg::Packet::empty();
+        while encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(0);
+            packet.rescale_ts(TIME_BASE, stream_time_base);
+            packet.write_interleaved(&mut self.output).map_err(video_error)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CatResult<()> {
+        let mut last_frame = self.last_frame.take().ok_or(Error::NoFrames)?;
+        last_frame.set_pts(Some(self.pts));
+        self.send(Some(&last_frame))?;
+        self.send(None)?;
+        self.output.write_trailer().map_err(video_error)?;
+        Ok(())
+    }
+}
+
+fn video_error(err: ffmpeg::Error) -> Error {
+    io::Error::new(io::ErrorKind::Other, format!("MP4: {}", err)).into()
+}
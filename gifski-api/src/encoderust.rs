@@ -3,39 +3,67 @@ use crate::GIFFrame;
 use crate::Settings;
 use crate::{Encoder, Repeat};
 use rgb::*;
-use std::io::Write;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
 
 pub(crate) struct RustEncoder<W: Write> {
-    writer: Option<W>,
-    gif_enc: Option<gif::Encoder<W>>,
+    writer: W,
+    /// gif encoder owns its writer, so it writes to a buffer that is moved to the writer after each frame
+    buf: Rc<RefCell<Vec<u8>>>,
+    gif_enc: Option<gif::Encoder<SharedBuf>>,
+    written: u64,
+}
+
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl<W: Write> RustEncoder<W> {
     pub fn new(writer: W) -> Self {
         Self {
-            writer: Some(writer),
+            writer,
+            buf: Rc::default(),
             gif_enc: None,
+            written: 0,
         }
     }
+
+    fn write_buffered(&mut self) -> CatResult<()> {
+        let mut buf = self.buf.borrow_mut();
+        self.writer.write_all(&buf)?;
+        self.written += buf.len() as u64;
+        buf.clear();
+        Ok(())
+    }
 }
 
 impl<W: Write> Encoder for RustEncoder<W> {
     fn write_frame(&mut self, f: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()> {
         let GIFFrame {left, top, pal, image, screen_width, screen_height, dispose, transparent_index} = f;
 
-        let writer = &mut self.writer;
-
-        let repeat;
-        match settings.repeat {
-            Repeat::Infinite => repeat = gif::Repeat::Infinite,
-            Repeat::Finite(x) => repeat = gif::Repeat::Finite(x),
-        }
+        // the extension's loop count of 0 means forever, so playing once needs no extension
+        let repeat = match settings.repeat {
+            Repeat::Infinite => Some(gif::Repeat::Infinite),
+            Repeat::None | Repeat::Finite(0) => None,
+            Repeat::Finite(x) => Some(gif::Repeat::Finite(x)),
+        };
 
         let enc = match self.gif_enc {
             None => {
-                let w = writer.take().expect("writer");
-                let mut enc = gif::Encoder::new(w, screen_width, screen_height, &[])?;
-                enc.write_extension(gif::ExtensionData::Repetitions(repeat))?;
+                let mut enc = gif::Encoder::new(SharedBuf(self.buf.clone()), screen_width, screen_height, &[])?;
+                if let Some(repeat) = repeat {
+                    enc.write_extension(gif::ExtensionData::Repetitions(repeat))?;
+                }
                 self.gif_enc.get_or_insert(enc)
             },
             Some(ref mut enc) => enc,
@@ -61,6 +89,22 @@ impl<W: Write> Encoder for RustEncoder<W> {
             palette: Some(pal_rgb),
             buffer: buffer.into(),
         })?;
+        self.write_buffered()
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    fn flush(&mut self) -> CatResult<()> {
+        self.writer.flush()?;
         Ok(())
     }
+
+    fn finish(&mut self) -> CatResult<()> {
+        // writes the trailer
+        drop(self.gif_enc.take());
+        self.write_buffered()?;
+        self.flush()
+    }
 }
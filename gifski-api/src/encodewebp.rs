@@ -0,0 +1,224 @@
+//! Animated WebP output
+//!
+//! Frames are the same as in the GIF, stored losslessly as RGBA. They're cut out of what a GIF player would show,
+//! because WebP frames can only start at even coordinates, and WebP can't dispose to the previous frame.
+
+use crate::error::*;
+use crate::progress::ProgressReporter;
+use crate::screen::Screen;
+use crate::{EncodeSummary, Encoder, GIFFrame, Repeat, Settings, Writer};
+use image_webp::{ColorType, WebPEncoder};
+use rgb::*;
+use std::io::{self, Write};
+
+impl Writer {
+    /// Same as `write()`, but makes an animated WebP instead of a GIF.
+    ///
+    /// WebP has to begin with the file size, so the whole file is written at the end,
+    /// and `Settings::flush` has no effect.
+    pub fn write_webp<W: Write>(self, writer: W, reporter: &mut dyn ProgressReporter) -> CatResult<EncodeSummary> {
+        self.write_with_encoder(&mut WebPAnimEncoder::new(writer), reporter)
+    }
+}
+
+pub(crate) struct WebPAnimEncoder<W: Write> {
+    writer: W,
+    screen: Option<Screen>,
+    /// Area cleared or restored by the previous frame's disposal, which the next frame has to cover
+    disposed: Option<(usize, usize, usize, usize)>,
+    loop_count: u16,
+    /// ANMF chunks
+    frames: Vec<u8>,
+    has_alpha: bool,
+}
+
+impl<W: Write> WebPAnimEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            screen: None,
+            disposed: None,
+            loop_count: 0,
+            frames: Vec::new(),
+            has_alpha: false,
+        }
+    }
+}
+
+impl<W: Write> Encoder for WebPAnimEncoder<W> {
+    fn write_frame(&mut self, f: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()> {
+        let screen = self.screen.get_or_insert_with(|| Screen::new(f.screen_width.into(), f.screen_height.into()));
+        self.loop_count = match settings.repeat {
+            Repeat::Infinite => 0,
+            Repeat::None | Repeat::Finite(0) => 1,
+            // in GIF it's the number of repeats after the first play
+            Repeat::Finite(n) => n.saturating_add(1),
+        };
+
+        // the frame's area, and whatever the previous frame's disposal changed, starting at even coordinates
+        let frame_area = (usize::from(f.left), usize::from(f.top), f.image.width(), f.image.height());
+        let (left, top, right, bottom) = match self.disposed.take() {
+            Some((left, top, width, height)) => (
+                left.min(frame_area.0), top.min(frame_area.1),
+                (left + width).max(frame_area.0 + frame_area.2), (top + height).max(frame_area.1 + frame_area.3),
+            ),
+            None => (frame_area.0, frame_area.1, frame_area.0 + frame_area.2, frame_area.1 + frame_area.3),
+        };
+        let (left, top) = (left & !1, top & !1);
+        let (width, height) = (right - left, bottom - top);
+
+        let mut still = Vec::new();
+        let has_alpha = screen.draw(&f, |pixels| {
+            let area = pixels.sub_image(left, top, width, height);
+            let (buf, ..) = area.to_contiguous_buf();
+            WebPEncoder::new(&mut still).encode(buf.as_bytes(), width as u32, height as u32, ColorType::Rgba8)?;
+            Ok::<_, image_webp::EncodingError>(area.pixels().any(|px| px.a < 255))
+        }).map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        self.has_alpha |= has_alpha;
+        if matches!(f.dispose, gif::DisposalMethod::Background | gif::DisposalMethod::Previous) {
+            self.disposed = Some(frame_area);
+        }
+
+        // the simple format is RIFF, size, WEBP, and then the VP8L chunk
+        let vp8l = still.get(12..).filter(|c| c.starts_with(b"VP8L"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "unexpected WebP encoder output"))?;
+
+        let mut anmf = Vec::with_capacity(16 + vp8l.len());
+        anmf.extend_from_slice(&u24(left / 2));
+        anmf.extend_from_slice(&u24(top / 2));
+        anmf.extend_from_slice(&u24(width - 1));
+        anmf.extend_from_slice(&u24(height - 1));
+        anmf.extend_from_slice(&u24(usize::from(delay) * 10));
+        // replaces the area without blending, since it's a copy of the whole screen there, and isn't disposed
+        anmf.push(1 << 1);
+        anmf.extend_from_slice(vp8l);
+        write_chunk(&mut self.frames, b"ANMF", &anmf)?;
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    fn flush(&mut self) -> CatResult<()> {
+        // nothing can be written before the file size is known
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CatResult<()> {
+        let (width, height) = self.screen.as_ref().map(|screen| (screen.width(), screen.height())).ok_or(Error::NoFrames)?;
+
+        let mut vp8x = Vec::with_capacity(10);
+        vp8x.push(if self.has_alpha { 1 << 4 | 1 << 1 } else { 1 << 1 });
+        vp8x.extend_from_slice(&[0; 3]);
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+
+        // transparent background, as in gifski's GIFs
+        let mut anim = vec![0; 4];
+        anim.extend_from_slice(&self.loop_count.to_le_bytes());
+
+        let mut header = Vec::with_capacity(64);
+        header.extend_from_slice(b"WEBP");
+        write_chunk(&mut header, b"VP8X", &vp8x)?;
+        write_chunk(&mut header, b"ANIM", &anim)?;
+
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_all(&((header.len() + self.frames.len()) as u32).to_le_bytes())?;
+        self.writer.write_all(&header)?;
+        self.writer.write_all(&std::mem::take(&mut self.frames))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn u24(n: usize) -> [u8; 3] {
+    let b = (n as u32).to_le_bytes();
+    [b[0], b[1], b[2]]
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(kind)?;
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+    out.write_all(data)?;
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    Ok(())
+}
+
+#[test]
+fn writes_animated_webp() {
+    use imgref::ImgVec;
+
+    let (collector, writer) = crate::new(Settings::default()).unwrap();
+    let mut out = Vec::new();
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..3u8 {
+                let mut img = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); 5 * 5], 5, 5);
+                img[(usize::from(i) + 1, 3usize)] = RGBA8::new(255, 0, 0, 255);
+                collector.add_frame_rgba(i.into(), img, f64::from(i) / 10.).unwrap();
+            }
+        });
+        writer.write_webp(&mut out, &mut crate::progress::NoProgress {}).unwrap();
+    });
+
+    assert_eq!(b"RIFF", &out[..4]);
+    assert_eq!(out.len() - 8, u32::from_le_bytes([out[4], out[5], out[6], out[7]]) as usize);
+    assert_eq!(b"WEBPVP8X", &out[8..16]);
+    assert_eq!(3, out.windows(4).filter(|w| w == b"ANMF").count());
+
+    let mut decoder = image_webp::WebPDecoder::new(io::Cursor::new(&out)).unwrap();
+    assert!(decoder.is_animated());
+    assert_eq!(3, decoder.num_frames());
+    let mut buf = vec![0; decoder.output_buffer_size().unwrap()];
+    decoder.read_frame(&mut buf).unwrap();
+    decoder.read_frame(&mut buf).unwrap();
+    // red pixel of the second frame at (2, 3)
+    let stride = 5 * if decoder.has_alpha() { 4 } else { 3 };
+    assert!(buf[3 * stride + 2 * stride / 5] > 250);
+}
+
+#[test]
+fn keeps_pixels_around_odd_offsets_and_disposal() {
+    use imgref::ImgVec;
+
+    let red = RGBA8::new(255, 0, 0, 255);
+    let frame = |left, top, color: RGBA8, dispose| GIFFrame {
+        left, top, screen_width: 4, screen_height: 4,
+        image: ImgVec::new(vec![0; if left == 0 { 16 } else { 1 }], if left == 0 { 4 } else { 1 }, if left == 0 { 4 } else { 1 }),
+        pal: vec![color], dispose, transparent_index: None,
+    };
+    let mut out = Vec::new();
+    let mut enc = WebPAnimEncoder::new(&mut out);
+    let settings = Settings::default();
+    enc.write_frame(frame(0, 0, red, gif::DisposalMethod::Keep), 10, &settings).unwrap();
+    enc.write_frame(frame(1, 1, RGBA8::new(0, 0, 255, 255), gif::DisposalMethod::Background), 10, &settings).unwrap();
+    enc.write_frame(frame(3, 3, RGBA8::new(0, 255, 0, 255), gif::DisposalMethod::Previous), 10, &settings).unwrap();
+    enc.write_frame(frame(3, 1, red, gif::DisposalMethod::Keep), 10, &settings).unwrap();
+    enc.finish().unwrap();
+
+    let mut decoder = image_webp::WebPDecoder::new(io::Cursor::new(&out)).unwrap();
+    assert_eq!(4, decoder.num_frames());
+    assert!(decoder.has_alpha());
+    let mut buf = vec![0; decoder.output_buffer_size().unwrap()];
+    let mut frames = Vec::new();
+    for _ in 0..4 {
+        decoder.read_frame(&mut buf).unwrap();
+        frames.push(buf.as_rgba().to_vec());
+    }
+    let px = |frame: usize, x: usize, y: usize| frames[frame][y * 4 + x];
+    assert_eq!(RGBA8::new(0, 0, 255, 255), px(1, 1, 1));
+    assert_eq!(red, px(1, 0, 0));
+    assert_eq!(red, px(1, 2, 1));
+    // the blue pixel is cleared by its disposal, and nothing else
+    assert_eq!(0, px(2, 1, 1).a);
+    assert_eq!(red, px(2, 0, 0));
+    assert_eq!(red, px(2, 2, 2));
+    assert_eq!(RGBA8::new(0, 255, 0, 255), px(2, 3, 3));
+    // the green pixel is restored to what was there before it
+    assert_eq!(red, px(3, 3, 3));
+    assert_eq!(0, px(3, 1, 1).a);
+    assert_eq!(red, px(3, 3, 1));
+}
@@ -1,7 +1,9 @@
 use std::io;
+use std::time::Duration;
 
 quick_error! {
     #[derive(Debug)]
+    #[non_exhaustive]
     pub enum Error {
         ThreadSend {
             display("thread aborted")
@@ -15,6 +17,9 @@ quick_error! {
         Gif(err: gif::EncodingError) {
             display("GIF encoding error: {}", err)
         }
+        InputStalled { last_frame: Option<usize>, waited: Duration } {
+            display("No frames were added for {:?} (last frame index received: {:?})", waited, last_frame)
+        }
         NoFrames {
             display("Found no usable frames to encode")
         }
@@ -25,6 +30,18 @@ quick_error! {
         PNG(msg: String) {
             display("{}", msg)
         }
+        InvalidInput(msg: String) {
+            display("{}", msg)
+        }
+        Archive(msg: String) {
+            display("{}", msg)
+        }
+        Decode(msg: String) {
+            display("{}", msg)
+        }
+        Subtitles(msg: String) {
+            display("{}", msg)
+        }
         WrongSize(msg: String) {
             display("{}", msg)
             from(e: resize::Error) -> (e.to_string())
@@ -42,6 +59,86 @@ quick_error! {
 
 pub type CatResult<T, E = Error> = Result<T, E>;
 
+/// Kind of an error, for deciding what to do about it without parsing error messages.
+///
+/// The numbers are the same as `GIFSKI_*` error constants in the C API.
+/// They will never change, but new codes may be added.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// Internal error related to palette quantization
+    Quant = 3,
+    /// Internal error related to GIF composing
+    Gif = 4,
+    /// Internal error related to multithreading
+    ThreadLost = 5,
+    /// I/O error: file or directory not found
+    NotFound = 6,
+    /// I/O error: permission denied
+    PermissionDenied = 7,
+    /// I/O error: file already exists
+    AlreadyExists = 8,
+    /// Invalid arguments or frames
+    InvalidInput = 9,
+    /// I/O error
+    TimedOut = 10,
+    /// I/O error
+    WriteZero = 11,
+    /// I/O error
+    Interrupted = 12,
+    /// I/O error
+    UnexpectedEof = 13,
+    /// Progress callback asked to stop
+    Aborted = 14,
+    /// Should not happen
+    Other = 15,
+    /// None of the frames could be used
+    NoFrames = 16,
+    /// Frame sizes don't match, and `WrongSizePolicy::Fail` is set
+    WrongSize = 17,
+    /// An input image or archive couldn't be decoded
+    Decode = 18,
+    /// No frames were added for longer than `Settings::input_timeout`
+    InputStalled = 19,
+}
+
+impl Error {
+    /// Stable numeric kind of this error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ThreadSend => ErrorCode::ThreadLost,
+            Self::Aborted => ErrorCode::Aborted,
+            Self::Gifsicle | Self::Gif(_) | Self::Pal(_) => ErrorCode::Gif,
+            Self::InputStalled { .. } => ErrorCode::InputStalled,
+            Self::NoFrames => ErrorCode::NoFrames,
+            Self::Io(err) => err.kind().into(),
+            Self::PNG(_) | Self::Archive(_) | Self::Decode(_) => ErrorCode::Decode,
+            Self::InvalidInput(_) | Self::Subtitles(_) => ErrorCode::InvalidInput,
+            Self::WrongSize(_) => ErrorCode::WrongSize,
+            Self::Quant(_) => ErrorCode::Quant,
+        }
+    }
+}
+
+impl From<io::ErrorKind> for ErrorCode {
+    #[cold]
+    fn from(kind: io::ErrorKind) -> Self {
+        use std::io::ErrorKind as EK;
+        match kind {
+            EK::NotFound => Self::NotFound,
+            EK::PermissionDenied => Self::PermissionDenied,
+            EK::AlreadyExists => Self::AlreadyExists,
+            EK::InvalidInput | EK::InvalidData => Self::InvalidInput,
+            EK::TimedOut => Self::TimedOut,
+            EK::WriteZero => Self::WriteZero,
+            EK::Interrupted => Self::Interrupted,
+            EK::UnexpectedEof => Self::UnexpectedEof,
+            _ => Self::Other,
+        }
+    }
+}
+
 impl From<gif::EncodingError> for Error {
     #[cold]
     fn from(err: gif::EncodingError) -> Self {
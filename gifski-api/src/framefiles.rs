@@ -0,0 +1,136 @@
+//! Frames from image files in a directory or an archive, recognized by their extension and contents
+
+use crate::error::*;
+use crate::{Collector, ToneMapping};
+use imgref::*;
+use rgb::*;
+use std::path::Path;
+
+impl Collector {
+    /// Add all PNG files (and JPEG, WebP, QOI, BMP and TGA with the features of the same name) from a directory as frames.
+    ///
+    /// Files are sorted in natural order (`frame2.png` before `frame10.png`), and
+    /// their presentation timestamps are computed from `fps`. Other files and subdirectories are ignored.
+    ///
+    /// Frames are added starting from frame index 0. Returns the number of frames added.
+    pub fn add_frames_from_dir(&self, path: &Path, fps: f32) -> CatResult<usize> {
+        if fps.is_nan() || fps <= 0. {
+            return Err(Error::InvalidInput(format!("Invalid frame rate {}", fps)));
+        }
+        let mut files = Vec::new();
+        for entry in path.read_dir()? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if is_frame_file(&name) && entry.file_type()?.is_file() {
+                files.push(name);
+            }
+        }
+        files.sort_by(|a, b| natord::compare(a, b));
+
+        for (frame_index, name) in files.iter().enumerate() {
+            let data = std::fs::read(path.join(name))?;
+            let image = decode_image(name, &data, self.tone_mapping)?;
+            self.add_frame_rgba(frame_index, image, frame_index as f64 / f64::from(fps))?;
+        }
+        Ok(files.len())
+    }
+}
+
+/// Whether the file name (or a path in an archive) looks like a supported image
+pub(crate) fn is_frame_file(name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    if file_name.starts_with('.') || name.starts_with("__MACOSX/") {
+        return false;
+    }
+    let lower = file_name.to_ascii_lowercase();
+    lower.ends_with(".png")
+        || (cfg!(feature = "jpeg") && (lower.ends_with(".jpg") || lower.ends_with(".jpeg")))
+        || (cfg!(feature = "webp") && lower.ends_with(".webp"))
+        || (cfg!(feature = "qoi") && lower.ends_with(".qoi"))
+        || (cfg!(feature = "bmp") && lower.ends_with(".bmp"))
+        || (cfg!(feature = "tga") && lower.ends_with(".tga"))
+}
+
+/// Decodes the image by its magic number. `name` is only for error messages and formats without a magic number.
+pub(crate) fn decode_image(name: &str, data: &[u8], tone_mapping: ToneMapping) -> CatResult<ImgVec<RGBA8>> {
+    if data.starts_with(b"\x89PNG") {
+        return crate::tonemap::decode_png(data, tone_mapping)
+            .map_err(|err| Error::PNG(format!("Can't load {}: {}", name, err)));
+    }
+
+    #[cfg(feature = "webp")]
+    {
+        if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+            return crate::decodewebp::decode_webp(data)
+                .map_err(|err| Error::Decode(format!("Can't load {}: {}", name, err)));
+        }
+    }
+
+    #[cfg(feature = "qoi")]
+    {
+        if data.starts_with(b"qoif") {
+            return crate::decodeqoi::decode_qoi(data)
+                .map_err(|err| Error::Decode(format!("Can't load {}: {}", name, err)));
+        }
+    }
+
+    #[cfg(any(feature = "bmp", feature = "tga"))]
+    {
+        let format = if cfg!(feature = "bmp") && data.starts_with(b"BM") {
+            Some(image::ImageFormat::Bmp)
+        } else if cfg!(feature = "tga") && name.to_ascii_lowercase().ends_with(".tga") {
+            // TGA has no magic number
+            Some(image::ImageFormat::Tga)
+        } else {
+            None
+        };
+        if let Some(format) = format {
+            let image = crate::decodebitmap::decode_bitmap(data, format)
+                .map_err(|err| Error::Decode(format!("Can't load {}: {}", name, err)))?
+                .into_rgba8();
+            let (width, height) = (image.width() as usize, image.height() as usize);
+            return Ok(ImgVec::new(image.into_raw().as_rgba().to_vec(), width, height));
+        }
+    }
+
+    #[cfg(not(feature = "jpeg"))]
+    {
+        Err(Error::Decode(format!("Can't load {}: unsupported file type", name)))
+    }
+
+    #[cfg(feature = "jpeg")]
+    {
+        let mut decoder = jpeg_decoder::Decoder::new(data);
+        let pixels = decoder.decode()
+            .map_err(|err| Error::Decode(format!("Can't load {}: {}", name, err)))?;
+        let info = decoder.info().ok_or_else(|| Error::Decode(format!("Can't load {}", name)))?;
+        let pixels: Vec<RGBA8> = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => pixels.as_rgb().iter().map(|px| RGBA8::new(px.r, px.g, px.b, 255)).collect(),
+            jpeg_decoder::PixelFormat::L8 => pixels.iter().map(|&l| RGBA8::new(l, l, l, 255)).collect(),
+            _ => return Err(Error::Decode(format!("Unsupported JPEG color format in {}", name))),
+        };
+        Ok(ImgVec::new(pixels, info.width as usize, info.height as usize))
+    }
+}
+
+#[test]
+fn reads_dir_in_natural_order() {
+    let dir = std::env::temp_dir().join(format!("gifski-frames-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    lodepng::encode32_file(dir.join("frame10.png"), &[RGBA8::new(0, 0, 255, 255); 4], 2, 2).unwrap();
+    lodepng::encode32_file(dir.join("frame2.png"), &[RGBA8::new(255, 0, 0, 255); 4], 2, 2).unwrap();
+    std::fs::write(dir.join("notes.txt"), "not a frame").unwrap();
+
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    let added = collector.add_frames_from_dir(&dir, 10.);
+    drop(collector);
+    let mut out = Vec::new();
+    writer.write(&mut out, &mut crate::progress::NoProgress {}).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(2, added.unwrap());
+
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = decoder.read_info(&out[..]).unwrap();
+    assert_eq!(&[255, 0, 0, 255], &decoder.read_next_frame().unwrap().unwrap().buffer[..4]);
+}
@@ -0,0 +1,58 @@
+//! Conversion of frames with an ICC profile, such as Display P3 screenshots, to sRGB
+
+use crate::error::*;
+use crate::Collector;
+use imgref::*;
+use moxcms::{ColorProfile, Layout, Transform8BitExecutor, TransformOptions};
+use rgb::*;
+use std::sync::Arc;
+
+/// Transform from the frames' color profile to sRGB, shared with deferred frames
+#[derive(Clone)]
+pub(crate) struct ColorConversion(Arc<Transform8BitExecutor>);
+
+impl ColorConversion {
+    fn new(profile: &ColorProfile) -> CatResult<Self> {
+        let transform = profile.create_transform_8bit(Layout::Rgba, &ColorProfile::new_srgb(), Layout::Rgba, TransformOptions::default())
+            .map_err(|err| Error::InvalidInput(format!("Can't convert the ICC profile to sRGB: {}", err)))?;
+        Ok(Self(transform))
+    }
+
+    pub fn convert(&self, pixels: &[RGBA8]) -> CatResult<Vec<RGBA8>> {
+        let mut out = vec![RGBA8::new(0, 0, 0, 0); pixels.len()];
+        self.0.transform(pixels.as_bytes(), out.as_bytes_mut())
+            .map_err(|err| Error::InvalidInput(format!("Can't convert colors to sRGB: {}", err)))?;
+        Ok(out)
+    }
+
+    pub fn to_srgb(&self, image: ImgVec<RGBA8>) -> CatResult<ImgVec<RGBA8>> {
+        let (buf, width, height) = image.into_contiguous_buf();
+        Ok(ImgVec::new(self.convert(&buf)?, width, height))
+    }
+}
+
+impl Collector {
+    /// Frames are in the color space of this ICC profile, rather than sRGB, and will be converted to sRGB.
+    ///
+    /// Screenshots from Macs are usually in Display P3, and their colors look too saturated when treated as sRGB.
+    /// The profile can be copied from the `iCCP` chunk of a PNG screenshot. Call before adding frames.
+    pub fn set_icc_profile(&mut self, icc: &[u8]) -> CatResult<()> {
+        let profile = ColorProfile::new_from_slice(icc)
+            .map_err(|err| Error::InvalidInput(format!("Can't read the ICC profile: {}", err)))?;
+        self.color_conversion = Some(ColorConversion::new(&profile)?);
+        Ok(())
+    }
+}
+
+#[test]
+fn display_p3_is_less_saturated_in_srgb() {
+    let p3 = ColorProfile::new_display_p3().encode().unwrap();
+    let (mut collector, _writer) = crate::new(crate::Settings::default()).unwrap();
+    collector.set_icc_profile(&p3).unwrap();
+    assert!(collector.set_icc_profile(b"not a profile").is_err());
+
+    let conversion = collector.color_conversion.as_ref().unwrap();
+    let srgb = conversion.convert(&[RGBA8::new(128, 128, 128, 255), RGBA8::new(200, 100, 100, 77)]).unwrap();
+    assert!(srgb[0].r.abs_diff(128) <= 1 && srgb[0].r == srgb[0].g && srgb[0].g == srgb[0].b);
+    assert!(srgb[1].r > 200 && srgb[1].g < 100 && srgb[1].a == 77);
+}
@@ -0,0 +1,107 @@
+//! Receiving frames from other processes over a pipe or a socket
+//!
+//! The stream is a sequence of frames, each a 16-byte header followed by pixels:
+//!
+//! | bytes | meaning |
+//! |---|---|
+//! | 4 | width, `u32` little-endian |
+//! | 4 | height, `u32` little-endian |
+//! | 8 | presentation timestamp in seconds, `f64` little-endian |
+//! | width×height×4 | RGBA pixels, row by row, without padding |
+//!
+//! The sender closes the connection after the last frame.
+
+use crate::error::*;
+use crate::Collector;
+use imgref::*;
+use rgb::*;
+use std::io::{self, Read};
+
+/// Larger frames are surely an error in the stream, and would make GIFs that no browser would open anyway
+const MAX_PIXELS: u64 = 1 << 28;
+
+impl Collector {
+    /// Add frames sent in the format described in the `ipc` module docs, until the end of the stream.
+    ///
+    /// Works with anything readable, such as stdin, a FIFO, or a Windows named pipe opened with `File::open(r"\\.\pipe\name")`.
+    ///
+    /// Frames are added starting from frame index 0. Returns the number of frames added.
+    pub fn add_frames_from_stream(&self, mut reader: impl Read) -> CatResult<usize> {
+        let mut frame_index = 0;
+        while let Some((image, pts)) = read_frame(&mut reader)? {
+            self.add_frame_rgba(frame_index, image, pts)?;
+            frame_index += 1;
+        }
+        Ok(frame_index)
+    }
+
+    /// Listen on a Unix domain socket at `path`, and add frames from the first process that connects.
+    ///
+    /// The socket file is created, and removed once the sender disconnects. Returns the number of frames added.
+    #[cfg(unix)]
+    pub fn add_frames_from_unix_socket(&self, path: &std::path::Path) -> CatResult<usize> {
+        let listener = std::os::unix::net::UnixListener::bind(path)
+            .map_err(|err| Error::InvalidInput(format!("Can't listen on {}: {}", path.display(), err)))?;
+        let res = listener.accept().map_err(Error::from)
+            .and_then(|(stream, _)| self.add_frames_from_stream(io::BufReader::new(stream)));
+        let _ = std::fs::remove_file(path);
+        res
+    }
+}
+
+fn read_frame(reader: &mut impl Read) -> CatResult<Option<(ImgVec<RGBA8>, f64)>> {
+    let mut header = [0; 16];
+    // distinguishes a clean end of the stream from a truncated header
+    let mut len = 0;
+    while len < header.len() {
+        match reader.read(&mut header[len..]) {
+            Ok(0) if len == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let width = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let height = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let mut pts = [0; 8];
+    pts.copy_from_slice(&header[8..]);
+    let pts = f64::from_le_bytes(pts);
+
+    let pixels = u64::from(width) * u64::from(height);
+    if pixels == 0 || pixels > MAX_PIXELS || !pts.is_finite() {
+        return Err(Error::InvalidInput(format!("Invalid frame header in the stream: {}x{} at {}s", width, height, pts)));
+    }
+    let mut data = vec![0u8; pixels as usize * 4];
+    reader.read_exact(&mut data)?;
+    Ok(Some((ImgVec::new(data.as_rgba().to_vec(), width as usize, height as usize), pts)))
+}
+
+#[test]
+fn reads_frames_from_stream() {
+    let mut stream = Vec::new();
+    for i in 0..3u8 {
+        stream.extend_from_slice(&2u32.to_le_bytes());
+        stream.extend_from_slice(&1u32.to_le_bytes());
+        stream.extend_from_slice(&(f64::from(i) / 10.).to_le_bytes());
+        stream.extend_from_slice(&[i, 0, 0, 255, 0, i, 0, 255]);
+    }
+    let mut reader = &stream[..];
+    let (image, pts) = read_frame(&mut reader).unwrap().unwrap();
+    assert_eq!((2, 1, 0.), (image.width(), image.height(), pts));
+    let (image, pts) = read_frame(&mut reader).unwrap().unwrap();
+    assert_eq!((RGBA8::new(0, 1, 0, 255), 0.1), (image.buf()[1], pts));
+    read_frame(&mut reader).unwrap().unwrap();
+    assert!(read_frame(&mut reader).unwrap().is_none());
+
+    assert!(read_frame(&mut &stream[..20]).is_err());
+    assert!(read_frame(&mut &[0u8; 16][..]).is_err());
+
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    std::thread::scope(|s| {
+        let t = s.spawn(move || writer.write(Vec::new(), &mut crate::progress::NoProgress {}).unwrap());
+        assert_eq!(3, collector.add_frames_from_stream(&stream[..]).unwrap());
+        drop(collector);
+        assert_eq!(3, t.join().unwrap().frames_written);
+    });
+}
@@ -23,60 +23,650 @@ extern crate quick_error;
 use imagequant::*;
 use imgref::*;
 use rgb::*;
+use rgb::alt::BGRA8;
 
 mod error;
 pub use crate::error::*;
 mod ordqueue;
 use crate::ordqueue::*;
+mod playlist;
+use crate::playlist::*;
+mod multi;
+pub use crate::multi::*;
+mod analyze;
+pub use crate::analyze::*;
 pub mod progress;
 use crate::progress::*;
+pub mod monitor;
+use crate::monitor::*;
 pub mod c_api;
 mod encoderust;
+mod dither;
+mod priority;
+mod decodegif;
 
 #[cfg(feature = "gifsicle")]
 mod encodegifsicle;
 
+#[cfg(feature = "subtitles")]
+pub mod subtitles;
+
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(feature = "png")]
+mod framefiles;
+
+#[cfg(feature = "async")]
+mod asyncwrite;
+
+#[cfg(feature = "ipc")]
+pub mod ipc;
+
+#[cfg(feature = "video")]
+pub mod video;
+
+#[cfg(feature = "y4m")]
+mod y4m;
+
+#[cfg(feature = "apng")]
+mod encodeapng;
+
+#[cfg(feature = "webp")]
+mod encodewebp;
+#[cfg(feature = "webp")]
+mod decodewebp;
+#[cfg(feature = "qoi")]
+mod decodeqoi;
+#[cfg(any(feature = "bmp", feature = "tga"))]
+mod decodebitmap;
+#[cfg(feature = "tiff")]
+mod decodetiff;
+
+#[cfg(feature = "avif")]
+mod encodeavif;
+
+#[cfg(feature = "mp4")]
+mod encodemp4;
+
+#[cfg(any(feature = "webp", feature = "avif", feature = "mp4"))]
+mod screen;
+
+#[cfg(all(feature = "macos", target_os = "macos"))]
+mod macos;
+#[cfg(all(feature = "windows", target_os = "windows"))]
+mod windows;
+#[cfg(any(all(feature = "macos", target_os = "macos"), all(feature = "windows", target_os = "windows")))]
+mod capture;
+mod yuv;
+pub use crate::yuv::{YuvColorSpace, YuvMatrix};
+mod rawstream;
+mod autocrop;
+mod denoise;
+mod resample;
+pub use crate::rawstream::{RawPixelFormat, RawStreamCollector};
+#[cfg(any(feature = "png", feature = "exr", feature = "tiff"))]
+mod tonemap;
+#[cfg(any(feature = "png", feature = "exr", feature = "tiff"))]
+pub use crate::tonemap::{HdrCurve, ToneMapping};
+#[cfg(feature = "exr")]
+mod decodeexr;
+#[cfg(feature = "icc")]
+mod icc;
+
 use crossbeam_channel::{Receiver, Sender};
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 use std::io::prelude::*;
+#[cfg(feature = "png")]
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+
+/// Frame after decoding: pixels, pts, options, palette if it has one, and tags of frames dropped in favor of it
+/// (by `Settings::fps` or a playlist), which are reported together with its own tag.
+type DecodedImage = CatResult<(ImgVec<RGBA8>, f64, FrameOptions, Option<IndexedImage>, Vec<FrameTag>)>;
+
+/// Frame waiting in the `Collector`'s queue
+type QueuedFrame = CatResult<(InputFrame, f64, FrameOptions)>;
+
+enum InputFrame {
+    Rgba(ImgVec<RGBA8>),
+    /// Converted to RGBA in the frame differencing thread, to keep the caller's thread free.
+    /// It's given the frame's presentation timestamp, which isn't known earlier for frames with a duration.
+    Deferred(Box<dyn FnOnce(f64) -> CatResult<ImgVec<RGBA8>> + Send>),
+    /// Already quantized, see `Collector::add_frame_indexed()`
+    Indexed(IndexedImage),
+    /// For `Settings::autocrop`, resized only after its borders are cut off
+    Uncropped(UncroppedFrame),
+}
+
+/// Frame with `Settings::crop` and `transform` applied, but not resized yet,
+/// so that borders are found in full-size frames, and the cropped frames are resized to the size in `Settings`.
+struct UncroppedFrame {
+    /// Converts the frame to RGBA on the encoder's thread
+    image: Box<dyn FnOnce() -> CatResult<ImgVec<RGBA8>> + Send>,
+    /// Does the rest of `Collector::prepare_frame()` to the cropped frame and its importance map
+    finish: FinishFrame,
+}
+
+type FinishFrame = Box<dyn FnOnce(ImgVec<RGBA8>, Option<ImgVec<u8>>, f64) -> CatResult<(ImgVec<RGBA8>, Option<ImgVec<u8>>)> + Send>;
+
+impl InputFrame {
+    /// RGBA pixels for frame differencing, and the palette to use instead of quantizing
+    fn into_rgba(self, presentation_timestamp: f64) -> CatResult<(ImgVec<RGBA8>, Option<IndexedImage>)> {
+        match self {
+            Self::Rgba(image) => Ok((image, None)),
+            Self::Deferred(convert) => Ok((convert(presentation_timestamp)?, None)),
+            Self::Indexed(indexed) => Ok((indexed.to_rgba(), Some(indexed))),
+            // the autocrop stage finishes these with their importance maps
+            Self::Uncropped(frame) => Ok(((frame.finish)((frame.image)()?, None, presentation_timestamp)?.0, None)),
+        }
+    }
+}
+
+/// Frame that doesn't need quantization
+#[derive(Clone)]
+struct IndexedImage {
+    pixels: ImgVec<u8>,
+    /// Up to 256 colors, with binary alpha
+    palette: Vec<RGBA8>,
+}
+
+impl IndexedImage {
+    fn to_rgba(&self) -> ImgVec<RGBA8> {
+        ImgVec::new(self.pixels.pixels().map(|i| self.palette[i as usize]).collect(), self.pixels.width(), self.pixels.height())
+    }
+
+    /// Palette of the image's exact colors, if it has no more than `max_colors`, like terminal recordings and flat UI
+    fn with_exact_colors(image: ImgRef<'_, RGBA8>, max_colors: usize) -> Option<Self> {
+        let mut palette = Vec::new();
+        let mut indices = HashMap::new();
+        let mut pixels = Vec::with_capacity(image.width() * image.height());
+        // runs of the same color are common, and skip the lookup
+        let mut last = None;
+        for px in image.pixels() {
+            let px = if px.a < 128 { RGBA8::new(0, 0, 0, 0) } else { px.with_alpha(255) };
+            let index = match last {
+                Some((color, index)) if color == px => index,
+                _ => {
+                    let index = match indices.get(&px) {
+                        Some(&index) => index,
+                        None if palette.len() < max_colors => {
+                            palette.push(px);
+                            let index = (palette.len() - 1) as u8;
+                            indices.insert(px, index);
+                            index
+                        },
+                        None => return None,
+                    };
+                    last = Some((px, index));
+                    index
+                },
+            };
+            pixels.push(index);
+        }
+        Some(Self { pixels: ImgVec::new(pixels, image.width(), image.height()), palette })
+    }
+}
 
-type DecodedImage = CatResult<(ImgVec<RGBA8>, f64)>;
+/// User's label for an input frame, such as its file name. See `Collector::add_frame_rgba_tagged()`.
+pub type FrameTag = Arc<str>;
+
+/// Extra information about a frame, for `Collector::add_frame_rgba_with_options()`
+#[derive(Debug, Clone, Default)]
+pub struct FrameOptions {
+    /// Label of the frame, see `Collector::add_frame_rgba_tagged()`
+    pub tag: Option<FrameTag>,
+    /// 1-100. Quality of this frame's colors, instead of `Settings::quality`,
+    /// e.g. higher for title cards or the last frame, or lower for fast motion.
+    /// If the frame is merged with similar frames, the highest quality of them is used.
+    pub quality: Option<u8>,
+    /// Areas that matter most, like a face, or code being typed. They get more of the palette, and the rest of the frame gets less.
+    /// They're in pixels of the GIF, i.e. after resizing.
+    pub regions: Vec<Rect>,
+    /// How much each pixel matters (0-255), if you already know, e.g. from layers of text.
+    /// It's the same size as the frame, and is multiplied with the importance gifski computes from changes between frames.
+    pub importance_map: Option<ImgVec<u8>>,
+    /// How long the frame is shown. If set, the presentation timestamp is ignored, and the frame starts when the previous frame ends.
+    /// See `Collector::add_frame_with_duration()`.
+    pub duration: Option<Duration>,
+}
 
 /// Number of repetitions
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Repeat {
+    /// Played once, and then repeated this many times. `Finite(0)` is the same as `None`.
     Finite(u16),
+    /// Loops forever
     Infinite,
+    /// Played once. The file has no looping extension at all.
+    None,
+}
+
+/// Error diffusion used by `Settings::dithering`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Dithering {
+    pub kernel: DitherKernel,
+    /// Alternate the direction of every row, which avoids diagonal "worm" artifacts, noticeable in slow pans
+    pub serpentine: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DitherKernel {
+    /// The classic
+    FloydSteinberg,
+    /// Cheaper, with a smaller spread of the error
+    SierraLite,
+    /// Spreads only 3/4 of the error, so it bleeds less, but loses some detail in shadows and highlights
+    Atkinson,
+}
+
+/// Advanced: how differences between frames affect which pixels get the best colors
+///
+/// Differences are measured as a weighted square of RGB differences (up to 390150).
+/// Text and UI may need to be more sensitive to small changes than video.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ImportanceTuning {
+    /// 0-255. How much less important are pixels that the next frame changes completely.
+    pub overwritten_penalty: u8,
+    /// Changes smaller than this are ignored at quality 100. Lower quality raises it.
+    pub min_diff: u32,
+    /// Differences from the previous frame are divided by this, and squared.
+    /// Smaller values make small changes more important.
+    pub diff_scale: u32,
+    /// Pixels whose scaled and squared difference is above this get full importance.
+    pub saturation: u32,
+    /// 1-255. Importance of pixels outside of `FrameOptions::regions` is divided by this.
+    pub outside_regions_divisor: u8,
+}
+
+impl Default for ImportanceTuning {
+    fn default() -> Self {
+        Self {
+            overwritten_penalty: 170,
+            min_diff: 80,
+            diff_scale: 32,
+            saturation: 256,
+            outside_regions_divisor: 4,
+        }
+    }
+}
+
+/// When the output is flushed, for viewing a file that is still being written
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlushPolicy {
+    /// Only when the whole file has been written
+    OnFinish,
+    /// After every frame
+    EveryFrame,
+    /// After a frame, if at least this many bytes have been written since the last flush
+    EveryBytes(u64),
+}
+
+/// Handling of frames that don't match size of the first frame
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WrongSizePolicy {
+    /// Abort encoding with `Error::WrongSize`
+    Fail,
+    /// Stretch the frame to the size of the first frame
+    Resize,
+}
+
+/// How frames are resized when both `Settings::width` and `Settings::height` are set
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResizeFit {
+    /// Resize to exactly the width and height (but not larger than the frame), ignoring the aspect ratio
+    Exact,
+    /// Keep the aspect ratio, and fit inside the width and height
+    Contain,
+    /// Keep the aspect ratio, and cover the whole width and height. One side may be larger than asked for.
+    Cover,
+    /// Same as `Cover`, and then cut to exactly the width and height, keeping the center. Good for thumbnails.
+    Crop,
+    /// Same as `Contain`, and then centered on bars of this color to make it exactly the width and height.
+    /// Colors with alpha < 128 make transparent bars.
+    Letterbox(RGBA8),
+}
+
+/// Filter used for resizing frames
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScalingFilter {
+    /// Nearest neighbor. Keeps pixels crisp, but is uneven when not scaling by a whole number.
+    Nearest,
+    /// Bilinear
+    Triangle,
+    /// Bicubic
+    CatmullRom,
+    /// Bicubic, softer than Catmull-Rom, with less ringing
+    Mitchell,
+    /// Sharpest, but adds ringing around hard edges, like in UI screenshots
+    Lanczos3,
+}
+
+impl ScalingFilter {
+    fn resize_type(self) -> resize::Type {
+        match self {
+            Self::Nearest => resize::Type::Point,
+            Self::Triangle => resize::Type::Triangle,
+            Self::CatmullRom => resize::Type::Catrom,
+            Self::Mitchell => resize::Type::Mitchell,
+            Self::Lanczos3 => resize::Type::Lanczos3,
+        }
+    }
+}
+
+/// Rotation or mirroring of frames. Rotations are clockwise.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Transform {
+    None,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror left to right
+    FlipHorizontal,
+    /// Mirror top to bottom
+    FlipVertical,
+}
+
+impl Transform {
+    fn swaps_dimensions(self) -> bool {
+        matches!(self, Self::Rotate90 | Self::Rotate270)
+    }
+}
+
+/// Area of a frame, in pixels
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Rect {
+    pub left: u32,
+    pub top: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Color of the background to cut out, for `Settings::chroma_key`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ChromaKey {
+    pub color: RGB8,
+    /// Pixels this close to the color (distance in RGB, 0-441) become transparent too,
+    /// since a green screen is never exactly one color.
+    pub tolerance: u16,
+}
+
+impl ChromaKey {
+    fn matches(&self, px: RGBA8) -> bool {
+        let diff = |a: u8, b: u8| u32::from(a.abs_diff(b)).pow(2);
+        let distance = diff(px.r, self.color.r) + diff(px.g, self.color.g) + diff(px.b, self.color.b);
+        distance <= u32::from(self.tolerance).pow(2)
+    }
+}
+
+/// Bounds for `Settings::adaptive_frame_rate`, in seconds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveFrameRate {
+    /// Frames that would be displayed for a shorter time are dropped. This caps the frame rate during motion.
+    pub min_delay: f64,
+    /// Runs of still frames are merged into one frame displayed for at most this long.
+    pub max_delay: f64,
 }
 
 /// Encoding settings for the `new()` function
+///
+/// New settings are added in minor versions, so start from `Settings::default()` and change the fields you need.
 #[derive(Copy, Clone)]
+#[non_exhaustive]
 pub struct Settings {
     /// Resize to max this width if non-0.
     pub width: Option<u32>,
-    /// Resize to max this height if width is non-0. Note that aspect ratio is not preserved.
+    /// Resize to max this height. If the width is set too, see `fit`.
     pub height: Option<u32>,
+    /// How to resize when both `width` and `height` are set. By default, aspect ratio is not preserved.
+    pub fit: ResizeFit,
+    /// Use only this area of every frame, e.g. a window in a full-screen capture. The frames are cut before they're resized.
+    /// The area can't be completely outside the frames, but it's trimmed to the frame size if it's partially outside.
+    pub crop: Option<Rect>,
+    /// Rotate or mirror every frame, e.g. for videos recorded on a phone held upright.
+    /// This is done first, so `crop`, `width` and `height` are for the rotated frames.
+    pub transform: Transform,
+    /// Filter used when frames are resized
+    pub scaling_filter: ScalingFilter,
+    /// 0-2. Strength of sharpening after frames are scaled down, e.g. 0.5 for text in screencasts that comes out blurry.
+    /// 0 disables it.
+    pub sharpen: f32,
+    /// If above 1, the width and height of the GIF are rounded down to a multiple of this, e.g. 2 for video encoders that need even sizes.
+    /// Frames are cut to that size at the edges, rather than stretched. A width or height smaller than this is left as it is.
+    pub size_multiple: u8,
+    /// GIF pixels are either transparent or opaque. By default semi-transparent pixels become a dithered pattern of both.
+    /// With a threshold, pixels with alpha below it are transparent and the rest are opaque, which keeps UI edges crisp.
+    pub alpha_threshold: Option<u8>,
+    /// Color of the background the GIF will be shown on. Semi-transparent pixels are blended with it and made opaque,
+    /// so that anti-aliased edges don't get a jagged or dithered fringe. Fully transparent pixels stay transparent.
+    pub matte: Option<RGBA8>,
+    /// 1-7. Keep only this many bits of each color channel, e.g. 5 for a retro look.
+    /// Noisy videos become much smaller, because frames have fewer colors, and fewer pixels change.
+    pub posterize: Option<u8>,
+    /// Make pixels of this color transparent, like a green screen. It's done before resizing, so the edges get smooth.
+    pub chroma_key: Option<ChromaKey>,
+    /// Make pixels of exactly this color transparent, like magenta in old sprite sheets.
+    pub transparent_color: Option<RGB8>,
+    /// Cut off borders of one color that are the same in every frame, like black bars, or a plain background around a window.
+    /// Borders are looked for in the first 30 frames, which are kept in memory until then, and later frames are cut the same way.
+    /// Frames are cut before they're resized to `width` and `height`, and then all of it is done on the encoder's thread.
+    pub autocrop: bool,
+    /// Changes between frames up to this much (0-255 in each channel) are seen as noise, like from a camera sensor, and averaged out.
+    /// It saves palette colors, and pixels that don't change any more don't have to be stored again. 4-16 works well.
+    pub denoise: Option<u8>,
+    /// Resize in linear light instead of sRGB, which keeps fine detail from getting darker when scaling down photos.
+    /// It's slower.
+    pub linear_light_resize: bool,
+    /// For crisp, low-res graphics with few colors: resize with `ScalingFilter::Nearest`, don't dither,
+    /// never merge similar colors to make the palette smaller, and don't use lossy compression.
+    pub pixel_art: bool,
     /// 1-100, but useful range is 50-100. Recommended to set to 100.
     pub quality: u8,
+    /// Quantize the first frame with the same `quality` as the rest. By default it gets the best quality,
+    /// since everything else is drawn over it, but then it may look different from the next frames, and is larger.
+    pub uniform_quality: bool,
+    /// 2-256. Fewer colors per frame make smaller files, or a retro look.
+    pub max_colors: u16,
     /// Lower quality, but faster encode.
     pub fast: bool,
     /// Sets the looping method for the image sequence.
     pub repeat: Repeat,
+    /// What to do with frames that have a different size than the first frame.
+    pub wrong_size: WrongSizePolicy,
+    /// For live capture. If encoding can't keep up, `add_frame_*` drops the oldest waiting frames
+    /// instead of blocking. The frame before a dropped one is displayed for longer.
+    ///
+    /// Frames must be added in order of their frame index, and adding a frame with a lower index than an earlier one is an error.
+    pub realtime: bool,
+    /// Keep the full frame rate only while something moves. Frames that are (almost) unchanged
+    /// are merged into the frame before them, which is then displayed for longer.
+    pub adaptive_frame_rate: Option<AdaptiveFrameRate>,
+    /// Move frames onto a grid of this many frames per second, so that captures with uneven timing play evenly.
+    /// A frame that is replaced before the next tick of the grid is dropped, and a frame that stays for several ticks is shown for longer.
+    pub fps: Option<f32>,
+    /// Frames that come sooner than this many frames per second after the current frame are merged into it.
+    /// GIF players don't show more than 50 frames per second anyway, so there's no point in encoding 120fps captures in full.
+    pub max_fps: Option<f32>,
+    /// Frames merged into the frame before them, such as frames dropped to lower the frame rate, are blended into it,
+    /// instead of being discarded. Motion looks smoother at low frame rates.
+    pub motion_blur: bool,
+    /// Percentage of pixels (0-100) that may visibly change between frames for the frame
+    /// to still be merged into the previous one, extending its delay. 0 merges only identical frames.
+    pub merge_threshold: f32,
+    /// 0-255. Frames that differ from the previous one by less than this on average (like a difference of each color channel)
+    /// are merged into it, even if the small differences are everywhere, like in frames of lossy video. 1-3 works well.
+    pub duplicate_threshold: f32,
+    /// Write every frame with its own delay, even if it's identical to the previous frame, for a 1:1 mapping of frames.
+    /// Frames can still be merged by other settings, like `merge_threshold` or `max_fps`.
+    pub keep_duplicates: bool,
+    /// 0-100. Pixels whose color is this close to what's already on screen are made transparent,
+    /// which creates long runs of the same index that compress well. 0 leaves it to the remapping.
+    pub transparency_bias: u8,
+    /// In areas that didn't change, keep the previous frame's dithering instead of dithering again.
+    /// This stops noise in static gradients from flickering, and makes frames smaller.
+    pub stable_dithering: bool,
+    /// If non-0, this many colors (up to 240, 128-192 works best) are picked from the first frames
+    /// and included in every frame's palette. The rest of each palette is still picked for the frame.
+    /// Colors that don't change between frames flicker less, and stay transparent more often.
+    pub shared_palette_colors: u8,
+    /// Pick one palette from the colors of all frames, and use it for every frame, instead of `shared_palette_colors`.
+    /// It compresses better and never flickers, which suits screencasts, but videos lose colors.
+    /// All frames are kept in memory until the last one is added.
+    pub global_palette: bool,
+    /// Pick a new palette only for every n-th frame, and map the frames in between to the last palette.
+    /// It's much faster for long screen recordings, but colors that appear in between may be off until the next palette.
+    /// 0 or 1 picks a palette for every frame.
+    pub palette_interval: u32,
+    /// Keep the previous palette for frames where only a few pixels change, and the palette has colors close to them, like when typing.
+    /// It's faster, but the changed pixels may be a bit off.
+    pub reuse_palette_for_small_changes: bool,
+    /// 1-100. How small changes between frames are still worth updating, and which frames are similar enough to merge.
+    /// `quality` then only controls colors of the pixels. If `None`, it's the same as `quality`.
+    pub motion_quality: Option<u8>,
+    /// Much slower encoding for a slightly better quality and smaller file:
+    /// the slowest palette generation, trimming unchanged columns of frames, and dropping unused palette entries.
+    pub extra_effort: bool,
+    /// How often to flush the output. It's only flushed after complete frames,
+    /// so a file cut off at a flush can be played up to that point.
+    pub flush: FlushPolicy,
+    /// Use this error diffusion instead of libimagequant's built-in dithering. It's slower.
+    pub dithering: Option<Dithering>,
+    /// 0-1. How much to dither, e.g. 0 for flat UI captures, even at high `quality`. If `None`, it depends on `quality`.
+    pub dithering_strength: Option<f32>,
+    /// Gamma of the input pixels, given to libimagequant. If `None`, they're sRGB (about 0.45).
+    /// Use 1 for linear-light pixels, e.g. straight out of a renderer, otherwise the colors shift.
+    pub quantization_gamma: Option<f64>,
+    pub importance: ImportanceTuning,
+    /// Run the encoder's threads with lower priority (`nice` on Linux, utility QoS on macOS),
+    /// so that a long encode doesn't slow down the rest of the app.
+    /// The thread calling `Writer::write()` is not changed.
+    pub low_priority: bool,
+    /// Give up with `Error::InputStalled` if no frame is added for this long, and the `Collector` hasn't been dropped.
+    /// This stops the `Writer` from waiting forever if whatever was adding frames has crashed.
+    pub input_timeout: Option<Duration>,
+    /// For players that mishandle transparency: frames after the first never use transparent pixels to show the previous frame.
+    /// Each frame is a rectangle of real pixels covering what changed. Files are larger.
+    /// Transparency of the input frames is still kept.
+    pub opaque_deltas: bool,
+    /// Put the transparent color at index 0 of every frame's palette, even in frames without transparency.
+    /// Some old decoders and sprite tools assume it's there.
+    pub transparent_index_first: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            fit: ResizeFit::Exact,
+            crop: None,
+            transform: Transform::None,
+            scaling_filter: ScalingFilter::Lanczos3,
+            sharpen: 0.,
+            pixel_art: false,
+            linear_light_resize: false,
+            autocrop: false,
+            denoise: None,
+            alpha_threshold: None,
+            matte: None,
+            chroma_key: None,
+            transparent_color: None,
+            posterize: None,
+            size_multiple: 0,
+            quality: 100,
+            uniform_quality: false,
+            max_colors: 256,
+            fast: false,
+            repeat: Repeat::Infinite,
+            wrong_size: WrongSizePolicy::Fail,
+            realtime: false,
+            adaptive_frame_rate: None,
+            fps: None,
+            max_fps: None,
+            motion_blur: false,
+            merge_threshold: 0.,
+            duplicate_threshold: 0.,
+            keep_duplicates: false,
+            transparency_bias: 0,
+            stable_dithering: false,
+            shared_palette_colors: 0,
+            global_palette: false,
+            palette_interval: 0,
+            reuse_palette_for_small_changes: false,
+            motion_quality: None,
+            extra_effort: false,
+            flush: FlushPolicy::OnFinish,
+            dithering: None,
+            dithering_strength: None,
+            quantization_gamma: None,
+            importance: ImportanceTuning::default(),
+            low_priority: false,
+            input_timeout: None,
+            opaque_deltas: false,
+            transparent_index_first: false,
+        }
+    }
+}
+
+/// libimagequant's quality for gifski's `quality`
+fn color_quality(quality: u8) -> u8 {
+    (u16::from(quality.min(100)) * 4 / 3).min(100) as u8
 }
 
 impl Settings {
     /// quality is used in other places, like gifsicle or frame differences,
     /// and it's better to lower quality there before ruining quantization
     pub(crate) fn color_quality(&self) -> u8 {
-        (self.quality as u16 * 4 / 3).min(100) as u8
+        color_quality(self.quality)
+    }
+
+    /// `max_colors` in the range libimagequant accepts
+    pub(crate) fn palette_size(&self) -> u16 {
+        self.max_colors.clamp(2, 256)
+    }
+
+    pub(crate) fn effective_scaling_filter(&self) -> ScalingFilter {
+        if self.pixel_art { ScalingFilter::Nearest } else { self.scaling_filter }
+    }
+
+    /// `dithering`, unless it's disabled by `pixel_art`
+    pub(crate) fn error_diffusion(&self) -> Option<Dithering> {
+        self.dithering.filter(|_| !self.pixel_art)
+    }
+
+    /// `quantization_gamma` in the range libimagequant accepts, 0 being its sRGB default
+    pub(crate) fn gamma(&self) -> f64 {
+        match self.quantization_gamma {
+            Some(gamma) if gamma.is_finite() && gamma > 0. => gamma.min(1.),
+            _ => 0.,
+        }
+    }
+
+    pub(crate) fn dithering_level(&self) -> f32 {
+        if self.pixel_art {
+            return 0.;
+        }
+        match self.dithering_strength {
+            Some(strength) if strength.is_finite() => strength.clamp(0., 1.),
+            _ => self.quality as f32 / 150.0,
+        }
+    }
+
+    /// Pixels that changed less than this (as in `colordiff`) aren't worth updating
+    pub(crate) fn min_pixel_diff(&self) -> u32 {
+        let motion_quality = self.motion_quality.unwrap_or(self.quality);
+        let q = 100 - (u32::from(motion_quality) * 4 / 3).min(100);
+        self.importance.min_diff + q * q
     }
 
     /// add_frame is going to resize the images to this size.
     pub fn dimensions_for_image(&self, width: usize, height: usize) -> (usize, usize) {
-        dimensions_for_image((width, height), (self.width, self.height))
+        Geometry::new(self).output_size((width, height))
     }
 
+    #[cfg(feature = "gifsicle")]
     pub(crate) fn gifsicle_loss(&self) -> u32 {
         (100./6. - self.quality as f32 / 6.).powf(1.75).ceil() as u32
     }
@@ -86,19 +676,60 @@ impl Settings {
 ///
 /// Note that writing will finish only when the collector is dropped.
 /// Collect frames on another thread, or call `drop(collector)` before calling `writer.write()`!
+///
+/// Frames can be added from multiple threads at once (the collector is `Sync`),
+/// e.g. from a rayon `par_iter()`, since frames are ordered by their index anyway.
+/// The exception is `Settings::realtime` mode, which doesn't wait for missing frames, so frames must be added
+/// in order of their index, e.g. from one thread. Frames added after a frame with a higher index are rejected.
 pub struct Collector {
-    width: Option<u32>,
-    height: Option<u32>,
-    queue: OrdQueue<DecodedImage>,
+    geometry: Geometry,
+    alpha: BinaryAlpha,
+    posterize: Option<u8>,
+    keys: ColorKeys,
+    queue: OrdQueue<QueuedFrame>,
+    counters: Arc<PipelineCounters>,
+    #[cfg(feature = "subtitles")]
+    subtitles: Option<Arc<subtitles::Subtitles>>,
+    #[cfg(any(feature = "png", feature = "exr", feature = "tiff"))]
+    tone_mapping: ToneMapping,
+    #[cfg(feature = "icc")]
+    color_conversion: Option<icc::ColorConversion>,
+}
+
+/// Statistics about a finished encode, returned by `Writer::write()`
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct EncodeSummary {
+    /// Number of frames in the GIF. Identical and out-of-order frames are skipped, so it may be less than the number of frames added.
+    pub frames_written: usize,
+    /// Wall-clock time of the whole encode
+    pub elapsed: Duration,
+    /// Time spent on processing frames in each stage of the pipeline,
+    /// not counting time the stages spent waiting for each other.
+    pub stages: StageTimings,
+    /// For every frame written, tags of the input frames it shows (including skipped and merged frames).
+    /// Empty for untagged frames.
+    pub frame_tags: Vec<Vec<FrameTag>>,
 }
 
 /// Perform GIF writing
 pub struct Writer {
     /// Input frame decoder results
-    queue_iter: Option<OrdQueueIter<DecodedImage>>,
+    queue_iter: Option<OrdQueueIter<QueuedFrame>>,
     settings: Settings,
+    playlist: Option<Vec<(usize, f64)>>,
+    frame_filter: Option<FrameFilter>,
+    thread_spawner: Option<ThreadSpawner>,
+    counters: Arc<PipelineCounters>,
+    /// From `set_fixed_colors()`
+    fixed_colors: Vec<RGBA8>,
+    /// From `set_palette_family()`
+    palette_family: Vec<RGBA8>,
 }
 
+type FrameFilter = Box<dyn FnMut(ImgRef<'_, RGBA8>, f64) -> bool + Send>;
+type ThreadSpawner = Box<dyn Fn(&str, Box<dyn FnOnce() + Send>) -> std::io::Result<()> + Send>;
+
 struct GIFFrame {
     left: u16,
     top: u16,
@@ -110,11 +741,26 @@ struct GIFFrame {
     transparent_index: Option<u8>,
 }
 
+impl GIFFrame {
+    /// Colors of the pixels, for formats without per-frame palettes. The transparent index is (0,0,0,0).
+    #[cfg(any(feature = "apng", feature = "webp", feature = "avif", feature = "mp4"))]
+    fn to_rgba(&self) -> ImgVec<RGBA8> {
+        let pixels = self.image.pixels().map(|px| match self.pal.get(px as usize) {
+            Some(&color) if Some(px) != self.transparent_index => color,
+            _ => RGBA8::default(),
+        }).collect();
+        ImgVec::new(pixels, self.image.width(), self.image.height())
+    }
+}
+
 trait Encoder {
+    /// Writes a complete frame, so that the output is valid up to that frame
     fn write_frame(&mut self, frame: GIFFrame, delay: u16, settings: &Settings) -> CatResult<()>;
-    fn finish(&mut self) -> CatResult<()> {
-        Ok(())
-    }
+    /// Size of the output so far
+    fn bytes_written(&self) -> u64;
+    fn flush(&mut self) -> CatResult<()>;
+    /// Writes the end of the file and flushes
+    fn finish(&mut self) -> CatResult<()>;
 }
 
 /// Frame before quantization
@@ -125,7 +771,14 @@ struct DiffMessage {
     end_pts: f64,
     dispose: gif::DisposalMethod,
     image: ImgVec<RGBA8>,
+    /// Frame's own palette, if it doesn't need quantization
+    indexed: Option<IndexedImage>,
     importance_map: Vec<u8>,
+    /// `FrameOptions::quality`
+    quality: Option<u8>,
+    /// `FrameOptions::regions` of this frame and frames merged into it
+    regions: Vec<Rect>,
+    tags: Vec<FrameTag>,
 }
 
 /// Frame post quantization, before remap
@@ -134,9 +787,36 @@ struct RemapMessage {
     ordinal_frame_number: usize,
     end_pts: f64,
     dispose: gif::DisposalMethod,
-    liq: Attributes,
-    remap: QuantizationResult,
-    liq_image: Image<'static>,
+    quantized: Quantized,
+    /// Pixels that can keep what's on screen, for `stable_dithering`
+    unchanged: Option<Vec<bool>>,
+    /// Source pixels, if the remapping is done by the `dither` module, or the frame may show the previous one through
+    image: Option<ImgVec<RGBA8>>,
+    tags: Vec<FrameTag>,
+}
+
+enum Quantized {
+    Liq {
+        remap: QuantizationResult,
+        liq_image: Image<'static>,
+    },
+    /// Frame added with its own palette
+    Indexed(IndexedImage),
+    /// Pixels to map to a palette of an earlier frame, see `Settings::palette_interval`
+    Palette {
+        palette: Vec<RGBA8>,
+        image: ImgVec<RGBA8>,
+    },
+}
+
+impl Quantized {
+    fn size(&self) -> (usize, usize) {
+        match self {
+            Self::Liq { liq_image, .. } => (liq_image.width(), liq_image.height()),
+            Self::Indexed(indexed) => (indexed.pixels.width(), indexed.pixels.height()),
+            Self::Palette { image, .. } => (image.width(), image.height()),
+        }
+    }
 }
 
 /// Frame post quantization and remap
@@ -145,6 +825,7 @@ struct FrameMessage {
     ordinal_frame_number: usize,
     end_pts: f64,
     frame: GIFFrame,
+    tags: Vec<FrameTag>,
 }
 
 /// Start new encoding
@@ -155,21 +836,100 @@ struct FrameMessage {
 /// You feed input frames to the `Collector`, and ask the `Writer` to
 /// start writing the GIF.
 pub fn new(settings: Settings) -> CatResult<(Collector, Writer)> {
-    let (queue, queue_iter) = ordqueue::new(4);
+    let (queue, mut queue_iter) = if settings.realtime {
+        ordqueue::new_lossy(4)
+    } else {
+        ordqueue::new(4)
+    };
+    if let Some(timeout) = settings.input_timeout {
+        queue_iter.set_idle_timeout(timeout, |last_frame, waited| Err(Error::InputStalled { last_frame, waited }));
+    }
+    let counters = Arc::new(PipelineCounters::default());
+    counters.decode.set_capacity(4);
 
     Ok((
         Collector {
             queue,
-            width: settings.width,
-            height: settings.height,
+            counters: counters.clone(),
+            geometry: Geometry::new(&settings),
+            alpha: BinaryAlpha::new(&settings),
+            posterize: settings.posterize,
+            keys: ColorKeys::new(&settings),
+            #[cfg(feature = "subtitles")]
+            subtitles: None,
+            #[cfg(any(feature = "png", feature = "exr", feature = "tiff"))]
+            tone_mapping: ToneMapping::default(),
+            #[cfg(feature = "icc")]
+            color_conversion: None,
         },
         Writer {
             queue_iter: Some(queue_iter),
             settings,
+            playlist: None,
+            frame_filter: None,
+            fixed_colors: Vec::new(),
+            palette_family: Vec::new(),
+            thread_spawner: None,
+            counters,
         },
     ))
 }
 
+/// Encode all frames in one call
+///
+/// This is a shortcut that creates the `Collector` and the `Writer`,
+/// and runs them on separate threads for you.
+///
+/// `frames` are images paired with their presentation timestamps (in seconds).
+/// This function returns when all frames have been written.
+pub fn encode<W: Write + Send>(settings: Settings, frames: impl IntoIterator<Item = (ImgVec<RGBA8>, f64)>, writer: W) -> CatResult<EncodeSummary> {
+    let (collector, gif_writer) = new(settings)?;
+    thread::scope(|s| {
+        let write_thread = s.spawn(move || gif_writer.write(writer, &mut NoProgress {}));
+        let collected = frames.into_iter().enumerate()
+            .try_for_each(|(i, (image, pts))| collector.add_frame_rgba(i, image, pts));
+        drop(collector);
+        // if writing failed, the collector fails too, but writer's error is the interesting one
+        let summary = write_thread.join().map_err(|_| Error::ThreadSend)??;
+        collected?;
+        Ok(summary)
+    })
+}
+
+/// Which output of `encode_progressive()` is ready
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EncodeStage {
+    /// Quickly made, half-size and lower quality
+    Preview,
+    /// Made with the given settings
+    Final,
+}
+
+/// Encode a quick preview first, and then the final GIF from the same frames
+///
+/// `on_output` gets the whole GIF file of each stage as soon as it's ready, so that there's something to show
+/// long before the final encode finishes. Returns statistics about the final encode.
+pub fn encode_progressive(settings: Settings, frames: &[(ImgVec<RGBA8>, f64)], mut on_output: impl FnMut(EncodeStage, &[u8])) -> CatResult<EncodeSummary> {
+    let (first_frame, _) = frames.first().ok_or(Error::NoFrames)?;
+    let (width, height) = settings.dimensions_for_image(first_frame.width(), first_frame.height());
+    let preview_settings = Settings {
+        width: Some((width / 2).max(1) as u32),
+        height: Some((height / 2).max(1) as u32),
+        quality: settings.quality.min(50),
+        fast: true,
+        extra_effort: false,
+        ..settings
+    };
+    let mut out = Vec::new();
+    encode(preview_settings, frames.iter().cloned(), &mut out)?;
+    on_output(EncodeStage::Preview, &out);
+
+    out.clear();
+    let summary = encode(settings, frames.iter().cloned(), &mut out)?;
+    on_output(EncodeStage::Final, &out);
+    Ok(summary)
+}
+
 impl Collector {
     /// Frame index starts at 0.
     ///
@@ -178,8 +938,138 @@ impl Collector {
     /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
     ///
     /// If the first frame doesn't start at pts=0, the delay will be used for the last frame.
-    pub fn add_frame_rgba(&mut self, frame_index: usize, image: ImgVec<RGBA8>, presentation_timestamp: f64) -> CatResult<()> {
-        self.queue.push(frame_index, Ok((Self::resized_binary_alpha(image, self.width, self.height)?, presentation_timestamp)))
+    pub fn add_frame_rgba(&self, frame_index: usize, image: ImgVec<RGBA8>, presentation_timestamp: f64) -> CatResult<()> {
+        let frame = self.prepared_frame(image, presentation_timestamp)?;
+        self.push(frame_index, frame, presentation_timestamp, FrameOptions::default())
+    }
+
+    /// Same as `add_frame_rgba()`, but labels the frame with a tag, such as its file name.
+    ///
+    /// Tags are passed to `ProgressReporter::written_frame()` and listed in `EncodeSummary::frame_tags`,
+    /// so that frames of the GIF can be matched to the input frames, even if some were skipped or merged.
+    pub fn add_frame_rgba_tagged(&self, frame_index: usize, image: ImgVec<RGBA8>, presentation_timestamp: f64, tag: impl Into<FrameTag>) -> CatResult<()> {
+        self.add_frame_rgba_with_options(frame_index, image, presentation_timestamp, FrameOptions { tag: Some(tag.into()), ..FrameOptions::default() })
+    }
+
+    /// Same as `add_frame_rgba()`, but with the time the frame is shown for, instead of a presentation timestamp,
+    /// e.g. delays of frames from an existing GIF or sprite sheet.
+    ///
+    /// Frames still can be added in any order. Use durations for all frames, since timestamps of other frames don't say when they end.
+    /// Subtitles are timed by when the frame starts, i.e. after the durations of all frames before it.
+    pub fn add_frame_with_duration(&self, frame_index: usize, image: ImgVec<RGBA8>, duration: Duration) -> CatResult<()> {
+        self.add_frame_rgba_with_options(frame_index, image, 0., FrameOptions { duration: Some(duration), ..FrameOptions::default() })
+    }
+
+    /// Same as `add_frame_rgba()`, but with pixels that matter more or less, see `FrameOptions::importance_map`.
+    pub fn add_frame_rgba_with_importance(&self, frame_index: usize, image: ImgVec<RGBA8>, importance_map: ImgVec<u8>, presentation_timestamp: f64) -> CatResult<()> {
+        self.add_frame_rgba_with_options(frame_index, image, presentation_timestamp, FrameOptions { importance_map: Some(importance_map), ..FrameOptions::default() })
+    }
+
+    /// Same as `add_frame_rgba()`, but with a tag or quality just for this frame. See `FrameOptions`.
+    pub fn add_frame_rgba_with_options(&self, frame_index: usize, image: ImgVec<RGBA8>, presentation_timestamp: f64, mut options: FrameOptions) -> CatResult<()> {
+        if let Some(map) = options.importance_map.take() {
+            if map.width() != image.width() || map.height() != image.height() {
+                return Err(Error::InvalidInput(format!("Importance map is {}×{}, but the frame is {}×{}", map.width(), map.height(), image.width(), image.height())));
+            }
+            options.importance_map = Some(if self.geometry.autocrop {
+                cropped(transformed(map, self.geometry.transform), self.geometry.crop)?
+            } else {
+                Self::resized_importance_map(map, self.geometry)?
+            });
+        }
+        if options.duration.is_some() && self.has_subtitles() {
+            // the frame's timestamp is known only after all frames before it have arrived
+            let frame = self.deferred_frame(move || image);
+            return self.push(frame_index, frame, presentation_timestamp, options);
+        }
+        let frame = self.prepared_frame(image, presentation_timestamp)?;
+        self.push(frame_index, frame, presentation_timestamp, options)
+    }
+
+    /// Same as `add_frame_rgba()`, but for frames without transparency.
+    ///
+    /// It's faster, because the frame is resized without the alpha channel,
+    /// and there's no transparency to make binary.
+    pub fn add_frame_rgb(&self, frame_index: usize, image: ImgVec<RGB8>, presentation_timestamp: f64) -> CatResult<()> {
+        if self.geometry.autocrop {
+            let frame = self.uncropped_frame(move || {
+                let (width, height) = (image.width(), image.height());
+                ImgVec::new(image.pixels().map(|px| px.with_alpha(255)).collect(), width, height)
+            });
+            return self.push(frame_index, frame, presentation_timestamp, FrameOptions::default());
+        }
+        let image = self.prepare_frame_rgb(image, presentation_timestamp)?;
+        self.push(frame_index, InputFrame::Rgba(image), presentation_timestamp, FrameOptions::default())
+    }
+
+    /// Same as `add_frame_rgba()`, but takes any image from the `image` crate.
+    ///
+    /// Grayscale and 16-bit images are converted to 8-bit RGBA. Images without an alpha channel take the faster path of `add_frame_rgb()`.
+    #[cfg(feature = "image")]
+    pub fn add_frame_image(&self, frame_index: usize, image: image::DynamicImage, presentation_timestamp: f64) -> CatResult<()> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        if image.color().has_alpha() {
+            let pixels = image.into_rgba8().into_raw();
+            self.add_frame_rgba(frame_index, ImgVec::new(pixels.as_rgba().to_vec(), width, height), presentation_timestamp)
+        } else {
+            let pixels = image.into_rgb8().into_raw();
+            self.add_frame_rgb(frame_index, ImgVec::new(pixels.as_rgb().to_vec(), width, height), presentation_timestamp)
+        }
+    }
+
+    /// Add a frame that already has a palette (up to 256 colors), such as pixel art or a terminal recording.
+    ///
+    /// The frame isn't quantized, so its colors are kept exactly, and encoding is much faster.
+    /// Semi-transparent palette colors are blended with `Settings::matte`, or made transparent or opaque using `Settings::alpha_threshold`,
+    /// and `Settings::posterize` applies to the palette.
+    ///
+    /// If the frame needs to be resized, has subtitles burned in or a `chroma_key`, has more colors than `Settings::max_colors`,
+    /// or has semi-transparent colors without an `alpha_threshold` or `matte` (which are dithered), it's converted to RGBA and quantized as usual.
+    pub fn add_frame_indexed(&self, frame_index: usize, image: ImgVec<u8>, palette: &[RGBA8], presentation_timestamp: f64) -> CatResult<()> {
+        if palette.is_empty() || palette.len() > 256 {
+            return Err(Error::InvalidInput(format!("Palette must have 1 to 256 colors, not {}", palette.len())));
+        }
+        if let Some(max) = image.pixels().max().filter(|&max| usize::from(max) >= palette.len()) {
+            return Err(Error::InvalidInput(format!("Pixel uses color {}, but the palette has only {} colors", max, palette.len())));
+        }
+        let pixels = cropped(transformed(image, self.geometry.transform), self.geometry.crop)?;
+
+        let (width, height) = (pixels.width(), pixels.height());
+        if !self.has_subtitles() && self.keys.chroma_key.is_none() && !self.geometry.autocrop && self.geometry.output_size((width, height)) == (width, height) {
+            #[cfg(feature = "icc")]
+            let converted_palette = self.color_conversion.as_ref().map(|conversion| conversion.convert(palette)).transpose()?;
+            #[cfg(feature = "icc")]
+            let palette = converted_palette.as_deref().unwrap_or(palette);
+            if let Some(palette) = self.prepare_palette(palette) {
+                return self.push(frame_index, InputFrame::Indexed(IndexedImage { pixels, palette }), presentation_timestamp, FrameOptions::default());
+            }
+        }
+        // colors are converted again in add_frame_rgba, so it gets the original palette
+        let indexed = IndexedImage { pixels, palette: palette.to_vec() };
+        self.add_frame_rgba(frame_index, indexed.to_rgba(), presentation_timestamp)
+    }
+
+    /// Same as `prepare_frame()` for the colors of a palette, which can be done only if the colors don't need dithering.
+    fn prepare_palette(&self, palette: &[RGBA8]) -> Option<Vec<RGBA8>> {
+        if self.alpha.threshold.is_none() && self.alpha.matte.is_none() && palette.iter().any(|c| c.a > 0 && c.a < 255) {
+            return None;
+        }
+        let mut palette = Self::keyed(ImgVec::new(palette.to_vec(), palette.len(), 1), self.keys);
+        Self::make_alpha_binary(&mut palette, self.alpha);
+        Self::posterize(&mut palette, self.posterize);
+        Some(palette.into_buf().into_iter().map(|c| if c.a == 0 { RGBA8::new(0, 0, 0, 0) } else { c }).collect())
+    }
+
+    /// Same as `add_frame_rgba()`, but takes pixels in BGRA order, the native format of most screen capture APIs.
+    ///
+    /// The pixels are copied, so the image can have any stride (bytes per row / 4), and can be reused right after this call.
+    pub fn add_frame_bgra(&self, frame_index: usize, image: ImgRef<'_, BGRA8>, presentation_timestamp: f64) -> CatResult<()> {
+        self.add_frame_rgba(frame_index, bgra_to_rgba(image), presentation_timestamp)
+    }
+
+    /// Number of frames discarded so far, because encoding couldn't keep up in `realtime` mode.
+    pub fn dropped_frames(&self) -> usize {
+        self.queue.dropped()
     }
 
     /// Read and decode a PNG file from disk.
@@ -189,29 +1079,337 @@ impl Collector {
     /// Presentation timestamp is time in seconds (since file start at 0) when this frame is to be displayed.
     ///
     /// If the first frame doesn't start at pts=0, the delay will be used for the last frame.
-    pub fn add_frame_png_file(&mut self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
-        let width = self.width;
-        let height = self.height;
-        let image = lodepng::decode32_file(&path)
-            .map_err(|err| Error::PNG(format!("Can't load {}: {}", path.display(), err)))?;
+    ///
+    /// 16-bit PNGs are reduced to 8 bits as configured with `set_tone_mapping()`.
+    #[cfg(feature = "png")]
+    pub fn add_frame_png_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let image = std::fs::read(&path)
+            .map_err(|err| Error::PNG(format!("Can't load {}: {}", path.display(), err)))
+            .and_then(|data| tonemap::decode_png(&data, self.tone_mapping)
+                .map_err(|err| Error::PNG(format!("Can't load {}: {}", path.display(), err))))?;
+
+        let frame = self.prepared_frame(image, presentation_timestamp)?;
+        self.push(frame_index, frame, presentation_timestamp, FrameOptions::default())
+    }
 
-        self.queue.push(frame_index, Ok((Self::resized_binary_alpha(ImgVec::new(image.buffer, image.width, image.height), width, height)?, presentation_timestamp)))
+    fn push(&self, frame_index: usize, image: InputFrame, presentation_timestamp: f64, options: FrameOptions) -> CatResult<()> {
+        let dropped = self.counters.decode.timed(|| self.queue.push(frame_index, Ok((image, presentation_timestamp, options))))?;
+        self.counters.decode.taken(dropped);
+        Ok(())
     }
 
-    #[allow(clippy::identity_op)]
-    #[allow(clippy::erasing_op)]
-    fn resized_binary_alpha(mut image: ImgVec<RGBA8>, width: Option<u32>, height: Option<u32>) -> CatResult<ImgVec<RGBA8>> {
-        let (width, height) = dimensions_for_image((image.width(), image.height()), (width, height));
+    /// Burn captions from an SRT or WebVTT file into the frames.
+    ///
+    /// Captions are matched to frames using their presentation timestamps,
+    /// and are drawn after resizing, so the text stays sharp.
+    #[cfg(feature = "subtitles")]
+    pub fn set_subtitles(&mut self, subtitles: subtitles::Subtitles) {
+        self.subtitles = Some(Arc::new(subtitles));
+    }
+
+    /// Frames are screenshots from a HiDPI screen with this many physical pixels per logical pixel, e.g. 2 for Retina.
+    ///
+    /// Frames are scaled down to the logical size, and `Settings::width` and `height` are in logical pixels too.
+    /// `Settings::crop` is still in pixels of the frames as they're added. Call before adding frames.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.geometry.scale_factor = if scale_factor.is_finite() { scale_factor.max(1.) } else { 1. };
+    }
+
+    /// `prepare_frame()`, unless the frame has to wait for `Settings::autocrop`
+    fn prepared_frame(&self, image: ImgVec<RGBA8>, presentation_timestamp: f64) -> CatResult<InputFrame> {
+        if self.geometry.autocrop {
+            return Ok(self.uncropped_frame(move || image));
+        }
+        Ok(InputFrame::Rgba(self.prepare_frame(image, presentation_timestamp)?))
+    }
+
+    fn prepare_frame(&self, image: ImgVec<RGBA8>, presentation_timestamp: f64) -> CatResult<ImgVec<RGBA8>> {
+        #[cfg(feature = "icc")]
+        let image = match &self.color_conversion {
+            Some(conversion) => conversion.to_srgb(image)?,
+            None => image,
+        };
+        let mut image = Self::resized(Self::keyed(image, self.keys), self.geometry)?;
+        self.render_subtitles(&mut image, presentation_timestamp);
+        Self::make_alpha_binary(&mut image, self.alpha);
+        Self::posterize(&mut image, self.posterize);
+        Ok(image)
+    }
+
+    fn prepare_frame_rgb(&self, image: ImgVec<RGB8>, presentation_timestamp: f64) -> CatResult<ImgVec<RGBA8>> {
+        #[cfg(feature = "icc")]
+        let converts_colors = self.color_conversion.is_some();
+        #[cfg(not(feature = "icc"))]
+        let converts_colors = false;
+        if self.geometry.linear_light || self.keys.any() || converts_colors {
+            let (width, height) = (image.width(), image.height());
+            return self.prepare_frame(ImgVec::new(image.pixels().map(|px| px.with_alpha(255)).collect(), width, height), presentation_timestamp);
+        }
+        let image = cropped(transformed(image, self.geometry.transform), self.geometry.crop)?;
+        let img_size = (image.width(), image.height());
+        let (scaled_width, scaled_height) = self.geometry.scaled_size(img_size);
+        let (width, height) = self.geometry.output_size(img_size);
+        let image = if scaled_width != image.width() || scaled_height != image.height() {
+            let (buf, img_width, img_height) = image.into_contiguous_buf();
+            let mut r = resize::new(img_width, img_height, scaled_width, scaled_height, resize::Pixel::RGB24, self.geometry.filter.resize_type())?;
+            let mut dst = vec![RGB8::new(0, 0, 0); scaled_width * scaled_height];
+            r.resize(buf.as_bytes(), dst.as_bytes_mut())?;
+            ImgVec::new(dst, scaled_width, scaled_height)
+        } else {
+            image
+        };
+        let mut image = ImgVec::new(image.pixels().map(|px| px.with_alpha(255)).collect(), image.width(), image.height());
+        if scaled_width < img_size.0 || scaled_height < img_size.1 {
+            Self::sharpen(&mut image, self.geometry.sharpen);
+        }
+        let image = center_cropped(image, width, height);
+        let mut image = letterboxed(image, width, height, self.geometry.fit);
+        // text is drawn opaque, so the frame stays opaque
+        self.render_subtitles(&mut image, presentation_timestamp);
+        Self::posterize(&mut image, self.posterize);
+        Ok(image)
+    }
+
+    /// Same as `prepare_frame()`, but done after the frame is converted to RGBA on the encoder's thread
+    #[allow(unused_variables)]
+    fn deferred_frame(&self, convert: impl FnOnce() -> ImgVec<RGBA8> + Send + 'static) -> InputFrame {
+        if self.geometry.autocrop {
+            return self.uncropped_frame(convert);
+        }
+        let (geometry, alpha, posterize, keys) = (self.geometry, self.alpha, self.posterize, self.keys);
+        #[cfg(feature = "subtitles")]
+        let subtitles = self.subtitles.clone();
+        #[cfg(feature = "icc")]
+        let color_conversion = self.color_conversion.clone();
+        InputFrame::Deferred(Box::new(move |presentation_timestamp| {
+            let image = convert();
+            #[cfg(feature = "icc")]
+            let image = match &color_conversion {
+                Some(conversion) => conversion.to_srgb(image)?,
+                None => image,
+            };
+            let mut image = Self::resized(Self::keyed(image, keys), geometry)?;
+            #[cfg(feature = "subtitles")]
+            {
+                if let Some(subtitles) = &subtitles {
+                    subtitles.render(image.as_mut(), presentation_timestamp);
+                }
+            }
+            Self::make_alpha_binary(&mut image, alpha);
+            Self::posterize(&mut image, posterize);
+            Ok(image)
+        }))
+    }
+
+    /// Same as `deferred_frame()`, but split at the resizing for `Settings::autocrop`
+    #[allow(unused_variables)]
+    fn uncropped_frame(&self, convert: impl FnOnce() -> ImgVec<RGBA8> + Send + 'static) -> InputFrame {
+        let (geometry, alpha, posterize, keys) = (self.geometry, self.alpha, self.posterize, self.keys);
+        // transform and crop are done before the borders are found
+        let scaling = Geometry { crop: None, transform: Transform::None, ..geometry };
+        #[cfg(feature = "subtitles")]
+        let subtitles = self.subtitles.clone();
+        #[cfg(feature = "icc")]
+        let color_conversion = self.color_conversion.clone();
+        InputFrame::Uncropped(UncroppedFrame {
+            image: Box::new(move || {
+                let image = convert();
+                #[cfg(feature = "icc")]
+                let image = match &color_conversion {
+                    Some(conversion) => conversion.to_srgb(image)?,
+                    None => image,
+                };
+                cropped(transformed(Self::keyed(image, keys), geometry.transform), geometry.crop)
+            }),
+            finish: Box::new(move |image, importance_map, presentation_timestamp| {
+                let mut image = Self::resized(image, scaling)?;
+                #[cfg(feature = "subtitles")]
+                {
+                    if let Some(subtitles) = &subtitles {
+                        subtitles.render(image.as_mut(), presentation_timestamp);
+                    }
+                }
+                Self::make_alpha_binary(&mut image, alpha);
+                Self::posterize(&mut image, posterize);
+                let importance_map = importance_map.map(|map| Self::resized_importance_map(map, scaling)).transpose()?;
+                Ok((image, importance_map))
+            }),
+        })
+    }
+
+    fn has_subtitles(&self) -> bool {
+        #[cfg(feature = "subtitles")]
+        {
+            self.subtitles.is_some()
+        }
+        #[cfg(not(feature = "subtitles"))]
+        {
+            false
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn render_subtitles(&self, image: &mut ImgVec<RGBA8>, presentation_timestamp: f64) {
+        #[cfg(feature = "subtitles")]
+        {
+            if let Some(subtitles) = &self.subtitles {
+                subtitles.render(image.as_mut(), presentation_timestamp);
+            }
+        }
+    }
+
+    fn resized(image: ImgVec<RGBA8>, geometry: Geometry) -> CatResult<ImgVec<RGBA8>> {
+        let image = cropped(transformed(image, geometry.transform), geometry.crop)?;
+        let img_size = (image.width(), image.height());
+        let (scaled_width, scaled_height) = geometry.scaled_size(img_size);
+        let (width, height) = geometry.output_size(img_size);
+        let mut image = Self::resized_exact(image, scaled_width, scaled_height, geometry.filter, geometry.linear_light)?;
+        if scaled_width < img_size.0 || scaled_height < img_size.1 {
+            Self::sharpen(&mut image, geometry.sharpen);
+        }
+        let image = center_cropped(image, width, height);
+        Ok(letterboxed(image, width, height, geometry.fit))
+    }
+
+    /// Unsharp mask with a 3×3 blur, for `Settings::sharpen`. Semi-transparent pixels are left as they are.
+    fn sharpen(image: &mut ImgVec<RGBA8>, amount: f32) {
+        if amount <= 0. {
+            return;
+        }
+        let source = image.clone();
+        let (width, height) = (source.width(), source.height());
+        let near = |pos: usize, offset: usize, len: usize| (pos + offset).saturating_sub(1).min(len - 1);
+        for y in 0..height {
+            for x in 0..width {
+                let center = source[(x, y)];
+                if center.a < 255 {
+                    continue;
+                }
+                let mut blur = [0u32; 3];
+                for (dy, wy) in [(0, 1), (1, 2), (2, 1)] {
+                    for (dx, wx) in [(0, 1), (1, 2), (2, 1)] {
+                        let px = source[(near(x, dx, width), near(y, dy, height))];
+                        // colors of transparent pixels are meaningless
+                        let px = if px.a == 0 { center } else { px };
+                        blur[0] += u32::from(px.r) * wx * wy;
+                        blur[1] += u32::from(px.g) * wx * wy;
+                        blur[2] += u32::from(px.b) * wx * wy;
+                    }
+                }
+                let sharp = |c: u8, blur: u32| (f32::from(c) + (f32::from(c) - blur as f32 / 16.) * amount).round().clamp(0., 255.) as u8;
+                image[(x, y)] = RGBA8::new(sharp(center.r, blur[0]), sharp(center.g, blur[1]), sharp(center.b, blur[2]), 255);
+            }
+        }
+    }
 
+    /// Same as `resized()`, but for `FrameOptions::importance_map`
+    fn resized_importance_map(map: ImgVec<u8>, geometry: Geometry) -> CatResult<ImgVec<u8>> {
+        let map = cropped(transformed(map, geometry.transform), geometry.crop)?;
+        let map_size = (map.width(), map.height());
+        let (scaled_width, scaled_height) = geometry.scaled_size(map_size);
+        let (width, height) = geometry.output_size(map_size);
+        let map = if (scaled_width, scaled_height) != map_size {
+            let (buf, map_width, map_height) = map.into_contiguous_buf();
+            let mut r = resize::new(map_width, map_height, scaled_width, scaled_height, resize::Pixel::Gray8, geometry.filter.resize_type())?;
+            let mut dst = vec![0; scaled_width * scaled_height];
+            r.resize(&buf, &mut dst)?;
+            ImgVec::new(dst, scaled_width, scaled_height)
+        } else {
+            map
+        };
+        // letterbox bars never change, so they don't need any colors
+        Ok(padded(center_cropped(map, width, height), width, height, 0))
+    }
+
+    fn resized_exact(mut image: ImgVec<RGBA8>, width: usize, height: usize, filter: ScalingFilter, linear_light: bool) -> CatResult<ImgVec<RGBA8>> {
         if width != image.width() || height != image.height() {
             let (buf, img_width, img_height) = image.into_contiguous_buf();
             assert_eq!(buf.len(), img_width * img_height);
 
-            let mut r = resize::new(img_width, img_height, width, height, resize::Pixel::RGBA, resize::Type::Lanczos3)?;
+            if linear_light {
+                return Self::resized_linear(&buf, (img_width, img_height), width, height, filter);
+            }
+            let mut r = resize::new(img_width, img_height, width, height, resize::Pixel::RGBA, filter.resize_type())?;
             let mut dst = vec![RGBA8::new(0, 0, 0, 0); width * height];
             r.resize(buf.as_bytes(), dst.as_bytes_mut())?;
             image = ImgVec::new(dst, width, height)
         }
+        Ok(image)
+    }
+
+    /// Resizes with premultiplied alpha in linear light, and converts back to sRGB
+    fn resized_linear(buf: &[RGBA8], (img_width, img_height): (usize, usize), width: usize, height: usize, filter: ScalingFilter) -> CatResult<ImgVec<RGBA8>> {
+        let to_linear: Vec<f32> = (0..=255u8).map(|c| {
+            let c = f32::from(c) / 255.;
+            if c <= 0.040_45 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+        }).collect();
+        let src: Vec<f32> = buf.iter().flat_map(|px| {
+            let a = f32::from(px.a) / 255.;
+            [to_linear[px.r as usize] * a, to_linear[px.g as usize] * a, to_linear[px.b as usize] * a, a]
+        }).collect();
+
+        let mut r = resize::new(img_width, img_height, width, height, resize::Pixel::RGBAF32, filter.resize_type())?;
+        let mut dst = vec![0.; width * height * 4];
+        r.resize(&src, &mut dst)?;
+
+        let to_srgb = |v: f32| {
+            let v = v.clamp(0., 1.);
+            let v = if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1. / 2.4) - 0.055 };
+            (v * 255. + 0.5) as u8
+        };
+        let pixels = dst.chunks_exact(4).map(|px| {
+            let a = px[3].clamp(0., 1.);
+            if a < 1. / 512. {
+                return RGBA8::new(0, 0, 0, 0);
+            }
+            RGBA8::new(to_srgb(px[0] / a), to_srgb(px[1] / a), to_srgb(px[2] / a), (a * 255. + 0.5) as u8)
+        }).collect();
+        Ok(ImgVec::new(pixels, width, height))
+    }
+
+    /// Applies `Settings::chroma_key` and `transparent_color`
+    fn keyed(mut image: ImgVec<RGBA8>, keys: ColorKeys) -> ImgVec<RGBA8> {
+        if keys.any() {
+            image.pixels_mut().filter(|px| keys.matches(**px)).for_each(|px| *px = RGBA8::new(0, 0, 0, 0));
+        }
+        image
+    }
+
+    /// Rounds each channel to the nearest of `2^bits` levels, spread evenly so that black and white are kept
+    fn posterize(image: &mut ImgVec<RGBA8>, bits: Option<u8>) {
+        let levels = match bits {
+            Some(bits @ 1..=7) => (1u16 << bits) - 1,
+            _ => return,
+        };
+        let round = |c: u8| ((u16::from(c) * levels + 127) / 255 * 255 / levels) as u8;
+        for row in image.rows_mut() {
+            for px in row.iter_mut() {
+                *px = RGBA8::new(round(px.r), round(px.g), round(px.b), px.a);
+            }
+        }
+    }
+
+    #[allow(clippy::identity_op)]
+    #[allow(clippy::erasing_op)]
+    fn make_alpha_binary(image: &mut ImgVec<RGBA8>, alpha: BinaryAlpha) {
+        if let Some(matte) = alpha.matte {
+            for row in image.rows_mut() {
+                for px in row.iter_mut().filter(|px| px.a > 0 && px.a < 255) {
+                    let a = u16::from(px.a);
+                    let blend = |c: u8, m: u8| ((u16::from(c) * a + u16::from(m) * (255 - a) + 127) / 255) as u8;
+                    *px = RGBA8::new(blend(px.r, matte.r), blend(px.g, matte.g), blend(px.b, matte.b), 255);
+                }
+            }
+            return;
+        }
+        if let Some(threshold) = alpha.threshold {
+            for row in image.rows_mut() {
+                for px in row {
+                    px.a = if px.a < threshold { 0 } else { 255 };
+                }
+            }
+            return;
+        }
 
         const DITHER: [u8; 64] = [
          0*2+8,48*2+8,12*2+8,60*2+8, 3*2+8,51*2+8,15*2+8,63*2+8,
@@ -231,91 +1429,544 @@ impl Collector {
                 }
             }
         }
-        Ok(image)
     }
 }
 
-/// add_frame is going to resize the image to this size.
-/// The `Option` args are user-specified max width and max height
-fn dimensions_for_image((img_w, img_h): (usize, usize), resize_to: (Option<u32>, Option<u32>)) -> (usize, usize) {
-    match resize_to {
-        (None, None) => {
-            let factor = (img_w * img_h + 800 * 600) / (800 * 600);
-            if factor > 1 {
-                (img_w / factor, img_h / factor)
-            } else {
-                (img_w, img_h)
-            }
-        },
-        (Some(w), Some(h)) => {
-            ((w as usize).min(img_w), (h as usize).min(img_h))
-        },
-        (Some(w), None) => {
-            let w = (w as usize).min(img_w);
-            (w, img_h * w / img_w)
-        }
-        (None, Some(h)) => {
-            let h = (h as usize).min(img_h);
-            (img_w * h / img_h, h)
-        },
+fn bgra_to_rgba(image: ImgRef<'_, BGRA8>) -> ImgVec<RGBA8> {
+    let mut pixels = Vec::with_capacity(image.width() * image.height());
+    // row by row, so that the loop over the row can be vectorized
+    for row in image.rows() {
+        pixels.extend(row.iter().map(|px| RGBA8::new(px.r, px.g, px.b, px.a)));
+    }
+    ImgVec::new(pixels, image.width(), image.height())
+}
+
+/// Starts a thread for a stage of the pipeline. Its result is sent to the returned channel.
+fn spawn_stage<T: Send + 'static>(name: &str, settings: &Settings, spawner: Option<&ThreadSpawner>, stage: impl FnOnce() -> T + Send + 'static) -> std::io::Result<Receiver<T>> {
+    let low_priority = settings.low_priority;
+    let (result, result_recv) = crossbeam_channel::bounded(1);
+    let task = move || {
+        if low_priority {
+            priority::lower_current_thread_priority();
+        }
+        let _ = result.send(stage());
+    };
+    match spawner {
+        Some(spawn) => spawn(name, Box::new(task))?,
+        None => drop(thread::Builder::new().name(name.into()).spawn(task)?),
+    }
+    Ok(result_recv)
+}
+
+/// Size the image is resized to, before `ResizeFit::Crop` or `ResizeFit::Letterbox` make it `dimensions_for_image()`
+fn scaled_dimensions_for_image(img_size: (usize, usize), resize_to: (Option<u32>, Option<u32>), fit: ResizeFit) -> (usize, usize) {
+    let fit = match fit {
+        ResizeFit::Crop => ResizeFit::Cover,
+        ResizeFit::Letterbox(_) => ResizeFit::Contain,
+        other => other,
+    };
+    dimensions_for_image(img_size, resize_to, fit)
+}
+
+/// How semi-transparent pixels are made transparent or opaque, from `Settings`
+#[derive(Debug, Copy, Clone)]
+struct BinaryAlpha {
+    threshold: Option<u8>,
+    matte: Option<RGBA8>,
+}
+
+impl BinaryAlpha {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            threshold: settings.alpha_threshold,
+            matte: settings.matte,
+        }
+    }
+}
+
+/// Colors made transparent, from `Settings`
+#[derive(Debug, Copy, Clone)]
+struct ColorKeys {
+    chroma_key: Option<ChromaKey>,
+    transparent_color: Option<RGB8>,
+}
+
+impl ColorKeys {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            chroma_key: settings.chroma_key,
+            transparent_color: settings.transparent_color,
+        }
+    }
+
+    fn any(&self) -> bool {
+        self.chroma_key.is_some() || self.transparent_color.is_some()
+    }
+
+    fn matches(&self, px: RGBA8) -> bool {
+        matches!(self.chroma_key, Some(key) if key.matches(px)) || self.transparent_color == Some(px.rgb())
+    }
+}
+
+/// How the `Collector` cuts and resizes frames, from `Settings`
+#[derive(Debug, Copy, Clone)]
+struct Geometry {
+    width: Option<u32>,
+    height: Option<u32>,
+    fit: ResizeFit,
+    crop: Option<Rect>,
+    transform: Transform,
+    filter: ScalingFilter,
+    linear_light: bool,
+    sharpen: f32,
+    size_multiple: u8,
+    /// `Settings::autocrop`, which resizes frames only after their borders are found
+    autocrop: bool,
+    /// From `Collector::set_scale_factor()`
+    scale_factor: f32,
+}
+
+impl Geometry {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            width: settings.width,
+            height: settings.height,
+            fit: settings.fit,
+            crop: settings.crop,
+            transform: settings.transform,
+            filter: settings.effective_scaling_filter(),
+            linear_light: settings.linear_light_resize,
+            sharpen: if settings.pixel_art || !settings.sharpen.is_finite() { 0. } else { settings.sharpen.clamp(0., 2.) },
+            size_multiple: settings.size_multiple,
+            autocrop: settings.autocrop,
+            scale_factor: 1.,
+        }
+    }
+
+    /// Size of an image after `transform` and `crop`, in logical pixels
+    fn cropped_size(&self, (img_w, img_h): (usize, usize)) -> (usize, usize) {
+        let (img_w, img_h) = if self.transform.swaps_dimensions() { (img_h, img_w) } else { (img_w, img_h) };
+        let (w, h) = match self.crop.and_then(|crop| crop_bounds(crop, img_w, img_h)) {
+            Some((_, _, w, h)) => (w, h),
+            None => (img_w, img_h),
+        };
+        if self.scale_factor > 1. {
+            let logical = |size: usize| ((size as f32 / self.scale_factor).round() as usize).max(1);
+            (logical(w), logical(h))
+        } else {
+            (w, h)
+        }
+    }
+
+    /// Size of a frame in the GIF, for an input image of this size
+    fn output_size(&self, img_size: (usize, usize)) -> (usize, usize) {
+        let (w, h) = dimensions_for_image(self.cropped_size(img_size), (self.width, self.height), self.fit);
+        (round_to_multiple(w, self.size_multiple), round_to_multiple(h, self.size_multiple))
+    }
+
+    /// Size the image is resized to (after the crop), before `ResizeFit` and `size_multiple` cut or pad it to `output_size()`
+    fn scaled_size(&self, img_size: (usize, usize)) -> (usize, usize) {
+        match self.fit {
+            // these cut or pad to the output size anyway
+            ResizeFit::Crop | ResizeFit::Letterbox(_) => scaled_dimensions_for_image(self.cropped_size(img_size), (self.width, self.height), self.fit),
+            _ => dimensions_for_image(self.cropped_size(img_size), (self.width, self.height), self.fit),
+        }
+    }
+
+    /// Size a video decoder can scale frames to, so that they don't need to be resized again.
+    /// Cropping and the scale factor are applied later, so with either of them they can't be scaled yet.
+    #[cfg(any(test, feature = "video"))]
+    fn scaled_size_in_decoder(&self, img_size: (usize, usize)) -> (usize, usize) {
+        if self.crop.is_some() || self.autocrop || self.scale_factor > 1. {
+            return img_size;
+        }
+        let (w, h) = self.scaled_size(img_size);
+        if self.transform.swaps_dimensions() { (h, w) } else { (w, h) }
+    }
+}
+
+/// For `Settings::size_multiple`. Rounds down, but sizes smaller than the multiple are kept.
+fn round_to_multiple(size: usize, multiple: u8) -> usize {
+    let multiple = usize::from(multiple.max(1));
+    if size < multiple {
+        return size;
+    }
+    size / multiple * multiple
+}
+
+/// Crop area trimmed to the image as (left, top, width, height). `None` if it's outside the image.
+fn crop_bounds(crop: Rect, img_w: usize, img_h: usize) -> Option<(usize, usize, usize, usize)> {
+    let (left, top) = (crop.left as usize, crop.top as usize);
+    let width = (crop.width as usize).min(img_w.saturating_sub(left));
+    let height = (crop.height as usize).min(img_h.saturating_sub(top));
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((left, top, width, height))
+}
+
+/// Applies `FrameOptions::regions` to the importance map
+fn lower_importance_outside(importance_map: &mut [u8], width: usize, regions: &[Rect], divisor: u8) {
+    let height = importance_map.len() / width.max(1);
+    let bounds: Vec<_> = regions.iter().filter_map(|&region| crop_bounds(region, width, height)).collect();
+    if bounds.is_empty() || divisor <= 1 {
+        return;
+    }
+    for (y, row) in importance_map.chunks_exact_mut(width).enumerate() {
+        for (x, imp) in row.iter_mut().enumerate() {
+            if !bounds.iter().any(|&(left, top, w, h)| x >= left && x < left + w && y >= top && y < top + h) {
+                *imp /= divisor;
+            }
+        }
+    }
+}
+
+/// Applies `Settings::transform`
+fn transformed<T: Copy>(image: ImgVec<T>, transform: Transform) -> ImgVec<T> {
+    let (width, height) = (image.width(), image.height());
+    let pixels: Vec<T> = match transform {
+        Transform::None => return image,
+        Transform::Rotate180 => image.pixels().collect::<Vec<_>>().into_iter().rev().collect(),
+        Transform::FlipHorizontal => image.rows().flat_map(|row| row.iter().rev().copied()).collect(),
+        Transform::FlipVertical => image.rows().rev().flat_map(|row| row.iter().copied()).collect(),
+        Transform::Rotate90 => (0..width).flat_map(|x| (0..height).rev().map(move |y| (x, y))).map(|(x, y)| image[(x, y)]).collect(),
+        Transform::Rotate270 => (0..width).rev().flat_map(|x| (0..height).map(move |y| (x, y))).map(|(x, y)| image[(x, y)]).collect(),
+    };
+    if transform.swaps_dimensions() {
+        ImgVec::new(pixels, height, width)
+    } else {
+        ImgVec::new(pixels, width, height)
+    }
+}
+
+/// Cuts out `Settings::crop`
+fn cropped<T: Copy>(image: ImgVec<T>, crop: Option<Rect>) -> CatResult<ImgVec<T>> {
+    let crop = match crop {
+        Some(crop) => crop,
+        None => return Ok(image),
+    };
+    let (left, top, width, height) = crop_bounds(crop, image.width(), image.height())
+        .ok_or_else(|| Error::InvalidInput(format!("Crop area {:?} is outside of the {}×{} frame", crop, image.width(), image.height())))?;
+    if (width, height) == (image.width(), image.height()) {
+        return Ok(image);
+    }
+    Ok(ImgVec::new(image.sub_image(left, top, width, height).pixels().collect(), width, height))
+}
+
+/// Cuts the image down to the given size, keeping its center
+fn center_cropped<T: Copy>(image: ImgVec<T>, width: usize, height: usize) -> ImgVec<T> {
+    if width >= image.width() && height >= image.height() {
+        return image;
+    }
+    let (width, height) = (width.min(image.width()), height.min(image.height()));
+    let cropped = image.sub_image((image.width() - width) / 2, (image.height() - height) / 2, width, height);
+    ImgVec::new(cropped.pixels().collect(), width, height)
+}
+
+/// Centers the image on a background of the given size, if `fit` is `ResizeFit::Letterbox`
+fn letterboxed(image: ImgVec<RGBA8>, width: usize, height: usize, fit: ResizeFit) -> ImgVec<RGBA8> {
+    let color = match fit {
+        ResizeFit::Letterbox(color) if width > image.width() || height > image.height() => color,
+        _ => return image,
+    };
+    let color = if color.a < 128 { RGBA8::new(0, 0, 0, 0) } else { color.with_alpha(255) };
+    padded(image, width, height, color)
+}
+
+/// Centers the image on a background of the given size, if it's smaller
+fn padded<T: Copy>(image: ImgVec<T>, width: usize, height: usize, background: T) -> ImgVec<T> {
+    if width <= image.width() && height <= image.height() {
+        return image;
+    }
+    let mut padded = ImgVec::new(vec![background; width * height], width, height);
+    let (left, top) = ((width - image.width()) / 2, (height - image.height()) / 2);
+    for (dst, src) in padded.rows_mut().skip(top).zip(image.rows()) {
+        dst[left..left + src.len()].copy_from_slice(src);
+    }
+    padded
+}
+
+/// GIF stores width and height as 16-bit numbers
+const MAX_GIF_DIMENSION: usize = u16::MAX as usize;
+
+/// add_frame is going to resize the image to this size.
+/// The `Option` args are user-specified max width and max height
+///
+/// Sizes above the GIF limit of 65535 pixels are scaled down, preserving aspect ratio.
+fn dimensions_for_image((img_w, img_h): (usize, usize), resize_to: (Option<u32>, Option<u32>), fit: ResizeFit) -> (usize, usize) {
+    let (w, h) = match resize_to {
+        (None, None) => {
+            let factor = (img_w * img_h + 800 * 600) / (800 * 600);
+            if factor > 1 {
+                (img_w / factor, img_h / factor)
+            } else {
+                (img_w, img_h)
+            }
+        },
+        (Some(w), Some(h)) => match fit {
+            ResizeFit::Exact => ((w as usize).min(img_w), (h as usize).min(img_h)),
+            ResizeFit::Contain | ResizeFit::Cover => {
+                let scale_w = f64::from(w) / img_w as f64;
+                let scale_h = f64::from(h) / img_h as f64;
+                let scale = if fit == ResizeFit::Contain { scale_w.min(scale_h) } else { scale_w.max(scale_h) };
+                if scale >= 1. {
+                    (img_w, img_h)
+                } else {
+                    (((img_w as f64 * scale).round() as usize).max(1), ((img_h as f64 * scale).round() as usize).max(1))
+                }
+            },
+            ResizeFit::Crop => {
+                let (scaled_w, scaled_h) = dimensions_for_image((img_w, img_h), resize_to, ResizeFit::Cover);
+                (scaled_w.min(w as usize), scaled_h.min(h as usize))
+            },
+            ResizeFit::Letterbox(_) => (w as usize, h as usize),
+        },
+        (Some(w), None) => {
+            let w = (w as usize).min(img_w);
+            (w, img_h * w / img_w)
+        }
+        (None, Some(h)) => {
+            let h = (h as usize).min(img_h);
+            (img_w * h / img_h, h)
+        },
+    };
+    let largest = w.max(h);
+    if largest > MAX_GIF_DIMENSION {
+        ((w * MAX_GIF_DIMENSION / largest).max(1), (h * MAX_GIF_DIMENSION / largest).max(1))
+    } else {
+        (w, h)
     }
 }
 
 /// Encode collected frames
 impl Writer {
+    /// Output frames in the order given by the playlist, instead of the order of their frame indices.
+    ///
+    /// Each entry is an index of a frame added to the `Collector`, and the presentation timestamp
+    /// that frame will have in the output. Frames can be listed more than once (e.g. for a boomerang effect),
+    /// and frames that aren't listed are skipped (their tags are reported with the next frame). Every frame is decoded only once, but frames
+    /// that are used again later are kept in memory until their last use.
+    ///
+    /// Presentation timestamps given to the `Collector` are ignored when a playlist is set.
+    pub fn set_playlist(&mut self, playlist: Vec<(usize, f64)>) {
+        self.playlist = Some(playlist);
+    }
+
+    /// Register a callback that decides which frames are used.
+    ///
+    /// It's called in order of frames, with each frame (after resizing) and its presentation timestamp.
+    /// Return `false` to skip the frame. The previous frame is then displayed for longer.
+    ///
+    /// The callback is called on another thread.
+    pub fn set_frame_filter(&mut self, filter: impl FnMut(ImgRef<'_, RGBA8>, f64) -> bool + Send + 'static) {
+        self.frame_filter = Some(Box::new(filter));
+    }
+
+    /// Colors that every frame's palette must have exactly, such as colors of a logo or a UI accent color.
+    ///
+    /// They're kept even if quantization would merge them with similar colors, and take up space in each palette
+    /// (at most `Settings::max_colors` - 1 of them are used). Colors with alpha < 128 are ignored, since transparency is always available.
+    pub fn set_fixed_colors(&mut self, colors: &[RGBA8]) -> CatResult<()> {
+        if colors.len() > 255 {
+            return Err(Error::InvalidInput(format!("At most 255 colors can be fixed, not {}", colors.len())));
+        }
+        self.fixed_colors.clear();
+        for color in colors.iter().filter(|c| c.a >= 128).map(|c| c.with_alpha(255)) {
+            if !self.fixed_colors.contains(&color) {
+                self.fixed_colors.push(color);
+            }
+        }
+        Ok(())
+    }
+
+    /// Use only colors from this set in palettes, such as `web_safe_colors()` or colors of a game console.
+    ///
+    /// Every frame still gets its own palette, made of the colors of the set that suit the frame best.
+    /// Colors from `set_fixed_colors()` are used too. Colors with alpha < 128 are ignored, since transparency is always available.
+    /// An empty set removes the restriction.
+    pub fn set_palette_family(&mut self, colors: &[RGBA8]) {
+        self.palette_family.clear();
+        for color in colors.iter().filter(|c| c.a >= 128).map(|c| c.with_alpha(255)) {
+            if !self.palette_family.contains(&color) {
+                self.palette_family.push(color);
+            }
+        }
+    }
+
+    /// Start the encoder's threads with this function, instead of `std::thread::Builder`.
+    ///
+    /// It's called with a name of the thread, and a task to run on it. `write()` starts three such tasks,
+    /// and they wait for each other, so all of them must be running at the same time
+    /// (a thread pool with fewer than three free threads would deadlock).
+    /// If the task is dropped without running, `write()` fails.
+    pub fn set_thread_spawner(&mut self, spawner: impl Fn(&str, Box<dyn FnOnce() + Send>) -> std::io::Result<()> + Send + 'static) {
+        self.thread_spawner = Some(Box::new(spawner));
+    }
+
+    /// Get a handle for checking which stage of the encoding is the bottleneck.
+    ///
+    /// It can be sampled from another thread while `write()` is running.
+    pub fn monitor(&self) -> PipelineMonitor {
+        PipelineMonitor {
+            counters: self.counters.clone(),
+        }
+    }
+
     /// `importance_map` is computed from previous and next frame.
     /// Improves quality of pixels visible for longer.
     /// Avoids wasting palette on pixels identical to the background.
     ///
     /// `background` is the previous frame.
-    fn quantize(image: ImgRef<'_, RGBA8>, importance_map: &[u8], has_prev_frame: bool, settings: &Settings) -> CatResult<(Attributes, QuantizationResult, Image<'static>)> {
+    fn quantize(image: ImgRef<'_, RGBA8>, importance_map: &[u8], has_prev_frame: bool, frame_quality: Option<u8>, fixed_colors: &[RGBA8], settings: &Settings) -> CatResult<(Attributes, QuantizationResult, Image<'static>)> {
         let mut liq = Attributes::new();
-        if settings.fast {
+        if settings.extra_effort {
+            liq.set_speed(1);
+        } else if settings.fast {
             liq.set_speed(10);
         }
-        let quality = if has_prev_frame {
+        let quality = if settings.pixel_art {
+            100
+        } else if let Some(frame_quality) = frame_quality {
+            color_quality(frame_quality).into()
+        } else if has_prev_frame || settings.uniform_quality {
             settings.color_quality().into()
         } else {
             100 // the first frame is too important to ruin it
         };
         liq.set_quality(0, quality);
-        let mut img = liq.new_image_stride_copy(image.buf(), image.width(), image.height(), image.stride(), 0.)?;
+        liq.set_max_colors(settings.palette_size().into()).ok()?;
+        let mut img = liq.new_image_stride_copy(image.buf(), image.width(), image.height(), image.stride(), settings.gamma())?;
         img.set_importance_map(importance_map)?;
-        if has_prev_frame {
+        if has_prev_frame && !settings.opaque_deltas {
             img.add_fixed_color(RGBA8::new(0, 0, 0, 0));
         }
+        for &color in fixed_colors {
+            img.add_fixed_color(color).ok()?;
+        }
         let res = liq.quantize(&img)?;
         Ok((liq, res, img))
     }
 
-    fn remap(liq: Attributes, mut res: QuantizationResult, mut img: Image<'static>, background: Option<ImgRef<'_, RGBA8>>, settings: &Settings) -> CatResult<(ImgVec<u8>, Vec<RGBA8>)> {
-        if let Some(bg) = background {
-            img.set_background(liq.new_image_stride(bg.buf(), bg.width(), bg.height(), bg.stride(), 0.)?)?;
+    /// Sets up libimagequant to remap to an existing palette, so that frames get its dithering
+    fn quantize_to_palette(image: ImgRef<'_, RGBA8>, palette: &[RGBA8], settings: &Settings) -> CatResult<(QuantizationResult, Image<'static>)> {
+        let mut liq = Attributes::new();
+        liq.set_speed(10);
+        liq.set_max_colors(palette.len().max(2) as i32).ok()?;
+        let mut img = liq.new_image_stride_copy(image.buf(), image.width(), image.height(), image.stride(), settings.gamma())?;
+        for &color in palette {
+            img.add_fixed_color(color).ok()?;
         }
+        let res = liq.quantize(&img)?;
+        Ok((res, img))
+    }
 
-        res.set_dithering_level(settings.quality as f32 / 150.0);
+    /// Palette for `Settings::shared_palette_colors` or `Settings::global_palette`
+    ///
+    /// `reserved` colors are already fixed, so the palette will have fewer colors.
+    fn shared_palette<'a>(frames: impl Iterator<Item = ImgRef<'a, RGBA8>>, reserved: usize, settings: &Settings) -> CatResult<Vec<RGBA8>> {
+        let colors = if settings.global_palette {
+            // one is left for transparency
+            settings.palette_size() - 1
+        } else {
+            // leave some colors for each frame
+            u16::from(settings.shared_palette_colors).min(settings.palette_size() * 15 / 16)
+        };
+        let colors = colors.saturating_sub(reserved as u16);
+        let mut liq = Attributes::new();
+        if settings.extra_effort {
+            liq.set_speed(1);
+        } else if settings.fast {
+            liq.set_speed(10);
+        }
+        liq.set_max_colors(colors.max(1).into()).ok()?;
+        let mut hist = Histogram::new(&liq);
+        for frame in frames {
+            let mut img = liq.new_image_stride(frame.buf(), frame.width(), frame.height(), frame.stride(), settings.gamma())?;
+            hist.add_image(&mut img).ok()?;
+        }
+        Ok(hist.quantize()?.palette())
+    }
+
+    fn remap(mut res: QuantizationResult, mut img: Image<'static>, source: Option<ImgVec<RGBA8>>, background: Option<ImgRef<'_, RGBA8>>, settings: &Settings) -> CatResult<(ImgVec<u8>, Vec<RGBA8>)> {
+        if let (Some(dithering), Some(source)) = (settings.error_diffusion(), &source) {
+            let pal = res.palette();
+            return Ok((dither::remap(source.as_ref(), &pal, background, dithering, settings.dithering_level()), pal));
+        }
+
+        res.set_dithering_level(settings.dithering_level());
 
         let (pal, pal_img) = res.remapped(&mut img)?;
         debug_assert_eq!(img.width() * img.height(), pal_img.len());
+        let mut image8 = Img::new(pal_img, img.width(), img.height());
+
+        // libimagequant's own `set_background` can't be used, because its wrapper frees the background image twice
+        if let (Some(bg), Some(source)) = (background, source) {
+            show_background(image8.as_mut(), &pal, source.as_ref(), bg);
+        }
 
-        Ok((Img::new(pal_img, img.width(), img.height()), pal))
+        Ok((image8, pal))
     }
 
-    fn write_frames(write_queue: Receiver<FrameMessage>, enc: &mut dyn Encoder, settings: &Settings, reporter: &mut dyn ProgressReporter) -> CatResult<()> {
+    /// Frame added with its own palette only needs pixels identical to the background made transparent
+    fn remap_indexed(indexed: IndexedImage, background: Option<ImgRef<'_, RGBA8>>, max_colors: usize) -> (ImgVec<u8>, Vec<RGBA8>) {
+        let IndexedImage { mut pixels, mut palette } = indexed;
+        if let Some(bg) = background {
+            let transparent_index = palette.iter().position(|c| c.a <= 128).or_else(|| {
+                // a full palette without transparency can't show the background
+                (palette.len() < max_colors).then(|| {
+                    palette.push(RGBA8::new(0, 0, 0, 0));
+                    palette.len() - 1
+                })
+            });
+            if let Some(transparent_index) = transparent_index {
+                for (row, bg_row) in pixels.rows_mut().zip(bg.rows()) {
+                    for (px, &bg) in row.iter_mut().zip(bg_row) {
+                        if bg.a > 128 && palette[*px as usize] == bg {
+                            *px = transparent_index as u8;
+                        }
+                    }
+                }
+            }
+        }
+        (pixels, palette)
+    }
+
+    fn write_frames(write_queue: Receiver<FrameMessage>, enc: &mut dyn Encoder, settings: &Settings, reporter: &mut dyn ProgressReporter, counters: &PipelineCounters) -> CatResult<(StageTiming, Vec<Vec<FrameTag>>)> {
         let mut pts_in_delay_units = 0_u64;
+        let mut timing = StageTiming::default();
+        let mut frame_tags = Vec::new();
+        // tags of skipped frames
+        let mut pending_tags = Vec::new();
+        let mut flushed_bytes = 0;
 
         let mut n_done = 0;
-        for FrameMessage {frame, ordinal_frame_number, end_pts, ..} in write_queue {
+        for FrameMessage {frame, ordinal_frame_number, end_pts, mut tags} in write_queue {
+            counters.write.taken(1);
             let delay = ((end_pts * 100.0).round() as u64)
                 .saturating_sub(pts_in_delay_units)
-                .min(30000) as u16;
+                .min((MAX_FRAME_DELAY * 100.) as u64) as u16;
             pts_in_delay_units += u64::from(delay);
 
             // skip frames with bad pts
+            pending_tags.append(&mut tags);
             if delay != 0 {
+                let start = Instant::now();
                 enc.write_frame(frame, delay, settings)?;
+                let flush = match settings.flush {
+                    FlushPolicy::OnFinish => false,
+                    FlushPolicy::EveryFrame => true,
+                    FlushPolicy::EveryBytes(bytes) => enc.bytes_written() - flushed_bytes >= bytes,
+                };
+                if flush {
+                    enc.flush()?;
+                    flushed_bytes = enc.bytes_written();
+                }
+                timing.add(start.elapsed());
+                reporter.written_frame(&pending_tags);
+                frame_tags.push(std::mem::take(&mut pending_tags));
             }
 
+            reporter.written_bytes(enc.bytes_written());
             // loop to report skipped frames too
             while n_done < ordinal_frame_number {
                 n_done += 1;
@@ -325,7 +1976,7 @@ impl Writer {
             }
         }
         enc.finish()?;
-        Ok(())
+        Ok((timing, frame_tags))
     }
 
     /// Start writing frames. This function will not return until `Collector` is dropped.
@@ -333,12 +1984,14 @@ impl Writer {
     /// `outfile` can be any writer, such as `File` or `&mut Vec`.
     ///
     /// `ProgressReporter.increase()` is called each time a new frame is being written.
+    ///
+    /// Returns statistics about the encode, such as time spent in each stage.
     #[allow(unused_mut)]
-    pub fn write<W: Write>(self, mut writer: W, reporter: &mut dyn ProgressReporter) -> CatResult<()> {
+    pub fn write<W: Write>(self, mut writer: W, reporter: &mut dyn ProgressReporter) -> CatResult<EncodeSummary> {
 
         #[cfg(feature = "gifsicle")]
         {
-            if self.settings.quality < 100 {
+            if self.settings.quality < 100 && !self.settings.pixel_art {
                 let mut gifsicle = encodegifsicle::Gifsicle::new(self.settings.gifsicle_loss(), &mut writer);
                 return self.write_with_encoder(&mut gifsicle, reporter);
             }
@@ -346,55 +1999,191 @@ impl Writer {
         self.write_with_encoder(&mut encoderust::RustEncoder::new(writer), reporter)
     }
 
-    fn write_with_encoder(mut self, encoder: &mut dyn Encoder, reporter: &mut dyn ProgressReporter) -> CatResult<()> {
+    fn write_with_encoder(mut self, encoder: &mut dyn Encoder, reporter: &mut dyn ProgressReporter) -> CatResult<EncodeSummary> {
+        let start = Instant::now();
         let decode_queue_recv = self.queue_iter.take().ok_or(Error::Aborted)?;
 
         let settings = self.settings;
-        let (quant_queue, quant_queue_recv) = crossbeam_channel::bounded(4);
-        let diff_thread = thread::Builder::new().name("diff".into()).spawn(move || {
-            Self::make_diffs(decode_queue_recv, quant_queue, &settings)
+        let playlist = self.playlist.take();
+        let frame_filter = self.frame_filter.take();
+        let fixed_colors = std::mem::take(&mut self.fixed_colors);
+        let palette_family = std::mem::take(&mut self.palette_family);
+        let counters = self.counters.clone();
+        let decode_counters = counters.clone();
+        // end of the previous frame, for frames that have a duration instead of pts
+        let mut prev_end_pts = 0.;
+        let decode_queue_recv = decode_queue_recv.inspect(move |_| decode_counters.decode.taken(1))
+            .map(move |frame| frame.map(|(frame, pts, options)| {
+                let pts = match options.duration {
+                    Some(duration) => {
+                        let pts = prev_end_pts;
+                        prev_end_pts += duration.as_secs_f64();
+                        pts
+                    },
+                    None => {
+                        prev_end_pts = pts;
+                        pts
+                    },
+                };
+                (frame, pts, options)
+            }));
+        let decode_queue_recv: Box<dyn Iterator<Item = QueuedFrame> + Send> = if settings.autocrop {
+            Box::new(autocrop::autocropped(decode_queue_recv, settings.size_multiple))
+        } else {
+            Box::new(decode_queue_recv)
+        };
+        let decode_queue_recv = decode_queue_recv.map(|frame| frame.and_then(|(frame, pts, options)| {
+            let (image, indexed) = frame.into_rgba(pts)?;
+            Ok((image, pts, options, indexed, Vec::new()))
+        }));
+        let (quant_queue, quant_queue_recv) = counters.quantize.bounded(4);
+        let diff_counters = counters.clone();
+        let spawner = self.thread_spawner.as_ref();
+        let diff_thread = spawn_stage("diff", &settings, spawner, move || {
+            let decode_queue_recv: Box<dyn Iterator<Item = DecodedImage>> = match settings.denoise {
+                Some(threshold) if threshold > 0 => Box::new(denoise::denoised(decode_queue_recv, threshold)),
+                _ => Box::new(decode_queue_recv),
+            };
+            let decode_queue_recv: Box<dyn Iterator<Item = DecodedImage>> = match playlist {
+                Some(playlist) => Box::new(PlaylistIter::new(decode_queue_recv, playlist)),
+                None => decode_queue_recv,
+            };
+            let decode_queue_recv: Box<dyn Iterator<Item = DecodedImage>> = match settings.fps {
+                Some(fps) if fps > 0. => Box::new(resample::resampled(decode_queue_recv, fps)),
+                _ => decode_queue_recv,
+            };
+            Self::make_diffs(decode_queue_recv, frame_filter, quant_queue, &settings, &diff_counters)
         })?;
-        let (remap_queue, remap_queue_recv) = crossbeam_channel::bounded(8);
-        let quant_thread = thread::Builder::new().name("quant".into()).spawn(move || {
-            Self::quantize_frames(quant_queue_recv, remap_queue, &settings)
+        let (remap_queue, remap_queue_recv) = counters.remap.bounded(8);
+        let quant_counters = counters.clone();
+        let quant_thread = spawn_stage("quant", &settings, spawner, move || {
+            Self::quantize_frames(quant_queue_recv, remap_queue, &fixed_colors, &palette_family, &settings, &quant_counters)
         })?;
-        let (write_queue, write_queue_recv) = crossbeam_channel::bounded(6);
-        let remap_thread = thread::Builder::new().name("remap".into()).spawn(move || {
-            Self::remap_frames(remap_queue_recv, write_queue, &settings)
+        let (write_queue, write_queue_recv) = counters.write.bounded(6);
+        let remap_counters = counters.clone();
+        let remap_thread = spawn_stage("remap", &settings, spawner, move || {
+            Self::remap_frames(remap_queue_recv, write_queue, &settings, &remap_counters)
         })?;
-        Self::write_frames(write_queue_recv, encoder, &self.settings, reporter)?;
-        diff_thread.join().map_err(|_| Error::ThreadSend)??;
-        quant_thread.join().map_err(|_| Error::ThreadSend)??;
-        remap_thread.join().map_err(|_| Error::ThreadSend)??;
-        Ok(())
+        let (write, frame_tags) = Self::write_frames(write_queue_recv, encoder, &self.settings, reporter, &counters)?;
+        // the channel is closed without a result if the thread panicked
+        let diff = diff_thread.recv().map_err(|_| Error::ThreadSend)??;
+        let quantize = quant_thread.recv().map_err(|_| Error::ThreadSend)??;
+        let remap = remap_thread.recv().map_err(|_| Error::ThreadSend)??;
+        Ok(EncodeSummary {
+            frames_written: write.frames,
+            elapsed: start.elapsed(),
+            stages: StageTimings { diff, quantize, remap, write },
+            frame_tags,
+        })
     }
 
-    fn make_diffs(mut inputs: OrdQueueIter<DecodedImage>, quant_queue: Sender<DiffMessage>, _settings: &Settings) -> CatResult<()> {
-        let (first_frame, first_frame_pts) = inputs.next().transpose()?.ok_or(Error::NoFrames)?;
+    fn make_diffs(inputs: impl Iterator<Item = DecodedImage>, mut frame_filter: Option<FrameFilter>, quant_queue: Sender<DiffMessage>, settings: &Settings, counters: &PipelineCounters) -> CatResult<StageTiming> {
+        // frames taken from the input, including ones rejected by the filter, for progress reporting
+        let pulled = Cell::new(0);
+        let mut inputs = inputs
+            .inspect(|_| pulled.set(pulled.get() + 1))
+            .filter(|frame| match (frame, frame_filter.as_mut()) {
+                (Ok((image, pts, ..)), Some(keep)) => keep(image.as_ref(), *pts),
+                _ => true,
+            });
+
+        let (first_frame, first_frame_pts, first_frame_options, first_frame_indexed, first_frame_dropped_tags) = inputs.next().transpose()?.ok_or(Error::NoFrames)?;
         let mut prev_frame_pts = 0.0;
 
         let first_frame_has_transparency = first_frame.pixels().any(|px| px.a < 128);
+        let (width, height) = (first_frame.width(), first_frame.height());
+        if width > MAX_GIF_DIMENSION || height > MAX_GIF_DIMENSION {
+            return Err(Error::WrongSize(format!("Frame is {}×{}, but GIF can't be larger than {}×{}", width, height, MAX_GIF_DIMENSION, MAX_GIF_DIMENSION)));
+        }
+
+        let mut fetch_next = || -> CatResult<_> {
+            Ok(match inputs.next().transpose()? {
+                Some((image, pts, options, _, dropped_tags)) if settings.wrong_size == WrongSizePolicy::Resize && (image.width() != width || image.height() != height) => {
+                    let mut image = Collector::resized_exact(image, width, height, settings.effective_scaling_filter(), settings.linear_light_resize)?;
+                    Collector::make_alpha_binary(&mut image, BinaryAlpha::new(settings));
+                    Collector::posterize(&mut image, settings.posterize);
+                    // resizing adds colors, so it has to be quantized
+                    Some((image, pts, options, None, dropped_tags))
+                },
+                other => other,
+            }.map(|(image, pts, options, indexed, dropped_tags)| (image, pts, pulled.get(), options, indexed, dropped_tags)))
+        };
 
-        let mut next_frame = Some((first_frame, first_frame_pts));
-        let mut ordinal_frame_number = 0;
-        while let Some((image, mut pts)) = {
+        let overwritten_divisor = 255 * 255 * 6 / u32::from(settings.importance.overwritten_penalty.max(1));
+        let mut next_frame = Some((first_frame, first_frame_pts, pulled.get(), first_frame_options, first_frame_indexed, first_frame_dropped_tags));
+        // pts of the last frame merged into the current one
+        let mut merged_until;
+        // tags of the current frame, and frames skipped or merged into it
+        let mut tags = Vec::new();
+        let mut quality;
+        let mut regions = Vec::new();
+        // `FrameOptions::importance_map` of the current frame
+        let mut user_importance;
+        // `FrameOptions::duration` of the last frame merged into the current one
+        let mut last_duration = None;
+        let mut timing = StageTiming::default();
+        while let Some((image, mut pts, ordinal_frame_number, indexed)) = {
             // this is not while loop's body, but a block that gets the next element
-            let curr_frame = next_frame.take();
-            next_frame = inputs.next().transpose()?;
-            curr_frame
+            quality = None;
+            user_importance = None;
+            let curr_frame = next_frame.take().map(|(image, pts, ordinal, options, indexed, dropped_tags)| {
+                tags.extend(dropped_tags);
+                tags.extend(options.tag);
+                quality = options.quality;
+                regions.extend(options.regions);
+                user_importance = options.importance_map;
+                last_duration = options.duration;
+                (image, pts, ordinal, indexed)
+            });
+            next_frame = fetch_next()?;
+            merged_until = None;
+            // colors of the current frame and frames merged into it, for `Settings::motion_blur`
+            let mut blur_sums = None;
+            if let Some((curr, curr_pts, ..)) = &curr_frame {
+                while let Some((next, next_pts, ..)) = &next_frame {
+                    if !can_merge(curr.as_ref(), next.as_ref(), next_pts - curr_pts, settings) {
+                        break;
+                    }
+                    merged_until = Some(*next_pts);
+                    if let Some((merged, _, _, options, _, dropped_tags)) = next_frame.take() {
+                        if settings.motion_blur && merged.width() == curr.width() && merged.height() == curr.height() {
+                            let sums = blur_sums.get_or_insert_with(|| {
+                                let mut sums = Vec::new();
+                                add_to_blur(&mut sums, curr.as_ref());
+                                sums
+                            });
+                            add_to_blur(sums, merged.as_ref());
+                        }
+                        tags.extend(dropped_tags);
+                        tags.extend(options.tag);
+                        quality = quality.max(options.quality);
+                        regions.extend(options.regions);
+                        user_importance = user_importance.or(options.importance_map);
+                        last_duration = options.duration;
+                    }
+                    next_frame = fetch_next()?;
+                }
+            }
+            match blur_sums {
+                // the blended frame needs new colors
+                Some(sums) => curr_frame.map(|(image, pts, ordinal, _)| (motion_blurred(image, &sums), pts, ordinal, None)),
+                None => curr_frame,
+            }
         } {
+            let start = Instant::now();
             pts -= first_frame_pts;
-            ordinal_frame_number += 1;
+            // the last frame accounts for any rejected frames after it
+            let ordinal_frame_number = if next_frame.is_some() { ordinal_frame_number } else { pulled.get() };
 
             let mut dispose = gif::DisposalMethod::Keep;
-            let importance_map = if let Some((next, _)) = &next_frame {
+            let mut importance_map = if let Some((next, ..)) = &next_frame {
                 if next.width() != image.width() || next.height() != image.height() {
                     return Err(Error::WrongSize(format!("Frame {} has wrong size ({}×{}, expected {}×{})", ordinal_frame_number,
                         next.width(), next.height(), image.width(), image.height())));
                 }
 
                 // Skip identical frames
-                if next.as_ref() == image.as_ref() {
+                if !settings.keep_duplicates && next.as_ref() == image.as_ref() {
                     prev_frame_pts = pts;
                     continue;
                 }
@@ -406,7 +2195,7 @@ impl Writer {
                     }
                     // Even if next frame completely overwrites it, it's still somewhat important to display current one
                     // but pixels that will stay unchanged should have higher quality
-                    255 - (colordiff(n, curr) / (255 * 255 * 6 / 170)) as u8
+                    255 - (colordiff(n, curr) / overwritten_divisor) as u8
                 }));
                 importance_map
             } else {
@@ -419,47 +2208,117 @@ impl Writer {
                 }
                 vec![255; image.width() * image.height()]
             };
+            // frames resized for `WrongSizePolicy::Resize` don't match their maps any more
+            if let Some(user_importance) = user_importance.take().filter(|map| map.width() == image.width() && map.height() == image.height()) {
+                importance_map.iter_mut().zip(user_importance.pixels()).for_each(|(imp, user)| {
+                    *imp = (u16::from(*imp) * u16::from(user) / 255) as u8;
+                });
+            }
 
             // conversion from pts to delay
-            let end_pts = if let Some((_, next_pts)) = next_frame {
+            let end_pts = if let Some((_, next_pts, ..)) = next_frame {
                 next_pts - first_frame_pts
+            } else if let Some(duration) = last_duration {
+                merged_until.map_or(pts, |merged_pts| merged_pts - first_frame_pts) + duration.as_secs_f64()
             } else if first_frame_pts > 1./100. {
                 // this is gifski's weird rule that non-zero first-frame pts
                 // shifts the whole anim and is the delay of the last frame
                 pts + first_frame_pts
             } else {
                 // otherwise assume steady framerate
-                pts + (pts - prev_frame_pts)
+                merged_until.map_or(pts, |merged_pts| merged_pts - first_frame_pts) + (pts - prev_frame_pts)
             };
             prev_frame_pts = pts;
 
-            quant_queue.send(DiffMessage {
+            timing.add(start.elapsed());
+            counters.quantize.send(&quant_queue, DiffMessage {
                 dispose,
                 importance_map,
                 ordinal_frame_number,
                 image,
+                indexed,
                 end_pts,
+                quality,
+                regions: std::mem::take(&mut regions),
+                tags: std::mem::take(&mut tags),
             })?;
         }
 
-        Ok(())
+        Ok(timing)
     }
 
-    fn quantize_frames(inputs: Receiver<DiffMessage>, remap_queue: Sender<RemapMessage>, settings: &Settings) -> CatResult<()> {
+    fn quantize_frames(inputs: Receiver<DiffMessage>, remap_queue: Sender<RemapMessage>, fixed_colors: &[RGBA8], palette_family: &[RGBA8], settings: &Settings, counters: &PipelineCounters) -> CatResult<StageTiming> {
         let next_frame = inputs.recv().map_err(|_| Error::NoFrames)?;
+        counters.quantize.taken(1);
+
+        let fixed_colors = &fixed_colors[..fixed_colors.len().min(usize::from(settings.palette_size()) - 1)];
+
+        // frames read ahead to pick the shared palette from
+        let mut buffered = VecDeque::new();
+        buffered.push_back(next_frame);
+        let shared_palette = if settings.global_palette || settings.shared_palette_colors > 0 {
+            let sample_frames = if settings.global_palette {
+                usize::MAX
+            } else if settings.extra_effort {
+                SHARED_PALETTE_SAMPLE_FRAMES * 4
+            } else {
+                SHARED_PALETTE_SAMPLE_FRAMES
+            };
+            while buffered.len() < sample_frames {
+                match inputs.recv() {
+                    Ok(frame) => buffered.push_back(frame),
+                    Err(_) => break,
+                }
+                counters.quantize.taken(1);
+            }
+            let shared_palette = Self::shared_palette(buffered.iter().map(|f| f.image.as_ref()), fixed_colors.len(), settings)?;
+            Some(if palette_family.is_empty() { shared_palette } else { snapped_to_family(&shared_palette, fixed_colors, palette_family) })
+        } else {
+            None
+        };
+        // colors in every palette
+        let fixed_colors: Vec<_> = fixed_colors.iter().copied().chain(shared_palette.into_iter().flatten()).collect();
+        // if the whole family fits, dithering can pick the best of its colors for every pixel
+        let whole_family = if !palette_family.is_empty() && fixed_colors.len() + palette_family.len() < settings.palette_size().into() {
+            let mut palette = Vec::with_capacity(fixed_colors.len() + palette_family.len() + 1);
+            for &color in fixed_colors.iter().chain(palette_family).chain(&[RGBA8::new(0, 0, 0, 0)]) {
+                if !palette.contains(&color) {
+                    palette.push(color);
+                }
+            }
+            Some(palette)
+        } else {
+            None
+        };
 
-        let mut next_frame = Some(next_frame);
+        let mut next_frame = buffered.pop_front();
         let mut prev_frame: Option<ImgVec<_>> = None;
-
-        while let Some(DiffMessage {image, end_pts, dispose, ordinal_frame_number, mut importance_map}) = {
+        // source pixels of what is currently on screen, for `stable_dithering`
+        let mut drawn: Option<ImgVec<RGBA8>> = None;
+        let mut timing = StageTiming::default();
+        let min_diff = settings.min_pixel_diff();
+        let diff_scale = settings.importance.diff_scale.max(1);
+        let saturation = settings.importance.saturation.max(1);
+        // the last palette picked by libimagequant, and for how many frames it has been used
+        let mut last_palette: Option<(Vec<RGBA8>, u32)> = None;
+        // running libimagequant for a few changed pixels is a waste
+        let reuse_for_small_changes = settings.reuse_palette_for_small_changes && !settings.opaque_deltas;
+
+        while let Some(DiffMessage {image, indexed, end_pts, dispose, ordinal_frame_number, mut importance_map, quality, regions, tags}) = {
             // that's not the while loop, that block gets the next element
             let curr_frame = next_frame.take();
-            next_frame = inputs.recv().ok();
+            next_frame = buffered.pop_front().or_else(|| {
+                let frame = inputs.recv().ok();
+                if frame.is_some() {
+                    counters.quantize.taken(1);
+                }
+                frame
+            });
             curr_frame
         } {
-            if let Some(prev_frame) = &prev_frame {
-                let q = 100 - u32::from(settings.color_quality());
-                let min_diff = 80 + q * q;
+            let start = Instant::now();
+            // unchanged pixels within the frame's rectangle will be drawn again, so they need good colors too
+            if let Some(prev_frame) = prev_frame.as_ref().filter(|_| !settings.opaque_deltas) {
                 importance_map
                     .chunks_exact_mut(image.width())
                     .zip(prev_frame.rows().zip(image.rows()))
@@ -477,44 +2336,119 @@ impl Writer {
                         } else {
                             // clip max value, since if something's different it doesn't matter how much, it has to be displayed anyway
                             // but multiply by previous map last, since it already decided non-max value
-                            let t = diff / 32;
-                            ((t * t).min(256) as u16 * u16::from(*imp) / 256) as u8
+                            let t = diff / diff_scale;
+                            (u64::from(t.saturating_mul(t).min(saturation)) * u64::from(*imp) / u64::from(saturation)) as u8
                         }
                     });
             }
-            let (liq, remap, liq_image) = Self::quantize(image.as_ref(), &importance_map, ordinal_frame_number > 1, settings)?;
-            remap_queue.send(RemapMessage {
+            lower_importance_outside(&mut importance_map, image.width(), &regions, settings.importance.outside_regions_divisor);
+            // comparing with the source of what's on screen, rather than the previous frame, so that slow fades still get updated
+            let unchanged: Option<Vec<_>> = drawn.as_ref().map(|drawn| {
+                drawn.pixels().zip(image.pixels()).map(|(drawn, px)| colordiff(drawn, px) < min_diff).collect()
+            });
+            drawn = if (settings.stable_dithering || settings.opaque_deltas) && dispose == gif::DisposalMethod::Keep {
+                Some(match (drawn.take(), &unchanged) {
+                    (Some(mut drawn), Some(unchanged)) => {
+                        drawn.pixels_mut().zip(image.pixels()).zip(unchanged)
+                            .filter(|&(_, &unchanged)| !unchanged)
+                            .for_each(|((drawn, px), _)| *drawn = px);
+                        drawn
+                    },
+                    _ => image.clone(),
+                })
+            } else {
+                None
+            };
+
+            // there's no need to pick colors if all of them fit, unless the palette has to be shared with other frames
+            let indexed = indexed.or_else(|| {
+                if fixed_colors.is_empty() && settings.palette_interval <= 1 {
+                    IndexedImage::with_exact_colors(image.as_ref(), settings.palette_size().into())
+                } else {
+                    None
+                }
+            }).filter(|_| palette_family.is_empty());
+            let quantized = match (indexed.filter(|indexed| indexed.palette.len() <= settings.palette_size().into()), &whole_family) {
+                (Some(indexed), _) => Quantized::Indexed(indexed),
+                (None, Some(palette)) => Quantized::Palette { palette: palette.clone(), image: image.clone() },
+                (None, None) => match &mut last_palette {
+                    Some((palette, used)) if *used < settings.palette_interval ||
+                        (reuse_for_small_changes && changes_fit_palette(image.as_ref(), &importance_map, palette, min_diff)) => {
+                        *used += 1;
+                        Quantized::Palette { palette: palette.clone(), image: image.clone() }
+                    },
+                    _ => {
+                        let (_, mut remap, liq_image) = Self::quantize(image.as_ref(), &importance_map, ordinal_frame_number > 1, quality, &fixed_colors, settings)?;
+                        let family_palette = if palette_family.is_empty() { None } else { Some(snapped_to_family(&remap.palette(), &fixed_colors, palette_family)) };
+                        if settings.palette_interval > 1 || reuse_for_small_changes {
+                            let mut palette = family_palette.clone().unwrap_or_else(|| remap.palette());
+                            // the first frame's palette may lack transparency, which later frames need
+                            if !settings.opaque_deltas && palette.len() < settings.palette_size().into() && !palette.iter().any(|c| c.a <= 128) {
+                                palette.push(RGBA8::new(0, 0, 0, 0));
+                            }
+                            last_palette = Some((palette, 1));
+                        }
+                        match family_palette {
+                            Some(palette) => Quantized::Palette { palette, image: image.clone() },
+                            None => Quantized::Liq { remap, liq_image },
+                        }
+                    },
+                },
+            };
+            timing.add(start.elapsed());
+            counters.remap.send(&remap_queue, RemapMessage {
                 ordinal_frame_number,
                 end_pts,
                 dispose,
-                liq, remap,
-                liq_image,
+                quantized,
+                unchanged,
+                image: if settings.error_diffusion().is_some() || (ordinal_frame_number > 1 && !settings.opaque_deltas) { Some(image.clone()) } else { None },
+                tags,
             })?;
             prev_frame = if dispose == gif::DisposalMethod::Keep { Some(image) } else { None };
         }
-        Ok(())
+        Ok(timing)
     }
 
-    fn remap_frames(inputs: Receiver<RemapMessage>, write_queue: Sender<FrameMessage>, settings: &Settings) -> CatResult<()> {
+    fn remap_frames(inputs: Receiver<RemapMessage>, write_queue: Sender<FrameMessage>, settings: &Settings, counters: &PipelineCounters) -> CatResult<StageTiming> {
         let next_frame = inputs.recv().map_err(|_| Error::NoFrames)?;
-        let mut screen = gif_dispose::Screen::new(next_frame.liq_image.width(), next_frame.liq_image.height(), RGBA8::new(0, 0, 0, 0), None);
+        counters.remap.taken(1);
+        let (width, height) = next_frame.quantized.size();
+        let mut screen = gif_dispose::Screen::new(width, height, RGBA8::new(0, 0, 0, 0), None);
 
         let mut next_frame = Some(next_frame);
 
         let mut first_frame = true;
-        while let Some(RemapMessage {ordinal_frame_number, end_pts, dispose, liq, remap, liq_image}) = {
+        // tags of frames that turned out to have no changed pixels
+        let mut pending_tags = Vec::new();
+        let mut timing = StageTiming::default();
+        while let Some(RemapMessage {ordinal_frame_number, end_pts, dispose, quantized, unchanged, image, mut tags}) = {
             // that's not the while loop, that block gets the next element
             let curr_frame = next_frame.take();
             next_frame = inputs.recv().ok();
+            if next_frame.is_some() {
+                counters.remap.taken(1);
+            }
             curr_frame
         } {
+            let start = Instant::now();
             let screen_width = screen.pixels.width() as u16;
             let screen_height = screen.pixels.height() as u16;
             let mut screen_after_dispose = screen.dispose();
 
             let (mut image8, mut image8_pal) = {
-                let bg = if !first_frame { Some(screen_after_dispose.pixels()) } else { None };
-                Self::remap(liq, remap, liq_image, bg, settings)?
+                let bg = if !first_frame && !settings.opaque_deltas { Some(screen_after_dispose.pixels()) } else { None };
+                match quantized {
+                    Quantized::Liq { remap, liq_image } => Self::remap(remap, liq_image, image, bg, settings)?,
+                    Quantized::Indexed(indexed) => Self::remap_indexed(indexed, bg, settings.palette_size().into()),
+                    Quantized::Palette { palette, image } => match settings.error_diffusion() {
+                        Some(dithering) => (dither::remap(image.as_ref(), &palette, bg, dithering, settings.dithering_level()), palette),
+                        None => {
+                            let (remap, liq_image) = Self::quantize_to_palette(image.as_ref(), &palette, settings)?;
+                            Self::remap(remap, liq_image, Some(image), bg, settings)?
+                        },
+                    },
+                }
             };
 
             // Palette may have multiple transparent indices :(
@@ -531,22 +2465,53 @@ impl Writer {
                 }
             }
 
+            if let (false, false, Some(transparent_index)) = (first_frame, settings.opaque_deltas, transparent_index) {
+                if settings.transparency_bias > 0 {
+                    bias_to_transparent(image8.as_mut(), &image8_pal, transparent_index, screen_after_dispose.pixels(), settings.transparency_bias);
+                }
+                if let Some(unchanged) = &unchanged {
+                    image8.pixels_mut().zip(screen_after_dispose.pixels().pixels()).zip(unchanged)
+                        .filter(|&((_, bg), &unchanged)| unchanged && bg.a > 128)
+                        .for_each(|((px, _), _)| *px = transparent_index);
+                }
+            }
+
             // Check that palette is fine and has no duplicate transparent indices
             debug_assert!(matches!(image8_pal.len(), 1..=256));
             debug_assert!(image8_pal.iter().enumerate().all(|(idx, color)| {
                 Some(idx as u8) == transparent_index || color.a > 128 || !image8.pixels().any(|px| px == idx as u8)
             }));
 
-            let (left, top, image8) = if !first_frame && next_frame.is_some() {
-                match trim_image(image8, &image8_pal, transparent_index, screen_after_dispose.pixels()) {
+            let (left, top, mut image8) = if !first_frame && next_frame.is_some() {
+                // without transparency, pixels that changed too little to update can only be left out of the frame's rectangle
+                let unchanged_mask = unchanged.as_deref().filter(|_| settings.opaque_deltas);
+                match trim_image(image8, &image8_pal, transparent_index, screen_after_dispose.pixels(), unchanged_mask, settings.extra_effort) {
                     Some(trimmed) => trimmed,
-                    None => continue, // no pixels left
+                    None if settings.keep_duplicates => {
+                        // a pixel of what's already on screen, just to keep the frame's delay
+                        let px = screen_after_dispose.pixels()[(0usize, 0usize)];
+                        image8_pal = vec![px];
+                        transparent_index = if px.a <= 128 { Some(0) } else { None };
+                        (0, 0, ImgVec::new(vec![0], 1, 1))
+                    },
+                    None => {
+                        // no pixels left
+                        pending_tags.append(&mut tags);
+                        continue;
+                    },
                 }
             } else {
                 // must keep first and last frame
                 (0, 0, image8)
             };
 
+            if settings.extra_effort {
+                remove_unused_colors(&mut image8, &mut image8_pal, &mut transparent_index);
+            }
+            if settings.transparent_index_first {
+                move_transparent_to_first(&mut image8, &mut image8_pal, &mut transparent_index);
+            }
+
             let frame = GIFFrame {
                 left,
                 top,
@@ -559,57 +2524,228 @@ impl Writer {
             };
 
             screen_after_dispose.then_blit(Some(&frame.pal), dispose, left, top as _, frame.image.as_ref(), transparent_index)?;
+            timing.add(start.elapsed());
 
-            write_queue.send(FrameMessage {
+            pending_tags.append(&mut tags);
+            counters.write.send(&write_queue, FrameMessage {
                 ordinal_frame_number,
                 end_pts,
                 frame,
+                tags: std::mem::take(&mut pending_tags),
             })?;
 
             first_frame = false;
         }
-        Ok(())
+        Ok(timing)
     }
 }
 
-fn trim_image(mut image8: ImgVec<u8>, image8_pal: &[RGBA8], transparent_index: Option<u8>, screen: ImgRef<RGBA8>) -> Option<(u16, u16, ImgVec<u8>)> {
-    let mut image_trimmed = image8.as_ref();
+/// Replaces pixels that look almost the same as the screen under them with transparency
+fn bias_to_transparent(mut image8: ImgRefMut<'_, u8>, image8_pal: &[RGBA8], transparent_index: u8, screen: ImgRef<'_, RGBA8>, bias: u8) {
+    let level = f32::from(bias.min(100)) * 0.16;
+    let max_diff = (level * level * 6.) as u32;
+    for (img_row, screen_row) in image8.rows_mut().zip(screen.rows()) {
+        for (px, &bg) in img_row.iter_mut().zip(screen_row) {
+            if bg.a > 128 && colordiff(image8_pal[*px as usize], bg) <= max_diff {
+                *px = transparent_index;
+            }
+        }
+    }
+}
 
-    let bottom = image_trimmed.rows().zip(screen.rows()).rev()
-        .take_while(|(img_row, screen_row)| {
-            img_row.iter().copied().zip(screen_row.iter().copied())
-                .all(|(px, bg)| {
-                    Some(px) == transparent_index || image8_pal.get(px as usize) == Some(&bg)
-                })
-        })
-        .count();
+/// Makes pixels transparent where the background is at least as close to the source as their palette color,
+/// like libimagequant does when it's given a background
+fn show_background(mut image8: ImgRefMut<'_, u8>, image8_pal: &[RGBA8], source: ImgRef<'_, RGBA8>, background: ImgRef<'_, RGBA8>) {
+    let transparent_index = match image8_pal.iter().position(|c| c.a == 0) {
+        Some(idx) => idx as u8,
+        None => return,
+    };
+    for ((img_row, src_row), bg_row) in image8.rows_mut().zip(source.rows()).zip(background.rows()) {
+        for ((px, &src), &bg) in img_row.iter_mut().zip(src_row).zip(bg_row) {
+            if bg.a > 128 && src.a > 128 && colordiff(bg, src) <= colordiff(image8_pal[*px as usize], src) {
+                *px = transparent_index;
+            }
+        }
+    }
+}
 
-    if bottom > 0 {
-        if bottom == image_trimmed.height() {
-            return None;
+fn trim_image(mut image8: ImgVec<u8>, image8_pal: &[RGBA8], transparent_index: Option<u8>, screen: ImgRef<RGBA8>, unchanged_mask: Option<&[bool]>, trim_columns: bool) -> Option<(u16, u16, ImgVec<u8>)> {
+    let width = image8.width();
+    let height = image8.height();
+    let unchanged = |x: usize, y: usize| {
+        let px = image8[(x, y)];
+        Some(px) == transparent_index || image8_pal.get(px as usize) == Some(&screen[(x, y)]) ||
+            matches!(unchanged_mask, Some(mask) if mask[y * width + x])
+    };
+    let row_unchanged = |y: usize| (0..width).all(|x| unchanged(x, y));
+
+    let bottom = (0..height).rev().take_while(|&y| row_unchanged(y)).count();
+    if bottom == height {
+        return None;
+    }
+    let top = (0..height - bottom).take_while(|&y| row_unchanged(y)).count();
+    let rows = top..height - bottom;
+
+    let mut left = 0;
+    let mut right = 0;
+    if trim_columns {
+        // at least one pixel is changed, so this can't trim everything
+        let column_unchanged = |x: usize| rows.clone().all(|y| unchanged(x, y));
+        left = (0..width).take_while(|&x| column_unchanged(x)).count();
+        right = (left..width).rev().take_while(|&x| column_unchanged(x)).count();
+    }
+
+    if rows.len() != height || left + right > 0 {
+        let trimmed = image8.sub_image(left, top, width - left - right, rows.len());
+        let (buf, width, height) = trimmed.to_contiguous_buf();
+        image8 = Img::new(buf.into_owned(), width, height);
+    }
+
+    Some((left as _, top as _, image8))
+}
+
+/// Smaller palette needs fewer bits per pixel
+fn remove_unused_colors(image8: &mut ImgVec<u8>, image8_pal: &mut Vec<RGBA8>, transparent_index: &mut Option<u8>) {
+    let mut used = [false; 256];
+    for px in image8.pixels() {
+        used[px as usize] = true;
+    }
+    let mut new_index = [0u8; 256];
+    let mut new_pal = Vec::with_capacity(image8_pal.len());
+    for (i, &color) in image8_pal.iter().enumerate() {
+        if used[i] {
+            new_index[i] = new_pal.len() as u8;
+            new_pal.push(color);
         }
-        image_trimmed = image_trimmed.sub_image(0, 0, image_trimmed.width(), image_trimmed.height() - bottom);
     }
+    if new_pal.len() == image8_pal.len() {
+        return;
+    }
+    image8.pixels_mut().for_each(|px| *px = new_index[*px as usize]);
+    *transparent_index = transparent_index.filter(|&t| used[t as usize]).map(|t| new_index[t as usize]);
+    *image8_pal = new_pal;
+}
 
-    let top = image_trimmed.rows().zip(screen.rows())
-        .take_while(|(img_row, screen_row)| {
-            img_row.iter().copied().zip(screen_row.iter().copied())
-                .all(|(px, bg)| {
-                    Some(px) == transparent_index || image8_pal.get(px as usize) == Some(&bg)
-                })
-        })
-        .count();
+/// For `Settings::transparent_index_first`. Adds a transparent color if there's room for it.
+fn move_transparent_to_first(image8: &mut ImgVec<u8>, image8_pal: &mut Vec<RGBA8>, transparent_index: &mut Option<u8>) {
+    match *transparent_index {
+        Some(0) => {},
+        Some(t) => {
+            image8_pal.swap(0, t as usize);
+            image8.pixels_mut().for_each(|px| *px = if *px == t { 0 } else if *px == 0 { t } else { *px });
+            *transparent_index = Some(0);
+        },
+        None if image8_pal.len() < 256 => {
+            image8_pal.insert(0, RGBA8::new(0, 0, 0, 0));
+            image8.pixels_mut().for_each(|px| *px += 1);
+            *transparent_index = Some(0);
+        },
+        None => {},
+    }
+}
+
+/// How many frames are read ahead to pick colors for `Settings::shared_palette_colors`
+const SHARED_PALETTE_SAMPLE_FRAMES: usize = 8;
+
+/// Frames with changes in fewer pixels than this can keep the previous frame's palette, like when typing
+const REUSE_PALETTE_MAX_CHANGED_PERCENT: usize = 5;
+
+/// Longest delay `write_frames` can write, in seconds
+const MAX_FRAME_DELAY: f64 = 300.;
 
-    if top > 0 {
-        image_trimmed = image_trimmed.sub_image(0, top, image_trimmed.width(), image_trimmed.height() - top);
+/// Whether `next` frame can be dropped, and `curr` displayed for `delay` seconds instead
+fn can_merge(curr: ImgRef<'_, RGBA8>, next: ImgRef<'_, RGBA8>, delay: f64, settings: &Settings) -> bool {
+    // longer delay would be clamped, and the missing time would be added to the next frame
+    if delay >= MAX_FRAME_DELAY {
+        return false;
+    }
+    if matches!(settings.max_fps, Some(max_fps) if max_fps > 0. && delay < 1. / f64::from(max_fps)) {
+        return true;
+    }
+    if settings.duplicate_threshold > 0. && is_near_duplicate(curr, next, settings.duplicate_threshold) {
+        return true;
+    }
+    let min_diff = settings.min_pixel_diff().max(8 * 8 * 6);
+    match settings.adaptive_frame_rate {
+        Some(adaptive) => delay < adaptive.min_delay || (delay < adaptive.max_delay && is_still(curr, next, settings.merge_threshold.max(0.1), min_diff)),
+        None => settings.merge_threshold > 0. && is_still(curr, next, settings.merge_threshold, min_diff),
     }
+}
 
-    if image_trimmed.height() != image8.height() {
-        let (buf, width, height) = image_trimmed.to_contiguous_buf();
-        image8 = Img::new(buf.into_owned(), width, height);
+/// Some, but few pixels need updating (have importance), and the palette has colors close to all of them
+fn changes_fit_palette(image: ImgRef<'_, RGBA8>, importance_map: &[u8], palette: &[RGBA8], max_diff: u32) -> bool {
+    let max_changed = image.width() * image.height() * REUSE_PALETTE_MAX_CHANGED_PERCENT / 100;
+    let changed = || image.pixels().zip(importance_map).filter(|&(_, &imp)| imp > 0).map(|(px, _)| px);
+    changed().next().is_some() && changed().nth(max_changed).is_none() &&
+        changed().all(|px| px.a < 128 || palette.iter().any(|&color| colordiff(color, px) < max_diff))
+}
+
+/// Adds up colors of opaque pixels, and how many frames had them opaque
+fn add_to_blur(sums: &mut Vec<[u32; 4]>, image: ImgRef<'_, RGBA8>) {
+    sums.resize(image.width() * image.height(), [0; 4]);
+    for (sum, px) in sums.iter_mut().zip(image.pixels()) {
+        if px.a > 0 {
+            *sum = [sum[0] + u32::from(px.r), sum[1] + u32::from(px.g), sum[2] + u32::from(px.b), sum[3] + 1];
+        }
+    }
+}
+
+/// Average colors from `add_to_blur()`. Transparency of the image is kept.
+fn motion_blurred(mut image: ImgVec<RGBA8>, sums: &[[u32; 4]]) -> ImgVec<RGBA8> {
+    for (px, sum) in image.pixels_mut().zip(sums) {
+        if px.a > 0 && sum[3] > 0 {
+            let avg = |total: u32| ((total + sum[3] / 2) / sum[3]) as u8;
+            *px = RGBA8::new(avg(sum[0]), avg(sum[1]), avg(sum[2]), px.a);
+        }
     }
+    image
+}
 
-    Some((0, top as _, image8))
+/// Frames differ by at least `min_diff` in at most `max_changed_percent` of pixels
+fn is_still(a: ImgRef<'_, RGBA8>, b: ImgRef<'_, RGBA8>, max_changed_percent: f32, min_diff: u32) -> bool {
+    if a.width() != b.width() || a.height() != b.height() {
+        return false;
+    }
+    let max_changed = ((a.width() * a.height()) as f32 * max_changed_percent / 100.) as usize;
+    a.pixels().zip(b.pixels())
+        .filter(|&(a, b)| colordiff(a, b) >= min_diff)
+        .nth(max_changed)
+        .is_none()
+}
+
+/// Frames differ on average by less than `max_diff` in a channel, i.e. only by noise or compression artifacts
+fn is_near_duplicate(a: ImgRef<'_, RGBA8>, b: ImgRef<'_, RGBA8>, max_diff: f32) -> bool {
+    if a.width() != b.width() || a.height() != b.height() {
+        return false;
+    }
+    let total: f64 = a.pixels().zip(b.pixels()).map(|(a, b)| {
+        if a.a == 0 && b.a == 0 { 0. } else { (f64::from(colordiff(a, b)) / 6.).sqrt() }
+    }).sum();
+    total < f64::from(max_diff) * (a.width() * a.height()) as f64
+}
+
+/// The 216 colors of the web-safe palette, for `Writer::set_palette_family()`
+pub fn web_safe_colors() -> Vec<RGBA8> {
+    (0..216u8).map(|i| RGBA8::new(i / 36 * 0x33, i / 6 % 6 * 0x33, i % 6 * 0x33, 255)).collect()
+}
+
+/// Replaces colors of the palette with the closest colors of the family, except the `keep` colors and transparency
+fn snapped_to_family(palette: &[RGBA8], keep: &[RGBA8], family: &[RGBA8]) -> Vec<RGBA8> {
+    let mut snapped = Vec::with_capacity(palette.len());
+    for &color in palette {
+        let color = if color.a <= 128 {
+            RGBA8::new(0, 0, 0, 0)
+        } else if keep.contains(&color) {
+            color
+        } else {
+            let color = color.with_alpha(255);
+            family.iter().copied().min_by_key(|&f| colordiff(color, f)).unwrap_or(color)
+        };
+        if !snapped.contains(&color) {
+            snapped.push(color);
+        }
+    }
+    snapped
 }
 
 #[inline]
@@ -621,3 +2757,952 @@ fn colordiff(a: RGBA8, b: RGBA8) -> u32 {
     (i32::from(i16::from(a.g) - i16::from(b.g)) * i32::from(i16::from(a.g) - i16::from(b.g))) as u32 * 3 +
     (i32::from(i16::from(a.b) - i16::from(b.b)) * i32::from(i16::from(a.b) - i16::from(b.b))) as u32
 }
+
+/// Frame `i` of an animation for tests: one color that changes in every frame, shown 0.1s after the previous frame
+#[cfg(test)]
+pub(crate) fn test_frame(i: u8, width: usize, height: usize) -> (ImgVec<RGBA8>, f64) {
+    (ImgVec::new(vec![RGBA8::new(i * 60, 0, 0, 255); width * height], width, height), f64::from(i) / 10.)
+}
+
+/// Frame `i` of an animation for tests: a gradient with a different green in every frame
+#[cfg(test)]
+fn gradient_test_frame(i: u8) -> (ImgVec<RGBA8>, f64) {
+    let pixels = (0..16 * 16).map(|n| RGBA8::new(n as u8, i * 60, 255 - n as u8, 255)).collect();
+    (ImgVec::new(pixels, 16, 16), f64::from(i) / 10.)
+}
+
+/// RGB palettes of all frames of a GIF
+#[cfg(test)]
+fn frame_palettes(gif: &[u8]) -> Vec<Vec<u8>> {
+    let mut decoder = gif::DecodeOptions::new().read_info(gif).unwrap();
+    std::iter::from_fn(|| decoder.read_next_frame().unwrap().map(|f| f.palette.clone().unwrap())).collect()
+}
+
+#[test]
+fn dimensions_fit_gif_limit() {
+    assert_eq!((65535, 1), dimensions_for_image((200_000, 1), (None, None), ResizeFit::Exact));
+    assert_eq!((65535, 32767), dimensions_for_image((100_000, 50_000), (Some(100_000), None), ResizeFit::Exact));
+    assert_eq!((400, 300), dimensions_for_image((800, 600), (Some(400), None), ResizeFit::Exact));
+}
+
+#[test]
+fn dimensions_fit_box() {
+    assert_eq!((400, 400), dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Exact));
+    assert_eq!((400, 300), dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Contain));
+    assert_eq!((533, 400), dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Cover));
+    assert_eq!((800, 600), dimensions_for_image((800, 600), (Some(1000), Some(1000)), ResizeFit::Contain));
+    assert_eq!((400, 400), dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Crop));
+    assert_eq!((533, 400), scaled_dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Crop));
+    assert_eq!((800, 500), dimensions_for_image((800, 600), (Some(1000), Some(500)), ResizeFit::Crop));
+}
+
+#[test]
+fn letterbox_pads_to_size() {
+    let (w, h) = dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Letterbox(RGBA8::new(0, 0, 0, 255)));
+    assert_eq!((400, 400), (w, h));
+    assert_eq!((400, 300), scaled_dimensions_for_image((800, 600), (Some(400), Some(400)), ResizeFit::Letterbox(RGBA8::new(0, 0, 0, 255))));
+
+    let image = ImgVec::new(vec![RGBA8::new(255, 255, 255, 255); 2], 1, 2);
+    let padded = letterboxed(image, 3, 2, ResizeFit::Letterbox(RGBA8::new(9, 9, 9, 100)));
+    assert_eq!(0, padded[(0usize, 1usize)].a);
+    assert_eq!(255, padded[(1usize, 1usize)].r);
+    assert_eq!(0, padded[(2usize, 0usize)].a);
+}
+
+#[test]
+fn crops_before_resizing() {
+    let settings = Settings {
+        width: Some(50),
+        crop: Some(Rect { left: 100, top: 0, width: 200, height: 1000 }),
+        ..Settings::default()
+    };
+    assert_eq!((50, 150), settings.dimensions_for_image(1000, 600));
+
+    let image = ImgVec::new((0..12u8).collect(), 4, 3);
+    let image = cropped(image, Some(Rect { left: 1, top: 1, width: 2, height: 5 })).unwrap();
+    assert_eq!(&[5, 6, 9, 10], image.buf().as_slice());
+    assert!(cropped(image, Some(Rect { left: 5, top: 0, width: 1, height: 1 })).is_err());
+}
+
+#[test]
+fn transforms_frames() {
+    // 1 2 3
+    // 4 5 6
+    let image = ImgVec::new(vec![1u8, 2, 3, 4, 5, 6], 3, 2);
+    let check = |transform, expected: &[u8], width| {
+        let out = transformed(image.clone(), transform);
+        assert_eq!((expected, width), (out.buf().as_slice(), out.width()), "{:?}", transform);
+    };
+    check(Transform::None, &[1, 2, 3, 4, 5, 6], 3);
+    check(Transform::Rotate90, &[4, 1, 5, 2, 6, 3], 2);
+    check(Transform::Rotate180, &[6, 5, 4, 3, 2, 1], 3);
+    check(Transform::Rotate270, &[3, 6, 2, 5, 1, 4], 2);
+    check(Transform::FlipHorizontal, &[3, 2, 1, 6, 5, 4], 3);
+    check(Transform::FlipVertical, &[4, 5, 6, 1, 2, 3], 3);
+
+    let settings = Settings { transform: Transform::Rotate90, ..Settings::default() };
+    assert_eq!((60, 80), settings.dimensions_for_image(80, 60));
+}
+
+#[test]
+fn nearest_filter_keeps_colors() {
+    let black = RGBA8::new(0, 0, 0, 255);
+    let white = RGBA8::new(255, 255, 255, 255);
+    let image = ImgVec::new(vec![black, black, white, white, black, black, white, white], 8, 1);
+    let image = Collector::resized_exact(image, 4, 1, ScalingFilter::Nearest, false).unwrap();
+    assert!(image.pixels().all(|px| px == black || px == white));
+}
+
+#[test]
+fn linear_light_keeps_brightness() {
+    let image = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255), RGBA8::new(255, 255, 255, 255)], 2, 1);
+    let srgb = Collector::resized_exact(image.clone(), 1, 1, ScalingFilter::Triangle, false).unwrap();
+    let linear = Collector::resized_exact(image, 1, 1, ScalingFilter::Triangle, true).unwrap();
+    assert!((126..=129).contains(&srgb[(0usize, 0usize)].g));
+    // half of the light is brighter than half of the sRGB value
+    assert!((185..=190).contains(&linear[(0usize, 0usize)].g), "{:?}", linear[(0usize, 0usize)]);
+    assert_eq!(255, linear[(0usize, 0usize)].a);
+}
+
+#[test]
+fn autocrop_removes_bars() {
+    let frames = (0..3u8).map(|i| {
+        let mut pixels = vec![RGBA8::new(0, 0, 0, 255); 8 * 8];
+        for px in &mut pixels[2 * 8..6 * 8] {
+            *px = RGBA8::new(i * 50, 100, 200, 255);
+        }
+        (ImgVec::new(pixels, 8, 8), f64::from(i) / 10.)
+    });
+    let mut out = Vec::new();
+    encode(Settings { autocrop: true, ..Settings::default() }, frames, &mut out).unwrap();
+    let decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    assert_eq!((8, 4), (decoder.width(), decoder.height()));
+}
+
+#[test]
+fn autocrop_is_done_before_resizing() {
+    // bars on the sides, in more frames than borders are looked for in
+    let frames = (0..40u8).map(|i| {
+        let mut frame = ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); 16 * 16], 16, 16);
+        for y in 0..16usize {
+            for x in 4..12usize {
+                frame[(x, y)] = RGBA8::new(i * 5, 100, 200, 255);
+            }
+        }
+        (frame, f64::from(i) / 10.)
+    });
+    let mut out = Vec::new();
+    encode(Settings { autocrop: true, width: Some(4), ..Settings::default() }, frames, &mut out).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    assert_eq!((4, 8), (decoder.width(), decoder.height()));
+    let mut frames = 0;
+    while decoder.read_next_frame().unwrap().is_some() {
+        frames += 1;
+    }
+    assert_eq!(40, frames);
+}
+
+#[test]
+fn rounds_to_multiple() {
+    let settings = Settings { width: Some(101), size_multiple: 4, ..Settings::default() };
+    assert_eq!((100, 76), settings.dimensions_for_image(202, 153));
+    let settings = Settings { size_multiple: 16, ..Settings::default() };
+    assert_eq!((3, 16), settings.dimensions_for_image(3, 20));
+    let settings = Settings { width: Some(50), height: Some(50), fit: ResizeFit::Letterbox(RGBA8::new(0, 0, 0, 0)), size_multiple: 8, ..Settings::default() };
+    assert_eq!((48, 48), settings.dimensions_for_image(200, 100));
+}
+
+#[test]
+fn size_multiple_cuts_instead_of_stretching() {
+    // a column of each color, so that stretching would blend them
+    let image = ImgVec::new((0..20 * 4).map(|i| RGBA8::new((i % 20) as u8 * 10, 0, 0, 255)).collect(), 20, 4);
+    let geometry = Geometry::new(&Settings { size_multiple: 8, ..Settings::default() });
+    let resized = Collector::resized(image, geometry).unwrap();
+    assert_eq!((16, 4), (resized.width(), resized.height()));
+    assert_eq!((20..180).step_by(10).map(|r| RGBA8::new(r as u8, 0, 0, 255)).collect::<Vec<_>>(), resized.rows().next().unwrap());
+}
+
+#[test]
+fn scales_to_logical_pixels() {
+    let (mut collector, _writer) = new(Settings { width: Some(500), ..Settings::default() }).unwrap();
+    collector.set_scale_factor(2.);
+    assert_eq!((500, 250), collector.geometry.output_size((2000, 1000)));
+    assert_eq!((300, 200), collector.geometry.output_size((600, 400)));
+    // the Collector divides by the scale factor, so the decoder must not do it too
+    assert_eq!((2000, 1000), collector.geometry.scaled_size_in_decoder((2000, 1000)));
+    collector.set_scale_factor(1.);
+    assert_eq!((500, 250), collector.geometry.scaled_size_in_decoder((2000, 1000)));
+}
+
+#[test]
+fn alpha_threshold_is_hard() {
+    let mut image = ImgVec::new((0..=255u8).map(|a| RGBA8::new(1, 2, 3, a)).collect(), 16, 16);
+    Collector::make_alpha_binary(&mut image, BinaryAlpha { threshold: Some(200), matte: None });
+    assert!(image.pixels().all(|px| px.a == if px.a == 0 { 0 } else { 255 }));
+    assert_eq!(200, image.pixels().filter(|px| px.a == 0).count());
+
+    let mut dithered = ImgVec::new(vec![RGBA8::new(1, 2, 3, 100); 64], 8, 8);
+    Collector::make_alpha_binary(&mut dithered, BinaryAlpha { threshold: None, matte: None });
+    assert!(dithered.pixels().any(|px| px.a == 0) && dithered.pixels().any(|px| px.a == 255));
+}
+
+#[test]
+fn posterizes_channels() {
+    let mut image = ImgVec::new(vec![RGBA8::new(0, 100, 200, 77), RGBA8::new(255, 127, 128, 255)], 2, 1);
+    Collector::posterize(&mut image, Some(1));
+    assert_eq!(&[RGBA8::new(0, 0, 255, 77), RGBA8::new(255, 0, 255, 255)], image.buf().as_slice());
+    Collector::posterize(&mut image, Some(8));
+    assert_eq!(RGBA8::new(0, 0, 255, 77), image.buf()[0]);
+}
+
+#[test]
+fn chroma_key_makes_background_transparent() {
+    let key = ChromaKey { color: RGB8::new(0, 255, 0), tolerance: 30 };
+    let image = ImgVec::new(vec![RGBA8::new(0, 255, 0, 255), RGBA8::new(20, 235, 10, 255), RGBA8::new(0, 200, 0, 255)], 3, 1);
+    let image = Collector::keyed(image, ColorKeys { chroma_key: Some(key), transparent_color: None });
+    assert_eq!(&[RGBA8::new(0, 0, 0, 0), RGBA8::new(0, 0, 0, 0), RGBA8::new(0, 200, 0, 255)], image.buf().as_slice());
+}
+
+#[test]
+fn transparent_color_is_exact() {
+    let image = ImgVec::new(vec![RGBA8::new(255, 0, 255, 255), RGBA8::new(254, 0, 255, 255)], 2, 1);
+    let image = Collector::keyed(image, ColorKeys { chroma_key: None, transparent_color: Some(RGB8::new(255, 0, 255)) });
+    assert_eq!(&[RGBA8::new(0, 0, 0, 0), RGBA8::new(254, 0, 255, 255)], image.buf().as_slice());
+
+    let (collector, writer) = new(Settings { transparent_color: Some(RGB8::new(255, 0, 255)), ..Settings::default() }).unwrap();
+    let palette = [RGBA8::new(255, 0, 255, 255), RGBA8::new(0, 0, 0, 255)];
+    for i in 0..2 {
+        collector.add_frame_indexed(i, ImgVec::new(vec![0, 1, 1, i as u8], 2, 2), &palette, i as f64).unwrap();
+    }
+    drop(collector);
+    let mut out = Vec::new();
+    writer.write(&mut out, &mut NoProgress {}).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    assert!(decoder.read_next_frame().unwrap().unwrap().transparent.is_some());
+}
+
+#[test]
+fn sharpens_edges() {
+    let gray = |v| RGBA8::new(v, v, v, 255);
+    let mut image = ImgVec::new(vec![gray(100), gray(100), gray(200), gray(200), RGBA8::new(200, 200, 200, 0)], 5, 1);
+    Collector::sharpen(&mut image, 1.);
+    assert_eq!(&[gray(100), gray(75), gray(225), gray(200), RGBA8::new(200, 200, 200, 0)], image.buf().as_slice());
+}
+
+#[test]
+fn blends_with_matte() {
+    let mut image = ImgVec::new(vec![RGBA8::new(200, 0, 0, 0), RGBA8::new(200, 0, 0, 51), RGBA8::new(200, 0, 0, 255)], 3, 1);
+    Collector::make_alpha_binary(&mut image, BinaryAlpha { threshold: None, matte: Some(RGBA8::new(0, 0, 255, 255)) });
+    assert_eq!(&[RGBA8::new(200, 0, 0, 0), RGBA8::new(40, 0, 204, 255), RGBA8::new(200, 0, 0, 255)], image.buf().as_slice());
+}
+
+#[test]
+fn crop_keeps_center() {
+    let image = ImgVec::new((0..5u8).collect(), 5, 1);
+    assert_eq!(&[1, 2, 3], center_cropped(image, 3, 1).buf().as_slice());
+}
+
+#[test]
+fn encode_in_one_call() {
+    let frames = (0..4u8).map(|i| test_frame(i, 4, 4));
+    let mut out = Vec::new();
+    let summary = encode(Settings::default(), frames, &mut out).unwrap();
+    assert_eq!(4, summary.frames_written);
+    assert!(out.starts_with(b"GIF89a"));
+}
+
+#[test]
+fn collector_is_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Collector>();
+}
+
+#[test]
+fn frame_filter_skips_frames() {
+    let (collector, mut writer) = new(Settings::default()).unwrap();
+    writer.set_frame_filter(|image, _| image.buf()[0].r != 60);
+    for i in 0..4u8 {
+        let (image, pts) = test_frame(i, 4, 4);
+        collector.add_frame_rgba(i.into(), image, pts).unwrap();
+    }
+    drop(collector);
+    let summary = writer.write(Vec::new(), &mut NoProgress {}).unwrap();
+    assert_eq!(3, summary.frames_written);
+}
+
+#[test]
+fn motion_blur_blends_dropped_frames() {
+    let settings = Settings {
+        adaptive_frame_rate: Some(AdaptiveFrameRate { min_delay: 0.05, max_delay: 0.25 }),
+        motion_blur: true,
+        ..Settings::default()
+    };
+    let frames = [(0, 0.), (200, 0.01), (50, 0.1)].iter().map(|&(r, pts)| (ImgVec::new(vec![RGBA8::new(r, 0, 0, 255); 4 * 4], 4, 4), pts));
+    let mut out = Vec::new();
+    assert_eq!(2, encode(settings, frames, &mut out).unwrap().frames_written);
+
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    let red = frame.palette.as_ref().unwrap()[usize::from(frame.buffer[0]) * 3];
+    assert!(red.abs_diff(100) <= 2, "{}", red);
+}
+
+#[test]
+fn adaptive_frame_rate_merges_still_frames() {
+    let settings = Settings {
+        adaptive_frame_rate: Some(AdaptiveFrameRate { min_delay: 0., max_delay: 0.25 }),
+        ..Settings::default()
+    };
+    // slightly noisy, but otherwise still
+    let frames = (0..8u8).map(|i| (ImgVec::new(vec![RGBA8::new(100 + i % 2, 0, 0, 255); 4 * 4], 4, 4), f64::from(i) / 10.));
+    let summary = encode(settings, frames, Vec::new()).unwrap();
+    // frames at 0s, 0.3s and 0.6s
+    assert_eq!(3, summary.frames_written);
+}
+
+#[test]
+fn merges_frames_below_threshold() {
+    let frames = (0..4u8).map(|i| {
+        let mut pixels = vec![RGBA8::new(0, 0, 0, 255); 10 * 10];
+        pixels[0] = RGBA8::new(i * 60, 0, 0, 255);
+        (ImgVec::new(pixels, 10, 10), f64::from(i) / 10.)
+    });
+    let summary = encode(Settings { merge_threshold: 1., ..Settings::default() }, frames.clone(), Vec::new()).unwrap();
+    assert_eq!(1, summary.frames_written);
+    let summary = encode(Settings { merge_threshold: 0.5, ..Settings::default() }, frames, Vec::new()).unwrap();
+    assert_eq!(4, summary.frames_written);
+}
+
+#[test]
+fn transparency_bias_keeps_similar_pixels() {
+    let pal = [RGBA8::new(0, 0, 0, 0), RGBA8::new(100, 100, 100, 255), RGBA8::new(200, 0, 0, 255)];
+    let screen = ImgVec::new(vec![RGBA8::new(103, 101, 98, 255); 3], 3, 1);
+    let mut image8 = ImgVec::new(vec![1, 2, 1], 3, 1);
+    bias_to_transparent(image8.as_mut(), &pal, 0, screen.as_ref(), 0);
+    assert_eq!([1, 2, 1], image8.buf()[..]);
+    bias_to_transparent(image8.as_mut(), &pal, 0, screen.as_ref(), 50);
+    assert_eq!([0, 2, 0], image8.buf()[..]);
+}
+
+#[test]
+fn background_shows_where_closer() {
+    let pal = [RGBA8::new(0, 0, 0, 0), RGBA8::new(100, 100, 100, 255), RGBA8::new(200, 0, 0, 255)];
+    let source = ImgVec::new(vec![RGBA8::new(40, 40, 40, 255), RGBA8::new(95, 95, 95, 255), RGBA8::new(40, 40, 40, 255)], 3, 1);
+    let screen = ImgVec::new(vec![RGBA8::new(40, 40, 40, 255), RGBA8::new(40, 40, 40, 255), RGBA8::new(40, 40, 40, 0)], 3, 1);
+    let mut image8 = ImgVec::new(vec![1, 1, 1], 3, 1);
+    show_background(image8.as_mut(), &pal, source.as_ref(), screen.as_ref());
+    assert_eq!([0, 1, 1], image8.buf()[..]);
+}
+
+#[test]
+fn pixel_art_keeps_colors() {
+    let colors = [RGBA8::new(255, 0, 0, 255), RGBA8::new(250, 0, 0, 255), RGBA8::new(0, 0, 255, 255)];
+    let frames = (0..3).map(|i| (ImgVec::new((0..64).map(|p| colors[(p + i) % 3]).collect(), 8, 8), i as f64 / 10.));
+    let settings = Settings { pixel_art: true, quality: 50, ..Settings::default() };
+    let mut out = Vec::new();
+    encode(settings, frames, &mut out).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = decoder.read_info(&out[..]).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    assert!(frame.buffer.as_rgba().iter().all(|px| colors.contains(px)));
+}
+
+#[test]
+fn limits_palette_size() {
+    let frames = (0..3u8).map(|i| {
+        let pixels = (0..16 * 16u32).map(|p| RGBA8::new(p as u8, (p * 7) as u8 ^ i, 255 - p as u8, 255)).collect();
+        (ImgVec::new(pixels, 16, 16), f64::from(i) / 10.)
+    });
+    let mut out = Vec::new();
+    encode(Settings { max_colors: 8, ..Settings::default() }, frames, &mut out).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        let palette = frame.palette.as_ref().unwrap();
+        assert!(palette.len() <= 8 * 3, "{}", palette.len() / 3);
+    }
+}
+
+#[test]
+fn global_palette_is_same_in_every_frame() {
+    let frames = (0..4u8).map(|i| {
+        let pixels = (0..16 * 16u32).map(|p| RGBA8::new(i * 60, p as u8, 255 - p as u8, 255)).collect();
+        (ImgVec::new(pixels, 16, 16), f64::from(i) / 10.)
+    });
+    let mut out = Vec::new();
+    encode(Settings { global_palette: true, max_colors: 16, ..Settings::default() }, frames, &mut out).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let mut palettes = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        let mut colors: Vec<_> = frame.palette.as_ref().unwrap().chunks(3).map(|c| c.to_vec()).collect();
+        colors.sort();
+        colors.dedup();
+        palettes.push(colors);
+    }
+    assert_eq!(4, palettes.len());
+    assert!(palettes[2..].iter().all(|p| p == &palettes[1]));
+}
+
+#[test]
+fn keeps_fixed_colors() {
+    let accent = RGBA8::new(255, 0, 128, 255);
+    let (collector, mut writer) = new(Settings { max_colors: 8, ..Settings::default() }).unwrap();
+    writer.set_fixed_colors(&[accent, RGBA8::new(1, 2, 3, 0)]).unwrap();
+    let adding = thread::spawn(move || {
+        for i in 0..2u8 {
+            let mut pixels: Vec<_> = (0..32 * 32u32).map(|p| RGBA8::new(0, (p / 4) as u8, i * 100, 255)).collect();
+            pixels[0] = RGBA8::new(250, 5, 130, 255);
+            collector.add_frame_rgba(i.into(), ImgVec::new(pixels, 32, 32), f64::from(i) / 10.).unwrap();
+        }
+    });
+    let mut out = Vec::new();
+    writer.write(&mut out, &mut progress::NoProgress {}).unwrap();
+    adding.join().unwrap();
+
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    assert!(frame.palette.as_ref().unwrap().chunks(3).any(|c| c == [255, 0, 128]));
+}
+
+#[test]
+fn dithering_strength_is_independent_of_quality() {
+    assert!((Settings::default().dithering_level() - 100. / 150.).abs() < 0.001);
+    let flat = Settings { quality: 100, dithering_strength: Some(0.), ..Settings::default() };
+    assert_eq!(0., flat.dithering_level());
+    let noisy = Settings { quality: 30, dithering_strength: Some(2.), ..Settings::default() };
+    assert_eq!(1., noisy.dithering_level());
+    encode(flat, (0..2).map(|i| (ImgVec::new(vec![RGBA8::new(i * 9, 0, 0, 255); 4], 2, 2), f64::from(i))), Vec::new()).unwrap();
+}
+
+#[test]
+fn uniform_quality_includes_first_frame() {
+    let image = ImgVec::new(vec![RGBA8::new(10, 20, 30, 255); 4], 2, 2);
+    let quality = |settings: &Settings| Writer::quantize(image.as_ref(), &[255; 4], false, None, &[], settings).unwrap().0.quality().1;
+    assert_eq!(100, quality(&Settings { quality: 60, ..Settings::default() }));
+    assert_eq!(80, quality(&Settings { quality: 60, uniform_quality: true, ..Settings::default() }));
+}
+
+#[test]
+fn linear_gamma_encodes() {
+    assert_eq!(0., Settings { quantization_gamma: Some(f64::NAN), ..Settings::default() }.gamma());
+    let dark_colors = |quantization_gamma| {
+        let mut out = Vec::new();
+        encode(Settings { quantization_gamma, shared_palette_colors: 64, ..Settings::default() }, (0..3).map(gradient_test_frame), &mut out).unwrap();
+        frame_palettes(&out).iter().map(|palette| palette.chunks(3).filter(|rgb| rgb[0] < 64).count()).sum::<usize>()
+    };
+    // linear values are darker than they look as sRGB, so more colors are spent on the darks
+    assert!(dark_colors(Some(1.)) > dark_colors(None));
+}
+
+#[test]
+fn palette_interval_reuses_palette() {
+    // the same colors moving around
+    let frames = (0..5u16).map(|i| {
+        let pixels = (0..16 * 16u16).map(|n| (n + i * 16) as u8).map(|v| RGBA8::new(v, 0, 255 - v, 255)).collect();
+        (ImgVec::new(pixels, 16, 16), f64::from(i) / 10.)
+    });
+    let mut out = Vec::new();
+    let summary = encode(Settings { palette_interval: 3, ..Settings::default() }, frames, &mut out).unwrap();
+    assert_eq!(5, summary.frames_written);
+
+    let palettes = frame_palettes(&out);
+    assert_eq!(palettes[1], palettes[2]);
+    assert_ne!(palettes[2], palettes[3]);
+}
+
+#[test]
+fn reuses_palette_for_small_changes_only_when_asked() {
+    // a block appears on a page with many colors, and then a few of its pixels swap colors
+    let frames = || (0..4usize).map(|i| {
+        let mut img = ImgVec::new((0..32 * 32).map(|n| RGBA8::new((n % 32 * 8) as u8, (n / 32 * 8) as u8, 128, 255)).collect::<Vec<_>>(), 32, 32);
+        if i > 0 {
+            let colors = [RGBA8::new(0, 0, 128, 255), RGBA8::new(255, 255, 0, 255), RGBA8::new(255, 0, 0, 255)];
+            for y in 8..16usize {
+                for x in 8..16usize {
+                    img[(x, y)] = colors[(y - 8) / 3];
+                }
+            }
+            // first the blue and red rows swap a few pixels, then the yellow and red ones
+            let swaps = [(8, 8..10), (11, 12..16)];
+            for (row, xs) in swaps.iter().take(i - 1).cloned() {
+                for x in xs {
+                    let top = img[(x, row)];
+                    img[(x, row)] = img[(x, 15usize)];
+                    img[(x, 15usize)] = top;
+                }
+            }
+        }
+        (img, i as f64 / 10.)
+    });
+    let palettes = |reuse| {
+        let mut out = Vec::new();
+        encode(Settings { reuse_palette_for_small_changes: reuse, ..Settings::default() }, frames(), &mut out).unwrap();
+        frame_palettes(&out)
+    };
+    let reused = palettes(true);
+    assert_eq!(4, reused.len());
+    assert_eq!(reused[2], reused[3]);
+    let fresh = palettes(false);
+    assert_eq!(4, fresh.len());
+    assert_ne!(fresh[2], fresh[3]);
+}
+
+#[test]
+fn small_changes_fit_palette() {
+    let image = ImgVec::new((0..100).map(|n| RGBA8::new(n, 0, 0, 255)).collect(), 10, 10);
+    let palette = [RGBA8::new(0, 0, 0, 255), RGBA8::new(50, 0, 0, 255), RGBA8::new(0, 0, 0, 0)];
+    let mut importance_map = vec![0; 100];
+    importance_map[0] = 255;
+    importance_map[50] = 10;
+    assert!(changes_fit_palette(image.as_ref(), &importance_map, &palette, 80));
+    importance_map[99] = 255;
+    assert!(!changes_fit_palette(image.as_ref(), &importance_map, &palette, 80));
+    assert!(!changes_fit_palette(image.as_ref(), &[255; 100], &palette, 80 * 100 * 100));
+    assert!(!changes_fit_palette(image.as_ref(), &[0; 100], &palette, 80));
+}
+
+#[test]
+fn transparent_index_is_first() {
+    let mut image8 = ImgVec::new(vec![0, 1, 2], 3, 1);
+    let mut pal = vec![RGBA8::new(1, 1, 1, 255), RGBA8::new(2, 2, 2, 255), RGBA8::new(0, 0, 0, 0)];
+    let mut transparent_index = Some(2);
+    move_transparent_to_first(&mut image8, &mut pal, &mut transparent_index);
+    assert_eq!((Some(0), &[2, 1, 0][..]), (transparent_index, image8.buf().as_slice()));
+    assert_eq!(RGBA8::new(1, 1, 1, 255), pal[2]);
+
+    let frames = (0..3u8).map(|i| test_frame(i, 4, 4));
+    let mut out = Vec::new();
+    encode(Settings { transparent_index_first: true, ..Settings::default() }, frames, &mut out).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!(Some(0), frame.transparent);
+    }
+}
+
+#[test]
+fn few_colors_skip_quantization() {
+    let image = ImgVec::new(vec![RGBA8::new(1, 2, 3, 255), RGBA8::new(9, 9, 9, 3), RGBA8::new(1, 2, 3, 255), RGBA8::new(4, 5, 6, 200)], 4, 1);
+    let indexed = IndexedImage::with_exact_colors(image.as_ref(), 3).unwrap();
+    assert_eq!(&[0, 1, 0, 2], indexed.pixels.buf().as_slice());
+    assert_eq!(vec![RGBA8::new(1, 2, 3, 255), RGBA8::new(0, 0, 0, 0), RGBA8::new(4, 5, 6, 255)], indexed.palette);
+    assert!(IndexedImage::with_exact_colors(image.as_ref(), 2).is_none());
+
+    let colors = [RGBA8::new(17, 200, 3, 255), RGBA8::new(240, 1, 77, 255), RGBA8::new(0, 0, 0, 255)];
+    let frames = (0..2).map(|i| (ImgVec::new((0..64).map(|p| colors[(p / 8 + i) % 3]).collect(), 8, 8), i as f64 / 10.));
+    let mut out = Vec::new();
+    encode(Settings { quality: 50, ..Settings::default() }, frames, &mut out).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    let pal = frame.palette.as_ref().unwrap();
+    assert!(colors.iter().all(|c| pal.chunks(3).any(|p| p == [c.r, c.g, c.b])));
+}
+
+#[test]
+fn palette_family_restricts_colors() {
+    let family = [RGBA8::new(0, 0, 0, 255), RGBA8::new(255, 0, 0, 255), RGBA8::new(0, 0, 255, 255)];
+    let snapped = snapped_to_family(&[RGBA8::new(200, 10, 10, 255), RGBA8::new(5, 5, 5, 0), RGBA8::new(250, 0, 5, 255), RGBA8::new(1, 2, 3, 255)], &[RGBA8::new(1, 2, 3, 255)], &family);
+    assert_eq!(vec![family[1], RGBA8::new(0, 0, 0, 0), RGBA8::new(1, 2, 3, 255)], snapped);
+    assert_eq!(216, web_safe_colors().len());
+
+    for family in [web_safe_colors(), (0..=255).map(|v| RGBA8::new(v, v / 2, 255 - v, 255)).collect()] {
+        let frames: Vec<_> = (0..2u8).map(|i| {
+            let pixels = (0..32 * 32u16).map(|n| RGBA8::new((n % 32 * 8) as u8, (n / 32 * 8) as u8, i * 100, 255)).collect();
+            (ImgVec::new(pixels, 32, 32), f64::from(i) / 10.)
+        }).collect();
+        let (collector, mut writer) = new(Settings::default()).unwrap();
+        writer.set_palette_family(&family);
+        let mut out = Vec::new();
+        thread::scope(|s| {
+            s.spawn(move || {
+                for (i, (image, pts)) in frames.into_iter().enumerate() {
+                    collector.add_frame_rgba(i, image, pts).unwrap();
+                }
+            });
+            writer.write(&mut out, &mut NoProgress {}).unwrap();
+        });
+        let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            let pal = frame.palette.as_ref().unwrap();
+            assert!(frame.buffer.iter().filter(|&&i| Some(i) != frame.transparent)
+                .map(|&i| &pal[usize::from(i) * 3..][..3])
+                .all(|c| family.contains(&RGBA8::new(c[0], c[1], c[2], 255))));
+        }
+    }
+}
+
+#[test]
+fn durations_become_delays() {
+    let (collector, writer) = new(Settings::default()).unwrap();
+    let mut out = Vec::new();
+    thread::scope(|s| {
+        s.spawn(move || {
+            for (i, ms) in [(2, 500), (0, 100), (1, 250)] {
+                let image = ImgVec::new(vec![RGBA8::new(i as u8 * 80, 0, 0, 255); 4 * 4], 4, 4);
+                collector.add_frame_with_duration(i, image, Duration::from_millis(ms)).unwrap();
+            }
+        });
+        writer.write(&mut out, &mut NoProgress {}).unwrap();
+    });
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let delays: Vec<_> = std::iter::from_fn(|| decoder.read_next_frame().unwrap().map(|f| f.delay)).collect();
+    assert_eq!(vec![10, 25, 50], delays);
+}
+
+#[test]
+fn max_fps_merges_frames() {
+    let frames = (0..10u8).map(|i| (ImgVec::new(vec![RGBA8::new(i * 20, 0, 0, 255); 4 * 4], 4, 4), f64::from(i) / 100.));
+    let mut out = Vec::new();
+    let summary = encode(Settings { max_fps: Some(30.), ..Settings::default() }, frames, &mut out).unwrap();
+    assert_eq!(3, summary.frames_written);
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let delays: Vec<_> = std::iter::from_fn(|| decoder.read_next_frame().unwrap().map(|f| f.delay)).collect();
+    assert_eq!(vec![4, 4, 5], delays);
+}
+
+#[test]
+fn near_duplicates_are_merged() {
+    let noisy = |seed: u32| ImgVec::new((0..16 * 16u32).map(|n| {
+        let v = 100 + ((n * 7 + seed * 13) % 5) as u8;
+        RGBA8::new(v, v, v, 255)
+    }).collect(), 16, 16);
+    assert!(is_near_duplicate(noisy(0).as_ref(), noisy(1).as_ref(), 3.));
+    assert!(!is_near_duplicate(noisy(0).as_ref(), noisy(1).as_ref(), 1.));
+
+    let frames = (0..4).map(|i| (if i < 3 { noisy(i) } else { ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); 16 * 16], 16, 16) }, f64::from(i) / 10.));
+    let summary = encode(Settings { duplicate_threshold: 3., ..Settings::default() }, frames, Vec::new()).unwrap();
+    assert_eq!(2, summary.frames_written);
+}
+
+#[test]
+fn keeps_duplicate_frames() {
+    let frames = || (0..4u8).map(|i| (ImgVec::new(vec![RGBA8::new(if i < 3 { 0 } else { 200 }, 50, 0, 255); 4 * 4], 4, 4), f64::from(i) / 10.));
+    let delays = |keep_duplicates| {
+        let mut out = Vec::new();
+        encode(Settings { keep_duplicates, ..Settings::default() }, frames(), &mut out).unwrap();
+        let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+        std::iter::from_fn(|| decoder.read_next_frame().unwrap().map(|f| f.delay)).collect::<Vec<_>>()
+    };
+    assert_eq!(vec![10, 10, 10, 10], delays(true));
+    assert_eq!(2, delays(false).len());
+}
+
+#[test]
+fn stable_dithering_encodes() {
+    // the top half has too many colors to fit the palette, and only the bottom half changes
+    let frames = || (0..3u8).map(|i| {
+        let pixels = (0..32 * 32u32).map(|n| if n < 32 * 16 { RGBA8::new((n * 7) as u8, (n / 2) as u8, (n * 3) as u8, 255) } else { RGBA8::new(i * 60, (n % 32 * 8) as u8, 0, 255) }).collect();
+        (ImgVec::new(pixels, 32, 32), f64::from(i) / 10.)
+    });
+    let top_halves = |stable_dithering| {
+        let mut out = Vec::new();
+        encode(Settings { stable_dithering, ..Settings::default() }, frames(), &mut out).unwrap();
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decoder.read_info(&out[..]).unwrap();
+        let mut screen = gif_dispose::Screen::new_decoder(&decoder);
+        let mut halves = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            screen.blit_frame(frame).unwrap();
+            halves.push(screen.pixels.buf()[..32 * 16].to_vec());
+        }
+        halves
+    };
+    let stable = top_halves(true);
+    assert_eq!(3, stable.len());
+    assert!(stable.iter().all(|half| *half == stable[0]));
+    let dithered_again = top_halves(false);
+    assert!(dithered_again.iter().any(|half| *half != dithered_again[0]));
+}
+
+#[test]
+fn shared_palette_encodes() {
+    let colors_in_every_palette = |shared_palette_colors| {
+        let mut out = Vec::new();
+        encode(Settings { shared_palette_colors, ..Settings::default() }, (0..3).map(gradient_test_frame), &mut out).unwrap();
+        let palettes = frame_palettes(&out);
+        assert_eq!(3, palettes.len());
+        palettes[0].chunks(3).filter(|rgb| palettes[1..].iter().all(|palette| palette.chunks(3).any(|other| other == *rgb))).count()
+    };
+    assert!(colors_in_every_palette(128) >= 128);
+    assert_eq!(0, colors_in_every_palette(0));
+}
+
+#[test]
+fn trims_unchanged_columns() {
+    let pal = [RGBA8::new(0, 0, 0, 0), RGBA8::new(10, 10, 10, 255), RGBA8::new(200, 0, 0, 255)];
+    let screen = ImgVec::new(vec![RGBA8::new(10, 10, 10, 255); 4 * 3], 4, 3);
+    let image8 = ImgVec::new(vec![
+        0, 1, 0, 0,
+        0, 2, 2, 1,
+        1, 0, 0, 0,
+    ], 4, 3);
+    let (left, top, trimmed) = trim_image(image8.clone(), &pal, Some(0), screen.as_ref(), None, false).unwrap();
+    assert_eq!((0, 1, 4, 1), (left, top, trimmed.width(), trimmed.height()));
+    let (left, top, trimmed) = trim_image(image8, &pal, Some(0), screen.as_ref(), None, true).unwrap();
+    assert_eq!((1, 1, 2, 1), (left, top, trimmed.width(), trimmed.height()));
+
+    let mut trimmed = trimmed;
+    let mut pal = pal.to_vec();
+    let mut transparent_index = Some(0);
+    remove_unused_colors(&mut trimmed, &mut pal, &mut transparent_index);
+    assert_eq!(vec![RGBA8::new(200, 0, 0, 255)], pal);
+    assert_eq!(None, transparent_index);
+    assert_eq!([0, 0], trimmed.buf()[..]);
+}
+
+#[test]
+fn repeat_none_has_no_loop_extension() {
+    let frames = || (0..2u8).map(|i| test_frame(i, 4, 4));
+    let has_netscape = |repeat| {
+        let mut out = Vec::new();
+        encode(Settings { repeat, ..Settings::default() }, frames(), &mut out).unwrap();
+        out.windows(11).any(|w| w == b"NETSCAPE2.0")
+    };
+    assert!(has_netscape(Repeat::Infinite));
+    assert!(has_netscape(Repeat::Finite(2)));
+    assert!(!has_netscape(Repeat::Finite(0)));
+    assert!(!has_netscape(Repeat::None));
+}
+
+#[test]
+fn tags_follow_skipped_frames() {
+    let (collector, writer) = new(Settings::default()).unwrap();
+    for (i, &r) in [0, 0, 60].iter().enumerate() {
+        collector.add_frame_rgba_tagged(i, ImgVec::new(vec![RGBA8::new(r, 0, 0, 255); 4 * 4], 4, 4), i as f64 / 10., format!("f{}", i)).unwrap();
+    }
+    drop(collector);
+    let summary = writer.write(Vec::new(), &mut NoProgress {}).unwrap();
+    let tags: Vec<Vec<&str>> = summary.frame_tags.iter().map(|t| t.iter().map(|t| &**t).collect()).collect();
+    assert_eq!(vec![vec!["f0", "f1"], vec!["f2"]], tags);
+}
+
+#[test]
+fn frame_quality_overrides_settings() {
+    let image = ImgVec::new(vec![RGBA8::new(10, 20, 30, 255); 4], 2, 2);
+    let quality = |frame_quality| Writer::quantize(image.as_ref(), &[255; 4], true, frame_quality, &[], &Settings::default()).unwrap().0.quality().1;
+    assert_eq!(100, quality(None));
+    assert_eq!(40, quality(Some(30)));
+
+    let (collector, writer) = new(Settings::default()).unwrap();
+    for i in 0..2u8 {
+        let options = FrameOptions { tag: Some(format!("f{}", i).into()), quality: Some(50), ..FrameOptions::default() };
+        let (image, pts) = test_frame(i, 4, 4);
+        collector.add_frame_rgba_with_options(i.into(), image, pts, options).unwrap();
+    }
+    drop(collector);
+    let summary = writer.write(Vec::new(), &mut NoProgress {}).unwrap();
+    assert_eq!(2, summary.frame_tags.len());
+}
+
+#[test]
+fn importance_map_is_resized_with_frame() {
+    let geometry = Geometry::new(&Settings { width: Some(2), height: Some(1), fit: ResizeFit::Letterbox(RGBA8::new(0, 0, 0, 0)), ..Settings::default() });
+    let map = Collector::resized_importance_map(ImgVec::new(vec![200; 4], 2, 2), geometry).unwrap();
+    assert_eq!((2, 1), (map.width(), map.height()));
+    assert_eq!(&[200, 0], map.buf().as_slice());
+
+    let (collector, writer) = new(Settings::default()).unwrap();
+    assert!(collector.add_frame_rgba_with_importance(0, ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); 4], 2, 2), ImgVec::new(vec![0; 2], 2, 1), 0.).is_err());
+    for i in 0..2u8 {
+        collector.add_frame_rgba_with_importance(i.into(), ImgVec::new(vec![RGBA8::new(i * 60, 0, 0, 255); 4], 2, 2), ImgVec::new(vec![255, 0, 0, 255], 2, 2), f64::from(i)).unwrap();
+    }
+    drop(collector);
+    assert_eq!(2, writer.write(Vec::new(), &mut NoProgress {}).unwrap().frames_written);
+}
+
+#[test]
+fn regions_keep_importance() {
+    let mut importance_map = vec![200; 4 * 3];
+    let regions = [Rect { left: 1, top: 1, width: 2, height: 9 }, Rect { left: 9, top: 0, width: 1, height: 1 }];
+    lower_importance_outside(&mut importance_map, 4, &regions, 4);
+    assert_eq!(vec![50, 50, 50, 50, 50, 200, 200, 50, 50, 200, 200, 50], importance_map);
+}
+
+#[test]
+fn flushes_after_complete_frames() {
+    struct FlushLog<'a>(&'a mut Vec<usize>, usize);
+    impl Write for FlushLog<'_> {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.1 += data.len();
+            Ok(data.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.push(self.1);
+            Ok(())
+        }
+    }
+
+    let frames = (0..3u8).map(|i| test_frame(i, 4, 4));
+    let mut flushes = Vec::new();
+    encode(Settings { flush: FlushPolicy::EveryFrame, ..Settings::default() }, frames, FlushLog(&mut flushes, 0)).unwrap();
+    // after each frame, and at the end
+    assert_eq!(4, flushes.len());
+    assert!(flushes.windows(2).all(|w| w[0] < w[1]));
+}
+
+#[test]
+fn progressive_encode_makes_preview_first() {
+    let frames: Vec<_> = (0..3u8).map(|i| test_frame(i, 8, 8)).collect();
+    let mut stages = Vec::new();
+    let summary = encode_progressive(Settings::default(), &frames, |stage, gif| {
+        assert!(gif.starts_with(b"GIF89a"));
+        // logical screen width
+        stages.push((stage, gif[6]));
+    }).unwrap();
+    assert_eq!(3, summary.frames_written);
+    assert_eq!(vec![(EncodeStage::Preview, 4), (EncodeStage::Final, 8)], stages);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn encodes_with_low_priority() {
+    extern "C" {
+        fn getpriority(which: std::os::raw::c_int, who: u32) -> std::os::raw::c_int;
+    }
+
+    let (collector, mut writer) = new(Settings { low_priority: true, ..Settings::default() }).unwrap();
+    let (niceness, niceness_recv) = crossbeam_channel::unbounded();
+    writer.set_thread_spawner(move |_, task| {
+        let niceness = niceness.clone();
+        thread::spawn(move || {
+            task();
+            // the "process" 0 is the calling thread
+            niceness.send(unsafe { getpriority(0, 0) }).unwrap();
+        });
+        Ok(())
+    });
+    for i in 0..2 {
+        let (image, pts) = test_frame(i, 4, 4);
+        collector.add_frame_rgba(i.into(), image, pts).unwrap();
+    }
+    drop(collector);
+    let summary = writer.write(Vec::new(), &mut NoProgress {}).unwrap();
+    assert_eq!(2, summary.frames_written);
+    let niceness: Vec<_> = niceness_recv.iter().take(3).collect();
+    assert!(niceness.iter().all(|&n| n >= 10), "{:?}", niceness);
+}
+
+#[test]
+fn rgb_frames_are_opaque() {
+    let (collector, _writer) = new(Settings { width: Some(4), ..Settings::default() }).unwrap();
+    let image = collector.prepare_frame_rgb(ImgVec::new(vec![RGB8::new(10, 20, 30); 8 * 8], 8, 8), 0.).unwrap();
+    assert_eq!((4, 4), (image.width(), image.height()));
+    assert!(image.pixels().all(|px| px == RGBA8::new(10, 20, 30, 255)));
+}
+
+#[test]
+fn bgra_is_swizzled() {
+    let bgra = [BGRA8 { b: 1, g: 2, r: 3, a: 4 }, BGRA8 { b: 5, g: 6, r: 7, a: 8 }, BGRA8 { b: 0, g: 0, r: 0, a: 0 }];
+    let image = bgra_to_rgba(ImgRef::new_stride(&bgra[..], 1, 2, 2));
+    assert_eq!(&[RGBA8::new(3, 2, 1, 4), RGBA8::new(0, 0, 0, 0)][..], image.buf());
+}
+
+#[test]
+#[cfg(feature = "image")]
+fn adds_dynamic_images() {
+    let gray16 = image::DynamicImage::ImageLuma16(image::ImageBuffer::from_pixel(4, 4, image::Luma([0x8000u16])));
+    let la8 = image::DynamicImage::ImageLumaA8(image::ImageBuffer::from_pixel(4, 4, image::LumaA([200u8, 255])));
+    let (collector, writer) = new(Settings::default()).unwrap();
+    collector.add_frame_image(0, gray16, 0.).unwrap();
+    collector.add_frame_image(1, la8, 0.1).unwrap();
+    drop(collector);
+    assert_eq!(2, writer.write(Vec::new(), &mut NoProgress {}).unwrap().frames_written);
+}
+
+#[test]
+fn indexed_frames_keep_colors() {
+    let palette = [RGBA8::new(1, 2, 3, 255), RGBA8::new(250, 100, 7, 255), RGBA8::new(9, 9, 9, 0)];
+    let (collector, writer) = new(Settings::default()).unwrap();
+    collector.add_frame_indexed(0, ImgVec::new(vec![0, 1, 1, 0], 2, 2), &palette, 0.).unwrap();
+    collector.add_frame_indexed(1, ImgVec::new(vec![0, 1, 1, 1], 2, 2), &palette, 0.1).unwrap();
+    assert!(collector.add_frame_indexed(2, ImgVec::new(vec![3; 4], 2, 2), &palette, 0.2).is_err());
+    drop(collector);
+    let mut out = Vec::new();
+    writer.write(&mut out, &mut NoProgress {}).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = decoder.read_info(&out[..]).unwrap();
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    assert_eq!(&[1, 2, 3, 255, 250, 100, 7, 255], &frame.buffer[..8]);
+    let frame = decoder.read_next_frame().unwrap().unwrap();
+    assert_eq!(&[250, 100, 7, 255], &frame.buffer[frame.buffer.len() - 4..]);
+}
+
+#[test]
+fn indexed_palettes_follow_alpha_and_posterize_settings() {
+    let palette = [RGBA8::new(200, 0, 0, 100), RGBA8::new(200, 0, 0, 200), RGBA8::new(100, 100, 100, 255), RGBA8::new(9, 9, 9, 0)];
+    let prepared = |settings| new(settings).unwrap().0.prepare_palette(&palette);
+
+    let t = RGBA8::new(0, 0, 0, 0);
+    assert_eq!(None, prepared(Settings::default()));
+    assert_eq!(Some(vec![t, RGBA8::new(200, 0, 0, 255), palette[2], t]), prepared(Settings { alpha_threshold: Some(150), ..Settings::default() }));
+    assert_eq!(Some(vec![t, t, palette[2], t]), prepared(Settings { alpha_threshold: Some(250), ..Settings::default() }));
+    let matte = RGBA8::new(0, 0, 255, 255);
+    assert_eq!(Some(vec![RGBA8::new(78, 0, 155, 255), RGBA8::new(157, 0, 55, 255), palette[2], t]), prepared(Settings { matte: Some(matte), ..Settings::default() }));
+    let posterized = prepared(Settings { alpha_threshold: Some(128), posterize: Some(1), ..Settings::default() });
+    assert_eq!(Some(vec![t, RGBA8::new(255, 0, 0, 255), RGBA8::new(0, 0, 0, 255), t]), posterized);
+}
+
+#[test]
+fn stalled_input_fails() {
+    let settings = Settings { input_timeout: Some(Duration::from_millis(50)), ..Settings::default() };
+    let (collector, writer) = new(settings).unwrap();
+    collector.add_frame_rgba(0, ImgVec::new(vec![RGBA8::new(0, 0, 0, 255); 4], 2, 2), 0.).unwrap();
+    // the collector is still alive, but nothing is added
+    match writer.write(Vec::new(), &mut NoProgress {}) {
+        Err(Error::InputStalled { last_frame: Some(0), .. }) => {},
+        other => panic!("{:?}", other.map(|s| s.frames_written)),
+    }
+    drop(collector);
+}
+
+#[test]
+fn opaque_deltas_have_no_transparency() {
+    let frames = (0..3usize).map(|i| {
+        let mut img = ImgVec::new(vec![RGBA8::new(40, 80, 120, 255); 16 * 16], 16, 16);
+        img[(i * 4, i * 4)] = RGBA8::new(255, 255, 0, 255);
+        (img, i as f64 / 10.)
+    });
+    let mut out = Vec::new();
+    encode(Settings { opaque_deltas: true, ..Settings::default() }, frames, &mut out).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    let mut sizes = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!(None, frame.transparent);
+        sizes.push((frame.width, frame.height));
+    }
+    assert_eq!(3, sizes.len());
+    assert_eq!((16, 16), sizes[0]);
+    assert!(sizes[1].1 < 16, "{:?}", sizes);
+}
+
+#[test]
+fn uses_custom_thread_spawner() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let spawned = Arc::new(AtomicUsize::new(0));
+    let (collector, mut writer) = new(Settings::default()).unwrap();
+    let counter = spawned.clone();
+    writer.set_thread_spawner(move |name, task| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        thread::Builder::new().name(format!("custom-{}", name)).spawn(task).map(drop)
+    });
+    let t = thread::spawn(move || writer.write(Vec::new(), &mut NoProgress {}));
+    for i in 0..3u8 {
+        collector.add_frame_rgba(i.into(), ImgVec::new(vec![RGBA8::new(i * 50, 0, 0, 255); 4], 2, 2), f64::from(i) / 10.).unwrap();
+    }
+    drop(collector);
+    assert_eq!(3, t.join().unwrap().unwrap().frames_written);
+    assert_eq!(3, spawned.load(Ordering::SeqCst));
+
+    // a spawner that drops the task
+    let (collector, mut writer) = new(Settings::default()).unwrap();
+    writer.set_thread_spawner(|_, _| Ok(()));
+    drop(collector);
+    assert!(writer.write(Vec::new(), &mut NoProgress {}).is_err());
+}
@@ -0,0 +1,104 @@
+//! Frames from CoreVideo pixel buffers, as given by AVFoundation and ScreenCaptureKit
+
+use crate::capture::CopiedPixels;
+use crate::error::*;
+use crate::{Collector, FrameOptions};
+use imgref::*;
+use rgb::*;
+use std::os::raw::c_void;
+
+type CVPixelBufferRef = *mut c_void;
+
+const LOCK_READ_ONLY: u64 = 1;
+const FORMAT_32BGRA: u32 = u32::from_be_bytes(*b"BGRA");
+const FORMAT_NV12_VIDEO_RANGE: u32 = u32::from_be_bytes(*b"420v");
+const FORMAT_NV12_FULL_RANGE: u32 = u32::from_be_bytes(*b"420f");
+
+#[link(name = "CoreVideo", kind = "framework")]
+extern "C" {
+    fn CVPixelBufferLockBaseAddress(buffer: CVPixelBufferRef, flags: u64) -> i32;
+    fn CVPixelBufferUnlockBaseAddress(buffer: CVPixelBufferRef, flags: u64) -> i32;
+    fn CVPixelBufferGetPixelFormatType(buffer: CVPixelBufferRef) -> u32;
+    fn CVPixelBufferGetWidth(buffer: CVPixelBufferRef) -> usize;
+    fn CVPixelBufferGetHeight(buffer: CVPixelBufferRef) -> usize;
+    fn CVPixelBufferGetBaseAddress(buffer: CVPixelBufferRef) -> *mut c_void;
+    fn CVPixelBufferGetBytesPerRow(buffer: CVPixelBufferRef) -> usize;
+    fn CVPixelBufferGetBaseAddressOfPlane(buffer: CVPixelBufferRef, plane: usize) -> *mut c_void;
+    fn CVPixelBufferGetBytesPerRowOfPlane(buffer: CVPixelBufferRef, plane: usize) -> usize;
+    fn CVPixelBufferGetWidthOfPlane(buffer: CVPixelBufferRef, plane: usize) -> usize;
+    fn CVPixelBufferGetHeightOfPlane(buffer: CVPixelBufferRef, plane: usize) -> usize;
+}
+
+/// Unlocks the buffer when dropped
+struct Locked(CVPixelBufferRef);
+
+impl Drop for Locked {
+    fn drop(&mut self) {
+        unsafe {
+            CVPixelBufferUnlockBaseAddress(self.0, LOCK_READ_ONLY);
+        }
+    }
+}
+
+impl Collector {
+    /// Add a frame from a `CVPixelBufferRef` in BGRA (`kCVPixelFormatType_32BGRA`) or NV12 (`420v` or `420f`) format.
+    ///
+    /// Pixels are copied, so the buffer can be reused as soon as this function returns.
+    /// Conversion to RGBA and resizing happen later on the encoder's thread, so the capture thread isn't held up.
+    /// Frame index starts at 0, and the presentation timestamp is in seconds (e.g. `CMTimeGetSeconds()` of the sample buffer).
+    ///
+    /// # Safety
+    ///
+    /// `pixel_buffer` must be a valid `CVPixelBufferRef`.
+    pub unsafe fn add_frame_cvpixelbuffer(&self, frame_index: usize, pixel_buffer: *mut c_void, presentation_timestamp: f64) -> CatResult<()> {
+        if pixel_buffer.is_null() {
+            return Err(Error::InvalidInput("Null CVPixelBuffer".into()));
+        }
+        let pixels = copy_pixels(pixel_buffer)?;
+        let frame = self.deferred_frame(move || pixels.into_rgba());
+        self.push(frame_index, frame, presentation_timestamp, FrameOptions::default())
+    }
+}
+
+unsafe fn copy_pixels(buffer: CVPixelBufferRef) -> CatResult<CopiedPixels> {
+    if CVPixelBufferLockBaseAddress(buffer, LOCK_READ_ONLY) != 0 {
+        return Err(Error::InvalidInput("Can't lock CVPixelBuffer".into()));
+    }
+    let _locked = Locked(buffer);
+
+    let width = CVPixelBufferGetWidth(buffer);
+    let height = CVPixelBufferGetHeight(buffer);
+    match CVPixelBufferGetPixelFormatType(buffer) {
+        FORMAT_32BGRA => {
+            let stride_bytes = CVPixelBufferGetBytesPerRow(buffer);
+            let base = CVPixelBufferGetBaseAddress(buffer) as *const u8;
+            if base.is_null() || width == 0 || height == 0 || stride_bytes % 4 != 0 || stride_bytes < width * 4 {
+                return Err(Error::InvalidInput("Unsupported CVPixelBuffer layout".into()));
+            }
+            let data = std::slice::from_raw_parts(base, stride_bytes * (height - 1) + width * 4);
+            let bgra = ImgRef::new_stride(data.as_bgra(), width, height, stride_bytes / 4);
+            Ok(CopiedPixels::Bgra(ImgVec::new(bgra.pixels().collect(), width, height)))
+        },
+        format @ (FORMAT_NV12_VIDEO_RANGE | FORMAT_NV12_FULL_RANGE) => {
+            let luma = plane(buffer, 0, 1)?;
+            let chroma = plane(buffer, 1, 2)?;
+            let luma = ImgRef::new_stride(luma.0, luma.1, luma.2, luma.3);
+            let chroma = ImgRef::new_stride(chroma.0, chroma.1 * 2, chroma.2, chroma.3);
+            Ok(CopiedPixels::nv12(luma, chroma, format == FORMAT_NV12_FULL_RANGE))
+        },
+        other => Err(Error::InvalidInput(format!("Unsupported CVPixelBuffer format {:?}", String::from_utf8_lossy(&other.to_be_bytes())))),
+    }
+}
+
+/// (data, width, height, stride in bytes)
+unsafe fn plane<'a>(buffer: CVPixelBufferRef, plane: usize, bytes_per_pixel: usize) -> CatResult<(&'a [u8], usize, usize, usize)> {
+    let base = CVPixelBufferGetBaseAddressOfPlane(buffer, plane) as *const u8;
+    let width = CVPixelBufferGetWidthOfPlane(buffer, plane);
+    let height = CVPixelBufferGetHeightOfPlane(buffer, plane);
+    let stride = CVPixelBufferGetBytesPerRowOfPlane(buffer, plane);
+    if base.is_null() || width == 0 || height == 0 || stride < width * bytes_per_pixel {
+        return Err(Error::InvalidInput("Unsupported CVPixelBuffer layout".into()));
+    }
+    let data = std::slice::from_raw_parts(base, stride * (height - 1) + width * bytes_per_pixel);
+    Ok((data, width, height, stride))
+}
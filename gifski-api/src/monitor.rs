@@ -0,0 +1,149 @@
+//! Live view of the encoding pipeline
+//!
+//! Frames pass through four queues: from the `Collector` to the frame differencing thread (`decode`),
+//! then to quantization (`quantize`), remapping (`remap`), and finally to the writer (`write`).
+//! If a queue is full, the stage feeding it is blocked, so the queue that stays full
+//! points to the slow stage right after it.
+
+use crate::error::CatResult;
+use crossbeam_channel::{Receiver, Sender};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Samples queue occupancy of a running encode. Cheap to clone, and can be used from any thread.
+///
+/// Get it from `Writer::monitor()`.
+#[derive(Clone, Default)]
+pub struct PipelineMonitor {
+    pub(crate) counters: Arc<PipelineCounters>,
+}
+
+/// State of all queues at the time of `PipelineMonitor::sample()`
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PipelineSnapshot {
+    /// Frames added to the `Collector`, waiting for frame differencing
+    pub decode: QueueSnapshot,
+    /// Frames waiting for palette quantization
+    pub quantize: QueueSnapshot,
+    /// Frames waiting to be remapped to their palette
+    pub remap: QueueSnapshot,
+    /// Frames waiting to be compressed and written
+    pub write: QueueSnapshot,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct QueueSnapshot {
+    /// Number of frames currently in the queue
+    pub queued: usize,
+    /// Maximum number of frames the queue holds before blocking
+    pub capacity: usize,
+    /// Total time the stage feeding this queue has spent waiting for space in it
+    pub blocked: Duration,
+}
+
+/// Time spent on processing frames in each stage of the pipeline
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StageTimings {
+    /// Comparing frames with each other
+    pub diff: StageTiming,
+    /// Palette generation
+    pub quantize: StageTiming,
+    /// Remapping and dithering frames to their palettes
+    pub remap: StageTiming,
+    /// Compressing and writing frames
+    pub write: StageTiming,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct StageTiming {
+    /// Number of frames processed by the stage
+    pub frames: usize,
+    /// Time spent on all frames
+    pub total: Duration,
+    /// Time spent on the slowest frame
+    pub max: Duration,
+}
+
+impl StageTiming {
+    /// Average time per frame
+    pub fn average(&self) -> Duration {
+        if self.frames > 0 {
+            self.total / self.frames as u32
+        } else {
+            Duration::default()
+        }
+    }
+
+    pub(crate) fn add(&mut self, elapsed: Duration) {
+        self.frames += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct PipelineCounters {
+    pub decode: QueueCounters,
+    pub quantize: QueueCounters,
+    pub remap: QueueCounters,
+    pub write: QueueCounters,
+}
+
+#[derive(Default)]
+pub(crate) struct QueueCounters {
+    queued: AtomicUsize,
+    capacity: AtomicUsize,
+    blocked_nanos: AtomicU64,
+}
+
+impl PipelineMonitor {
+    pub fn sample(&self) -> PipelineSnapshot {
+        let c = &self.counters;
+        PipelineSnapshot {
+            decode: c.decode.snapshot(),
+            quantize: c.quantize.snapshot(),
+            remap: c.remap.snapshot(),
+            write: c.write.snapshot(),
+        }
+    }
+}
+
+impl QueueCounters {
+    pub fn bounded<T>(&self, depth: usize) -> (Sender<T>, Receiver<T>) {
+        self.capacity.store(depth, Relaxed);
+        crossbeam_channel::bounded(depth)
+    }
+
+    pub fn set_capacity(&self, depth: usize) {
+        self.capacity.store(depth, Relaxed);
+    }
+
+    /// Sends, keeping track of how long it blocked
+    pub fn send<T>(&self, sender: &Sender<T>, msg: T) -> CatResult<()> {
+        self.timed(|| sender.send(msg))?;
+        Ok(())
+    }
+
+    /// Runs a blocking push into the queue
+    pub fn timed<R>(&self, push: impl FnOnce() -> R) -> R {
+        self.queued.fetch_add(1, Relaxed);
+        let start = Instant::now();
+        let res = push();
+        self.blocked_nanos.fetch_add(start.elapsed().as_nanos() as u64, Relaxed);
+        res
+    }
+
+    /// Frames have left the queue (or were dropped from it)
+    pub fn taken(&self, n: usize) {
+        self.queued.fetch_sub(n, Relaxed);
+    }
+
+    fn snapshot(&self) -> QueueSnapshot {
+        QueueSnapshot {
+            queued: self.queued.load(Relaxed),
+            capacity: self.capacity.load(Relaxed),
+            blocked: Duration::from_nanos(self.blocked_nanos.load(Relaxed)),
+        }
+    }
+}
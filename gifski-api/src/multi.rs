@@ -0,0 +1,91 @@
+//! Encoding several sizes of the same animation at once
+
+use crate::error::*;
+use crate::{new, Collector, Settings, Writer};
+use imgref::ImgVec;
+use rgb::RGBA8;
+#[cfg(feature = "png")]
+use std::path::PathBuf;
+
+/// Start encoding the same frames into several GIFs, e.g. full size and a thumbnail
+///
+/// Each `Settings` is for one output, usually differing only in `width` and `height`.
+/// Frames are decoded once, and resized for each output separately.
+///
+/// Each `Writer` has to be written on its own thread, since the collector
+/// waits for the slowest one.
+pub fn new_multi(settings: &[Settings]) -> CatResult<(MultiCollector, Vec<Writer>)> {
+    if settings.is_empty() {
+        return Err(Error::InvalidInput("At least one output is needed".into()));
+    }
+    let (collectors, writers) = settings.iter().map(|&s| new(s)).collect::<CatResult<Vec<_>>>()?
+        .into_iter().unzip();
+    Ok((MultiCollector { collectors }, writers))
+}
+
+/// Collects frames for all outputs of `new_multi()`
+///
+/// Like `Collector`, it must be dropped for the writers to finish.
+pub struct MultiCollector {
+    collectors: Vec<Collector>,
+}
+
+impl MultiCollector {
+    /// Same as `Collector::add_frame_rgba()`, for all outputs
+    pub fn add_frame_rgba(&self, frame_index: usize, image: ImgVec<RGBA8>, presentation_timestamp: f64) -> CatResult<()> {
+        let (last, rest) = self.collectors.split_last().expect("at least one collector");
+        for collector in rest {
+            collector.add_frame_rgba(frame_index, image.clone(), presentation_timestamp)?;
+        }
+        last.add_frame_rgba(frame_index, image, presentation_timestamp)
+    }
+
+    /// Same as `Collector::add_frame_png_file()`, but the file is decoded only once
+    ///
+    /// 16-bit PNGs are reduced to 8 bits as configured with `set_tone_mapping()`.
+    #[cfg(feature = "png")]
+    pub fn add_frame_png_file(&self, frame_index: usize, path: PathBuf, presentation_timestamp: f64) -> CatResult<()> {
+        let tone_mapping = self.collectors[0].tone_mapping;
+        let image = std::fs::read(&path)
+            .map_err(|err| Error::PNG(format!("Can't load {}: {}", path.display(), err)))
+            .and_then(|data| crate::tonemap::decode_png(&data, tone_mapping)
+                .map_err(|err| Error::PNG(format!("Can't load {}: {}", path.display(), err))))?;
+        self.add_frame_rgba(frame_index, image, presentation_timestamp)
+    }
+
+    /// Same as `Collector::set_tone_mapping()`, for all outputs
+    #[cfg(any(feature = "png", feature = "exr", feature = "tiff"))]
+    pub fn set_tone_mapping(&mut self, tone_mapping: crate::ToneMapping) {
+        for collector in &mut self.collectors {
+            collector.set_tone_mapping(tone_mapping);
+        }
+    }
+
+    /// Collectors of individual outputs, in the same order as their settings
+    pub fn collectors(&self) -> &[Collector] {
+        &self.collectors
+    }
+}
+
+#[test]
+fn encodes_each_size() {
+    use crate::progress::NoProgress;
+
+    let sizes = [Some(8), Some(4)];
+    let settings: Vec<_> = sizes.iter().map(|&width| Settings { width, ..Settings::default() }).collect();
+    let (collector, writers) = new_multi(&settings).unwrap();
+    std::thread::scope(|s| {
+        let threads: Vec<_> = writers.into_iter().map(|w| s.spawn(move || {
+            let mut out = Vec::new();
+            w.write(&mut out, &mut NoProgress {}).unwrap();
+            out[6]
+        })).collect();
+        for i in 0..3u8 {
+            let (image, pts) = crate::test_frame(i, 8, 8);
+            collector.add_frame_rgba(i.into(), image, pts).unwrap();
+        }
+        drop(collector);
+        let widths: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(vec![8, 4], widths);
+    });
+}
@@ -1,34 +1,101 @@
 use crate::error::*;
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::iter::FusedIterator;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 pub struct OrdQueue<T> {
     sender: Sender<ReverseTuple<T>>,
+    /// In lossy mode, a handle to drain the oldest item from the channel when it's full,
+    /// and a way to check whether the reading side still exists.
+    overflow: Option<(Receiver<ReverseTuple<T>>, Weak<()>)>,
+    dropped: AtomicUsize,
+    /// One past the highest index pushed, to reject items pushed out of order in lossy mode
+    next_index: AtomicUsize,
 }
 
+/// Makes the item returned when waiting for items timed out, from the last index received and the time waited
+type TimeoutItem<T> = fn(Option<usize>, Duration) -> T;
+
 pub struct OrdQueueIter<T> {
     receiver: Receiver<ReverseTuple<T>>,
     next_index: usize,
     receive_buffer: BinaryHeap<ReverseTuple<T>>,
+    /// Items may be missing, so don't wait for every index
+    allow_gaps: bool,
+    /// If nothing arrives for this long, an item made by the function ends the iteration
+    idle_timeout: Option<(Duration, TimeoutItem<T>)>,
+    last_received: Option<usize>,
+    stalled: bool,
+    _alive: Arc<()>,
 }
 
 pub fn new<T>(depth: usize) -> (OrdQueue<T>, OrdQueueIter<T>) {
     let (sender, receiver) = crossbeam_channel::bounded(depth);
     (OrdQueue {
         sender,
+        overflow: None,
+        dropped: AtomicUsize::new(0),
+        next_index: AtomicUsize::new(0),
     }, OrdQueueIter {
         receiver,
         next_index: 0,
-        receive_buffer: BinaryHeap::new()
+        receive_buffer: BinaryHeap::new(),
+        allow_gaps: false,
+        idle_timeout: None,
+        last_received: None,
+        stalled: false,
+        _alive: Arc::new(()),
     })
 }
 
+/// Pushing never blocks. When the queue is full, the oldest item is dropped.
+///
+/// Items must be pushed in order, since the reader doesn't wait for missing ones. Items with a lower index than one pushed before are rejected.
+pub fn new_lossy<T>(depth: usize) -> (OrdQueue<T>, OrdQueueIter<T>) {
+    let (mut queue, mut iter) = new(depth);
+    iter.allow_gaps = true;
+    queue.overflow = Some((iter.receiver.clone(), Arc::downgrade(&iter._alive)));
+    (queue, iter)
+}
+
 impl<T: Send + 'static> OrdQueue<T> {
-    pub fn push(&mut self, index: usize, item: T) -> CatResult<()> {
-        self.sender.send(ReverseTuple(index, item))?;
-        Ok(())
+    /// Returns the number of older items dropped to make room for this one (only in lossy mode).
+    pub fn push(&self, index: usize, item: T) -> CatResult<usize> {
+        let mut item = ReverseTuple(index, item);
+        if let Some((oldest, alive)) = &self.overflow {
+            let next_index = self.next_index.fetch_max(index + 1, Relaxed);
+            if index < next_index {
+                return Err(Error::InvalidInput(format!("Frame {} was added after frame {}, but in realtime mode frames must be added in order", index, next_index - 1)));
+            }
+            let mut dropped = 0;
+            loop {
+                if alive.upgrade().is_none() {
+                    return Err(Error::ThreadSend);
+                }
+                match self.sender.try_send(item) {
+                    Ok(()) => return Ok(dropped),
+                    Err(TrySendError::Full(returned)) => {
+                        item = returned;
+                        if oldest.try_recv().is_ok() {
+                            self.dropped.fetch_add(1, Relaxed);
+                            dropped += 1;
+                        }
+                    },
+                    Err(TrySendError::Disconnected(_)) => return Err(Error::ThreadSend),
+                }
+            }
+        }
+        self.sender.send(item)?;
+        Ok(0)
+    }
+
+    /// Number of items discarded in lossy mode
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Relaxed)
     }
 }
 
@@ -37,12 +104,26 @@ impl<T> FusedIterator for OrdQueueIter<T> {}
 impl<T> Iterator for OrdQueueIter<T> {
     type Item = T;
     fn next(&mut self) -> Option<T> {
-        while self.receive_buffer.peek().map(|i| i.0) != Some(self.next_index) {
-            match self.receiver.recv() {
+        if self.stalled {
+            return None;
+        }
+        while !self.next_is_ready() {
+            let res = match self.idle_timeout {
+                Some((timeout, _)) => self.receiver.recv_timeout(timeout),
+                None => self.receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match res {
                 Ok(item) => {
+                    self.last_received = Some(self.last_received.map_or(item.0, |last| last.max(item.0)));
                     self.receive_buffer.push(item);
                 },
-                Err(_) => {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some((timeout, make_item)) = self.idle_timeout {
+                        self.stalled = true;
+                        return Some(make_item(self.last_received, timeout));
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => {
                     // Sender dropped (but continue to dump receive_buffer buffer)
                     break;
                 },
@@ -50,7 +131,7 @@ impl<T> Iterator for OrdQueueIter<T> {
         }
 
         if let Some(item) = self.receive_buffer.pop() {
-            self.next_index += 1;
+            self.next_index = item.0 + 1;
             Some(item.1)
         } else {
             None
@@ -58,14 +139,48 @@ impl<T> Iterator for OrdQueueIter<T> {
     }
 }
 
+impl<T> OrdQueueIter<T> {
+    pub fn set_idle_timeout(&mut self, timeout: Duration, make_item: TimeoutItem<T>) {
+        self.idle_timeout = Some((timeout, make_item));
+    }
+
+    fn next_is_ready(&self) -> bool {
+        match self.receive_buffer.peek() {
+            Some(item) => self.allow_gaps || item.0 == self.next_index,
+            None => false,
+        }
+    }
+}
+
 struct ReverseTuple<T>(usize, T);
 impl<T> PartialEq for ReverseTuple<T> {
     fn eq(&self, o: &Self) -> bool { o.0.eq(&self.0) }
 }
 impl<T> Eq for ReverseTuple<T> {}
 impl<T> PartialOrd for ReverseTuple<T> {
-    fn partial_cmp(&self, o: &Self) -> Option<Ordering> { o.0.partial_cmp(&self.0) }
+    fn partial_cmp(&self, o: &Self) -> Option<Ordering> { Some(self.cmp(o)) }
 }
 impl<T> Ord for ReverseTuple<T> {
     fn cmp(&self, o: &Self) -> Ordering { o.0.cmp(&self.0) }
 }
+
+#[test]
+fn lossy_queue_drops_oldest() {
+    let (queue, iter) = new_lossy(2);
+    let dropped: usize = (0..5).map(|i| queue.push(i, i).unwrap()).sum();
+    assert_eq!(3, dropped);
+    assert_eq!(3, queue.dropped());
+    assert!(queue.push(4, 4).is_err());
+    assert!(queue.push(2, 2).is_err());
+    drop(queue);
+    assert_eq!(vec![3, 4], iter.collect::<Vec<_>>());
+}
+
+#[test]
+fn idle_timeout_ends_iteration() {
+    let (queue, mut iter) = new(4);
+    iter.set_idle_timeout(Duration::from_millis(10), |last, _| last.map_or(100, |l| l + 100));
+    queue.push(1, 1).unwrap();
+    queue.push(0, 0).unwrap();
+    assert_eq!(vec![0, 1, 101], iter.collect::<Vec<_>>());
+}
@@ -0,0 +1,136 @@
+use crate::error::*;
+use crate::{DecodedImage, FrameOptions, FrameTag, IndexedImage};
+use imgref::ImgVec;
+use rgb::RGBA8;
+use std::collections::HashMap;
+
+/// Decoded frame without its timestamp, which comes from the playlist
+type KeptFrame = (ImgVec<RGBA8>, FrameOptions, Option<IndexedImage>, Vec<FrameTag>);
+
+/// Reorders frames coming in index order into the order requested by the playlist.
+///
+/// Each input frame is decoded once. Frames used again later are kept until their last use,
+/// and frames that aren't on the playlist are dropped as soon as they arrive. Their tags are passed on to the next frame returned.
+pub(crate) struct PlaylistIter<I> {
+    inputs: I,
+    next_input_index: usize,
+    playlist: std::vec::IntoIter<(usize, f64)>,
+    remaining_uses: HashMap<usize, usize>,
+    kept: HashMap<usize, KeptFrame>,
+    /// Tags of dropped frames, for the next frame returned
+    dropped_tags: Vec<FrameTag>,
+}
+
+impl<I: Iterator<Item = DecodedImage>> PlaylistIter<I> {
+    pub fn new(inputs: I, playlist: Vec<(usize, f64)>) -> Self {
+        let mut remaining_uses = HashMap::new();
+        for &(index, _) in &playlist {
+            *remaining_uses.entry(index).or_insert(0) += 1;
+        }
+        Self {
+            inputs,
+            next_input_index: 0,
+            playlist: playlist.into_iter(),
+            remaining_uses,
+            kept: HashMap::new(),
+            dropped_tags: Vec::new(),
+        }
+    }
+
+    fn take_frame(&mut self, index: usize) -> CatResult<KeptFrame> {
+        while !self.kept.contains_key(&index) {
+            let (image, _, options, indexed, dropped_tags) = self.inputs.next().ok_or_else(|| {
+                Error::InvalidInput(format!("Playlist refers to frame {}, but only {} frames have been added", index, self.next_input_index))
+            })??;
+            if self.remaining_uses.contains_key(&self.next_input_index) {
+                self.kept.insert(self.next_input_index, (image, options, indexed, dropped_tags));
+            } else {
+                self.dropped_tags.extend(dropped_tags);
+                self.dropped_tags.extend(options.tag);
+            }
+            self.next_input_index += 1;
+        }
+
+        let uses = self.remaining_uses.get_mut(&index).expect("playlist entry");
+        *uses -= 1;
+        Ok(if *uses == 0 {
+            self.remaining_uses.remove(&index);
+            self.kept.remove(&index).expect("kept frame")
+        } else {
+            self.kept[&index].clone()
+        })
+    }
+
+    /// The collector blocks if nobody reads the frames it sends,
+    /// so frames added after the end of the playlist are read and discarded.
+    fn drain_inputs(&mut self) -> CatResult<()> {
+        for frame in &mut self.inputs {
+            let (_, _, options, _, dropped_tags) = frame?;
+            self.dropped_tags.extend(dropped_tags);
+            self.dropped_tags.extend(options.tag);
+        }
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = DecodedImage>> Iterator for PlaylistIter<I> {
+    type Item = DecodedImage;
+
+    fn next(&mut self) -> Option<DecodedImage> {
+        match self.playlist.next() {
+            Some((index, pts)) => Some(self.take_frame(index).and_then(|(image, options, indexed, dropped_tags)| {
+                // the last frame also gets tags of the frames after the end of the playlist
+                if self.playlist.len() == 0 {
+                    self.drain_inputs()?;
+                }
+                let mut tags = std::mem::take(&mut self.dropped_tags);
+                tags.extend(dropped_tags);
+                Ok((image, pts, options, indexed, tags))
+            })),
+            None => self.drain_inputs().err().map(Err),
+        }
+    }
+}
+
+#[test]
+fn playlist_reorders_and_repeats() {
+    use imgref::Img;
+
+    let (queue, queue_iter) = crate::ordqueue::new(4);
+    for i in 0..3 {
+        queue.push(i, Ok((Img::new(vec![RGBA8::new(i as u8, 0, 0, 255)], 1, 1), 0., FrameOptions::default(), None, Vec::new()))).unwrap();
+    }
+    drop(queue);
+
+    let frames: Vec<_> = PlaylistIter::new(queue_iter, vec![(2, 0.), (0, 0.5), (2, 1.)])
+        .map(|f| f.map(|(img, pts, ..)| (img.buf()[0].r, pts)))
+        .collect::<CatResult<_>>()
+        .unwrap();
+    assert_eq!(vec![(2, 0.), (0, 0.5), (2, 1.)], frames);
+}
+
+#[test]
+fn playlist_keeps_tags_and_errors_of_dropped_frames() {
+    use imgref::Img;
+
+    let frame = |i: usize| Ok((Img::new(vec![RGBA8::default()], 1, 1), 0., FrameOptions { tag: Some(i.to_string().into()), ..FrameOptions::default() }, None, Vec::new()));
+    let (queue, queue_iter) = crate::ordqueue::new(8);
+    for i in 0..5 {
+        queue.push(i, frame(i)).unwrap();
+    }
+    drop(queue);
+    let frames: Vec<_> = PlaylistIter::new(queue_iter, vec![(2, 0.), (3, 1.)])
+        .map(|f| f.map(|(.., options, _, dropped_tags)| {
+            (options.tag.unwrap().to_string(), dropped_tags.iter().map(|t| t.to_string()).collect::<Vec<_>>())
+        }))
+        .collect::<CatResult<_>>()
+        .unwrap();
+    assert_eq!(vec![("2".to_string(), vec!["0".to_string(), "1".into()]), ("3".into(), vec!["4".into()])], frames);
+
+    let (queue, queue_iter) = crate::ordqueue::new(8);
+    queue.push(0, frame(0)).unwrap();
+    queue.push(1, Err(Error::NoFrames)).unwrap();
+    drop(queue);
+    let mut frames = PlaylistIter::new(queue_iter, vec![(0, 0.)]);
+    assert!(frames.next().unwrap().is_err());
+}
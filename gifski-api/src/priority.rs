@@ -0,0 +1,67 @@
+//! Lowering priority of the encoder's threads, for `Settings::low_priority`
+
+#[cfg(any(target_os = "linux", target_vendor = "apple", windows))]
+use std::os::raw::c_int;
+
+/// Best effort. Affects only the calling thread.
+pub(crate) fn lower_current_thread_priority() {
+    imp::lower();
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::c_int;
+
+    extern "C" {
+        fn setpriority(which: c_int, who: u32, prio: c_int) -> c_int;
+    }
+
+    pub fn lower() {
+        // on Linux, the "process" 0 is the calling thread
+        unsafe {
+            setpriority(0, 0, 10);
+        }
+    }
+}
+
+#[cfg(target_vendor = "apple")]
+mod imp {
+    use super::c_int;
+
+    const QOS_CLASS_UTILITY: u32 = 0x11;
+
+    extern "C" {
+        fn pthread_set_qos_class_self_np(qos_class: u32, relative_priority: c_int) -> c_int;
+    }
+
+    pub fn lower() {
+        unsafe {
+            pthread_set_qos_class_self_np(QOS_CLASS_UTILITY, 0);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use super::c_int;
+    use std::os::raw::c_void;
+
+    const THREAD_PRIORITY_BELOW_NORMAL: c_int = -1;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetCurrentThread() -> *mut c_void;
+        fn SetThreadPriority(thread: *mut c_void, priority: c_int) -> c_int;
+    }
+
+    pub fn lower() {
+        unsafe {
+            SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL);
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_vendor = "apple", windows)))]
+mod imp {
+    pub fn lower() {}
+}
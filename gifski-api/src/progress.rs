@@ -1,3 +1,4 @@
+use crate::FrameTag;
 pub use pbr::ProgressBar;
 use std::io::Stdout;
 use std::os::raw::{c_int, c_void};
@@ -9,6 +10,12 @@ pub trait ProgressReporter: Send {
 
     /// Mark the progress as done.
     fn done(&mut self, msg: &str);
+
+    /// A frame has been written. `tags` are of the input frames it shows, if they were added with tags.
+    fn written_frame(&mut self, _tags: &[FrameTag]) {}
+
+    /// Total size of the output so far. Called before `increase()` for the frames that have been written.
+    fn written_bytes(&mut self, _bytes: u64) {}
 }
 
 /// No-op progress reporter
@@ -28,6 +35,33 @@ impl ProgressCallback {
     }
 }
 
+/// For C, with output size and its estimate
+pub struct SizeProgressCallback {
+    callback: unsafe extern "C" fn(u32, u64, u64, *mut c_void) -> c_int,
+    arg: *mut c_void,
+    expected_frames: u32,
+    frames_done: u32,
+    bytes_written: u64,
+}
+
+unsafe impl Send for SizeProgressCallback {}
+
+impl SizeProgressCallback {
+    pub fn new(callback: unsafe extern "C" fn(u32, u64, u64, *mut c_void) -> c_int, expected_frames: u32, arg: *mut c_void) -> Self {
+        Self { callback, arg, expected_frames, frames_done: 0, bytes_written: 0 }
+    }
+
+    /// Extrapolated from the size of frames done so far. 0 if the number of frames isn't known.
+    fn estimated_total_bytes(&self) -> u64 {
+        if self.expected_frames == 0 || self.frames_done == 0 {
+            return 0;
+        }
+        let per_frame = self.bytes_written as f64 / f64::from(self.frames_done);
+        let remaining = self.expected_frames.saturating_sub(self.frames_done);
+        self.bytes_written + (per_frame * f64::from(remaining)) as u64
+    }
+}
+
 impl ProgressReporter for NoProgress {
     fn increase(&mut self) -> bool {
         true
@@ -42,6 +76,19 @@ impl ProgressReporter for ProgressCallback {
     fn done(&mut self, _msg: &str) {}
 }
 
+impl ProgressReporter for SizeProgressCallback {
+    fn increase(&mut self) -> bool {
+        self.frames_done += 1;
+        unsafe { (self.callback)(self.frames_done, self.bytes_written, self.estimated_total_bytes(), self.arg) == 1 }
+    }
+
+    fn done(&mut self, _msg: &str) {}
+
+    fn written_bytes(&mut self, bytes: u64) {
+        self.bytes_written = bytes;
+    }
+}
+
 /// Implement the progress reporter trait for a progress bar,
 /// to make it usable for frame processing reporting.
 impl ProgressReporter for ProgressBar<Stdout> {
@@ -54,3 +101,16 @@ impl ProgressReporter for ProgressBar<Stdout> {
         self.finish_print(msg);
     }
 }
+
+#[test]
+fn extrapolates_size() {
+    unsafe extern "C" fn cb(_: u32, _: u64, _: u64, _: *mut c_void) -> c_int {
+        1
+    }
+    let mut p = SizeProgressCallback::new(cb, 10, std::ptr::null_mut());
+    assert_eq!(0, p.estimated_total_bytes());
+    p.written_bytes(1000);
+    assert!(p.increase());
+    assert!(p.increase());
+    assert_eq!(5000, p.estimated_total_bytes());
+}
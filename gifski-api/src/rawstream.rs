@@ -0,0 +1,98 @@
+//! Reading uncompressed frames from a pipe, such as ffmpeg's `-f rawvideo -` output
+
+use crate::error::*;
+use crate::Collector;
+use imgref::*;
+use rgb::*;
+use std::io::{self, Read};
+
+/// Layout of pixels in a raw video stream. Names in parenthesis are ffmpeg's `-pix_fmt` names.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RawPixelFormat {
+    /// 3 bytes per pixel (`rgb24`)
+    Rgb24,
+    /// 4 bytes per pixel (`rgba`)
+    Rgba,
+    /// 4 bytes per pixel, in the order used by most screen capture APIs (`bgra`)
+    Bgra,
+}
+
+impl RawPixelFormat {
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            Self::Rgb24 => 3,
+            Self::Rgba | Self::Bgra => 4,
+        }
+    }
+}
+
+/// Splits a stream of raw pixels into frames of a fixed size.
+///
+/// The stream has no header, so the size, frame rate and pixel format have to be known in advance, e.g.
+/// `ffmpeg -i video.mp4 -vf scale=480:-2 -f rawvideo -pix_fmt rgb24 -`.
+#[derive(Debug, Clone)]
+pub struct RawStreamCollector {
+    width: usize,
+    height: usize,
+    fps: f64,
+    format: RawPixelFormat,
+}
+
+impl RawStreamCollector {
+    /// Size and pixel format of every frame in the stream. Timestamps are computed from `fps`.
+    pub fn new(width: usize, height: usize, fps: f64, format: RawPixelFormat) -> CatResult<Self> {
+        if width == 0 || height == 0 || width.checked_mul(height).and_then(|px| px.checked_mul(format.bytes_per_pixel())).is_none() {
+            return Err(Error::InvalidInput(format!("Invalid frame size {}×{}", width, height)));
+        }
+        if fps.is_nan() || fps <= 0. {
+            return Err(Error::InvalidInput(format!("Invalid frame rate {}", fps)));
+        }
+        Ok(Self { width, height, fps, format })
+    }
+
+    /// Reads frames until the end of the stream, and adds them to the collector, starting from frame index 0.
+    ///
+    /// Returns the number of frames added. It's an error if the stream ends in the middle of a frame.
+    pub fn add_frames_to(&self, dest: &Collector, mut reader: impl Read) -> CatResult<usize> {
+        let mut buf = vec![0; self.width * self.height * self.format.bytes_per_pixel()];
+        let mut frame_index = 0;
+        while read_frame(&mut reader, &mut buf)? {
+            let pts = frame_index as f64 / self.fps;
+            match self.format {
+                RawPixelFormat::Rgb24 => dest.add_frame_rgb(frame_index, ImgVec::new(buf.as_rgb().to_vec(), self.width, self.height), pts)?,
+                RawPixelFormat::Rgba => dest.add_frame_rgba(frame_index, ImgVec::new(buf.as_rgba().to_vec(), self.width, self.height), pts)?,
+                RawPixelFormat::Bgra => dest.add_frame_bgra(frame_index, ImgRef::new(buf.as_bgra(), self.width, self.height), pts)?,
+            }
+            frame_index += 1;
+        }
+        Ok(frame_index)
+    }
+}
+
+/// `false` at the end of the stream
+fn read_frame(reader: &mut impl Read, buf: &mut [u8]) -> CatResult<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(Error::Decode(format!("The stream ended in the middle of a frame ({} of {} bytes)", filled, buf.len()))),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+#[test]
+fn splits_raw_stream() {
+    let raw = RawStreamCollector::new(2, 1, 10., RawPixelFormat::Rgb24).unwrap();
+    let (collector, writer) = crate::new(crate::Settings::default()).unwrap();
+    let stream = [255, 0, 0, 0, 0, 255, 0, 255, 0, 0, 0, 0];
+    assert_eq!(2, raw.add_frames_to(&collector, &stream[..]).unwrap());
+    assert!(raw.add_frames_to(&collector, &stream[..5]).is_err());
+    drop(collector);
+    assert_eq!(2, writer.write(Vec::new(), &mut crate::progress::NoProgress {}).unwrap().frames_written);
+
+    assert!(RawStreamCollector::new(0, 1, 10., RawPixelFormat::Rgba).is_err());
+}
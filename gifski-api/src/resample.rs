@@ -0,0 +1,53 @@
+//! Constant frame rate for inputs with uneven timestamps
+
+use crate::DecodedImage;
+
+/// Rounds timestamps up to the next tick of a `fps` grid, i.e. the time the frame would be seen by a camera running at that rate.
+///
+/// Frames that are followed by another frame in the same tick are dropped, and their tags are moved to the frame that's kept.
+pub(crate) fn resampled(inputs: impl Iterator<Item = DecodedImage>, fps: f32) -> impl Iterator<Item = DecodedImage> {
+    let fps = f64::from(fps);
+    // tolerance for timestamps that are already on the grid, but not exactly due to float rounding
+    let tick_of = move |pts: f64| (pts * fps - 1e-6).ceil().max(0.);
+    let mut inputs = inputs.peekable();
+    let mut dropped_tags = Vec::new();
+    std::iter::from_fn(move || loop {
+        let (image, pts, options, indexed, tags) = match inputs.next()? {
+            Ok(frame) => frame,
+            Err(err) => return Some(Err(err)),
+        };
+        let tick = tick_of(pts);
+        if let Some(Ok((_, next_pts, ..))) = inputs.peek() {
+            if tick_of(*next_pts) <= tick {
+                dropped_tags.extend(tags);
+                dropped_tags.extend(options.tag);
+                continue;
+            }
+        }
+        let mut tags_before = std::mem::take(&mut dropped_tags);
+        tags_before.extend(tags);
+        return Some(Ok((image, tick / fps, options, indexed, tags_before)));
+    })
+}
+
+#[test]
+fn snaps_to_grid() {
+    use crate::FrameOptions;
+    use imgref::ImgVec;
+    use rgb::RGBA8;
+
+    let frames = [0., 0.09, 0.15, 0.19, 0.2, 0.52].iter().enumerate().map(|(i, &pts)| {
+        let options = FrameOptions { tag: Some(i.to_string().into()), ..FrameOptions::default() };
+        Ok((ImgVec::new(vec![RGBA8::new(i as u8, 0, 0, 255)], 1, 1), pts, options, None, Vec::new()))
+    });
+    let frames: Vec<_> = resampled(frames, 10.).map(|f| f.unwrap()).map(|(image, pts, options, _, dropped_tags)| {
+        let dropped_tags: Vec<_> = dropped_tags.iter().map(|t| t.to_string()).collect();
+        (image.buf()[0].r, (pts * 100.).round(), options.tag.unwrap().to_string(), dropped_tags)
+    }).collect();
+    assert_eq!(vec![
+        (0, 0., "0".into(), vec![]),
+        (1, 10., "1".into(), vec![]),
+        (4, 20., "4".into(), vec!["2".to_string(), "3".into()]),
+        (5, 60., "5".into(), vec![]),
+    ], frames);
+}
@@ -0,0 +1,58 @@
+//! Full-screen frames for video formats, which can't show just the changed area of a frame
+
+use crate::GIFFrame;
+use imgref::*;
+use rgb::*;
+
+/// What a GIF player would display, frame by frame
+pub(crate) struct Screen {
+    pixels: ImgVec<RGBA8>,
+}
+
+impl Screen {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            pixels: ImgVec::new(vec![RGBA8::default(); width * height], width, height),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.pixels.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.pixels.height()
+    }
+
+    /// Draws the frame, passes the whole screen to `show`, and then applies the frame's disposal method
+    pub fn draw<R>(&mut self, f: &GIFFrame, show: impl FnOnce(ImgRef<'_, RGBA8>) -> R) -> R {
+        let previous = if f.dispose == gif::DisposalMethod::Previous { Some(self.pixels.clone()) } else { None };
+        let rgba = f.to_rgba();
+        let (left, top) = (usize::from(f.left), usize::from(f.top));
+        let mut frame_area = self.pixels.sub_image_mut(left, top, rgba.width(), rgba.height());
+        for (dst, src) in frame_area.rows_mut().zip(rgba.rows()) {
+            for (dst, src) in dst.iter_mut().zip(src) {
+                if src.a > 0 {
+                    *dst = *src;
+                }
+            }
+        }
+
+        let res = show(self.pixels.as_ref());
+
+        match f.dispose {
+            gif::DisposalMethod::Background => {
+                for row in self.pixels.sub_image_mut(left, top, rgba.width(), rgba.height()).rows_mut() {
+                    row.fill(RGBA8::default());
+                }
+            },
+            gif::DisposalMethod::Previous => {
+                if let Some(previous) = previous {
+                    self.pixels = previous;
+                }
+            },
+            _ => {},
+        }
+        res
+    }
+}
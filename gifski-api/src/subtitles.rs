@@ -0,0 +1,220 @@
+//! Burning SRT/WebVTT captions into frames
+//!
+//! GIF has no text track, so captions have to be drawn into the pixels.
+//! Frames are matched to cues by their presentation timestamp.
+
+use crate::error::*;
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use imgref::*;
+use rgb::*;
+use std::path::Path;
+
+/// Parsed captions and the font to draw them with
+pub struct Subtitles {
+    cues: Vec<Cue>,
+    font: FontVec,
+    font_size: Option<f32>,
+}
+
+struct Cue {
+    start: f64,
+    end: f64,
+    lines: Vec<String>,
+}
+
+impl Subtitles {
+    /// Parse SRT or WebVTT text (format is auto-detected).
+    ///
+    /// `font_data` is a TTF or OTF font file. No font is bundled with gifski.
+    pub fn new(text: &str, font_data: Vec<u8>) -> CatResult<Self> {
+        let font = FontVec::try_from_vec(font_data)
+            .map_err(|_| Error::Subtitles("Can't load the subtitle font".into()))?;
+        Ok(Self {
+            cues: parse_cues(text)?,
+            font,
+            font_size: None,
+        })
+    }
+
+    /// Read `.srt` or `.vtt` file from disk
+    pub fn from_file(path: &Path, font_data: Vec<u8>) -> CatResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| Error::Subtitles(format!("Can't load {}: {}", path.display(), err)))?;
+        Self::new(&text, font_data)
+    }
+
+    /// Height of the text in pixels. By default it's 1/16th of the frame height.
+    pub fn set_font_size(&mut self, px: f32) {
+        self.font_size = Some(px);
+    }
+
+    /// Draw captions visible at `pts` (in seconds) onto the frame
+    pub fn render(&self, mut image: ImgRefMut<'_, RGBA8>, pts: f64) {
+        let lines: Vec<&str> = self.cues.iter()
+            .filter(|cue| cue.start <= pts && pts < cue.end)
+            .flat_map(|cue| cue.lines.iter().map(|l| l.as_str()))
+            .collect();
+        if lines.is_empty() {
+            return;
+        }
+
+        let width = image.width();
+        let height = image.height();
+        let size = self.font_size.unwrap_or(height as f32 / 16.).max(6.);
+        let font = self.font.as_scaled(PxScale::from(size));
+        let outline = (size / 14.).ceil().max(1.) as usize;
+
+        // coverage of the text, drawn separately to compute the outline from it
+        let mut mask = vec![0f32; width * height];
+        let line_height = font.height() + font.line_gap();
+        let margin = height as f32 / 20.;
+        let mut baseline = height as f32 - margin - font.descent() - line_height * (lines.len() - 1) as f32;
+
+        for line in lines {
+            let mut line_width = 0.;
+            let mut prev = None;
+            for ch in line.chars() {
+                let id = font.glyph_id(ch);
+                if let Some(prev) = prev {
+                    line_width += font.kern(prev, id);
+                }
+                line_width += font.h_advance(id);
+                prev = Some(id);
+            }
+
+            let mut x = (width as f32 - line_width) / 2.;
+            let mut prev = None;
+            for ch in line.chars() {
+                let id = font.glyph_id(ch);
+                if let Some(prev) = prev {
+                    x += font.kern(prev, id);
+                }
+                let glyph = id.with_scale_and_position(size, point(x, baseline));
+                x += font.h_advance(id);
+                prev = Some(id);
+
+                if let Some(outlined) = font.outline_glyph(glyph) {
+                    let bounds = outlined.px_bounds();
+                    outlined.draw(|gx, gy, c| {
+                        let px = bounds.min.x as i32 + gx as i32;
+                        let py = bounds.min.y as i32 + gy as i32;
+                        if px >= 0 && py >= 0 && (px as usize) < width && (py as usize) < height {
+                            let m = &mut mask[py as usize * width + px as usize];
+                            *m = m.max(c);
+                        }
+                    });
+                }
+            }
+            baseline += line_height;
+        }
+
+        let mut shadow = mask.clone();
+        for _ in 0..outline {
+            shadow = dilate(&shadow, width, height);
+        }
+
+        for (y, row) in image.rows_mut().enumerate() {
+            for (x, px) in row.iter_mut().enumerate() {
+                let i = y * width + x;
+                blend(px, RGB8::new(0, 0, 0), shadow[i]);
+                blend(px, RGB8::new(255, 255, 255), mask[i]);
+            }
+        }
+    }
+}
+
+fn dilate(src: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let mut dst = src.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            let mut m = src[y * width + x];
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    m = m.max(src[ny * width + nx]);
+                }
+            }
+            dst[y * width + x] = m;
+        }
+    }
+    dst
+}
+
+#[inline]
+fn blend(px: &mut RGBA8, color: RGB8, alpha: f32) {
+    if alpha <= 0. {
+        return;
+    }
+    let alpha = alpha.min(1.);
+    // composite "over" the existing pixel, so that text drawn over transparency doesn't get a dark fringe
+    let dst_a = px.a as f32 / 255.;
+    let out_a = alpha + dst_a * (1. - alpha);
+    let mix = |c: u8, d: u8| ((c as f32 * alpha + d as f32 * dst_a * (1. - alpha)) / out_a).round() as u8;
+    *px = RGBA8::new(mix(color.r, px.r), mix(color.g, px.g), mix(color.b, px.b), (out_a * 255.).round() as u8);
+}
+
+fn parse_cues(text: &str) -> CatResult<Vec<Cue>> {
+    let text = text.trim_start_matches('\u{feff}').replace("\r\n", "\n").replace('\r', "\n");
+    let mut cues = Vec::new();
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|l| !l.contains("-->"));
+        let timing = match lines.next() {
+            Some(t) => t,
+            None => continue, // WEBVTT header, NOTE, STYLE, or garbage
+        };
+        let mut times = timing.split("-->");
+        let start = times.next().and_then(parse_timestamp);
+        // WebVTT can have cue settings after the end time
+        let end = times.next().and_then(|t| t.split_whitespace().next()).and_then(parse_timestamp);
+        let (start, end) = match (start, end) {
+            (Some(s), Some(e)) => (s, e),
+            _ => return Err(Error::Subtitles(format!("Invalid subtitle timing: {}", timing.trim()))),
+        };
+        let lines: Vec<_> = lines.map(strip_tags).filter(|l| !l.is_empty()).collect();
+        if !lines.is_empty() {
+            cues.push(Cue { start, end, lines });
+        }
+    }
+    Ok(cues)
+}
+
+/// Accepts `hh:mm:ss,mmm` (SRT) and `[hh:]mm:ss.mmm` (WebVTT)
+fn parse_timestamp(t: &str) -> Option<f64> {
+    let t = t.trim().replace(',', ".");
+    let mut seconds = 0.;
+    for part in t.split(':') {
+        seconds = seconds * 60. + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+/// Removes `<i>`, `<c.yellow>`, `{\an8}` and such
+fn strip_tags(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut depth = 0;
+    for ch in line.chars() {
+        match ch {
+            '<' | '{' => depth += 1,
+            '>' | '}' if depth > 0 => depth -= 1,
+            _ if depth == 0 => out.push(ch),
+            _ => {},
+        }
+    }
+    out.trim().to_string()
+}
+
+#[test]
+fn parse_srt_and_vtt() {
+    let srt = parse_cues("1\r\n00:00:01,500 --> 00:00:04,000\r\n<i>Hello</i>\r\nworld\r\n\r\n2\r\n00:01:00,000 --> 00:01:02,250\r\nBye\r\n").unwrap();
+    assert_eq!(2, srt.len());
+    assert_eq!(1.5, srt[0].start);
+    assert_eq!(vec!["Hello", "world"], srt[0].lines);
+    assert_eq!(62.25, srt[1].end);
+
+    let vtt = parse_cues("WEBVTT\n\nNOTE hi\n\nintro\n00:02.000 --> 00:03.000 align:start\n{\\an8}Hey\n").unwrap();
+    assert_eq!(1, vtt.len());
+    assert_eq!(2., vtt[0].start);
+    assert_eq!(3., vtt[0].end);
+    assert_eq!(vec!["Hey"], vtt[0].lines);
+
+    assert!(parse_cues("1\n00:00:01 --> soon\nx\n").is_err());
+}
@@ -0,0 +1,167 @@
+//! Reduction of 16-bit and HDR images to 8 bits per channel
+
+use crate::Collector;
+use imgref::*;
+use rgb::*;
+
+/// How frames with more than 8 bits per channel (such as 16-bit PNGs and TIFFs, and OpenEXR renders) are reduced to 8 bits.
+///
+/// Set with `Collector::set_tone_mapping()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ToneMapping {
+    /// Stretch the range between the darkest and the brightest channel value of each frame to the full 0–255 range.
+    ///
+    /// Useful for dark or low-contrast images, such as scientific captures, that use a small part of the 16-bit range.
+    pub normalize: bool,
+    /// Add ordered dithering before rounding, so that smooth gradients don't get banding.
+    pub dithering: bool,
+    /// Brightness adjustment of HDR images in stops. +1 doubles the brightness. Doesn't affect 16-bit images.
+    pub exposure: f32,
+    /// How HDR values brighter than white are compressed
+    pub hdr_curve: HdrCurve,
+}
+
+/// Tone curve applied to linear-light HDR values (after exposure), before conversion to sRGB
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HdrCurve {
+    /// Values above 1.0 become white, like Blender's "Standard" view transform
+    Clip,
+    /// `x / (1 + x)`, keeps detail in highlights, but makes the image flatter
+    Reinhard,
+    /// Approximation of the ACES filmic curve, with more contrast than Reinhard
+    Filmic,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            normalize: false,
+            dithering: true,
+            exposure: 0.,
+            hdr_curve: HdrCurve::Clip,
+        }
+    }
+}
+
+impl Collector {
+    /// Configure how 16-bit and HDR frames are converted to 8 bits. Call before adding frames.
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+}
+
+#[cfg(feature = "png")]
+/// Decodes PNG of any color type, keeping precision of 16-bit files until tone mapping
+pub(crate) fn decode_png(data: &[u8], tone_mapping: ToneMapping) -> Result<ImgVec<RGBA8>, lodepng::Error> {
+    let mut decoder = lodepng::Decoder::new();
+    decoder.inspect(data)?;
+    if decoder.info_png().color.bitdepth() != 16 {
+        let image = lodepng::decode32(data)?;
+        return Ok(ImgVec::new(image.buffer, image.width, image.height));
+    }
+    match lodepng::decode_memory(data, lodepng::ColorType::RGBA, 16)? {
+        lodepng::Image::RGBA16(image) => {
+            // samples are in PNG's big-endian order
+            let pixels: Vec<_> = image.buffer.into_iter().map(|px| px.map(u16::from_be)).collect();
+            Ok(to_8bit(ImgVec::new(pixels, image.width, image.height).as_ref(), tone_mapping))
+        },
+        _ => Err(lodepng::Error::new(56)),
+    }
+}
+
+/// 4×4 Bayer matrix, as fractions of 16
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+fn dither_threshold(x: usize, y: usize, tone_mapping: ToneMapping) -> f32 {
+    if tone_mapping.dithering { (f32::from(BAYER[y % 4][x % 4]) + 0.5) / 16. } else { 0.5 }
+}
+
+#[cfg(any(feature = "png", feature = "tiff"))]
+pub(crate) fn to_8bit(image: ImgRef<'_, RGBA16>, tone_mapping: ToneMapping) -> ImgVec<RGBA8> {
+    let (min, max) = if tone_mapping.normalize {
+        image.pixels().flat_map(|px| [px.r, px.g, px.b])
+            .fold((u16::MAX, 0), |(min, max), v| (min.min(v), max.max(v)))
+    } else {
+        (0, u16::MAX)
+    };
+    let scale = 255. / f32::from(max.saturating_sub(min).max(1));
+
+    let mut out = Vec::with_capacity(image.width() * image.height());
+    for (y, row) in image.rows().enumerate() {
+        for (x, px) in row.iter().enumerate() {
+            let threshold = dither_threshold(x, y, tone_mapping);
+            let channel = |v: u16| (f32::from(v.saturating_sub(min)) * scale + threshold).min(255.) as u8;
+            out.push(RGBA8::new(channel(px.r), channel(px.g), channel(px.b), (px.a >> 8) as u8));
+        }
+    }
+    ImgVec::new(out, image.width(), image.height())
+}
+
+/// Converts linear-light RGBA with premultiplied alpha (as in OpenEXR) to sRGB
+#[cfg(feature = "exr")]
+pub(crate) fn hdr_to_8bit(image: ImgRef<'_, [f32; 4]>, tone_mapping: ToneMapping) -> ImgVec<RGBA8> {
+    let unpremultiply = |[r, g, b, a]: [f32; 4]| if a > 0. { [r / a, g / a, b / a] } else { [0.; 3] };
+    let mut gain = 2f32.powf(tone_mapping.exposure);
+    if tone_mapping.normalize {
+        let brightest = image.pixels().flat_map(unpremultiply).fold(0f32, f32::max) * gain;
+        if brightest > 0. {
+            gain /= brightest;
+        }
+    }
+    let curve = |v: f32| {
+        let v = (v * gain).max(0.);
+        let v = match tone_mapping.hdr_curve {
+            HdrCurve::Clip => v,
+            HdrCurve::Reinhard => v / (1. + v),
+            HdrCurve::Filmic => (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14),
+        }.min(1.);
+        if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1. / 2.4) - 0.055 }
+    };
+
+    let mut out = Vec::with_capacity(image.width() * image.height());
+    for (y, row) in image.rows().enumerate() {
+        for (x, &px) in row.iter().enumerate() {
+            let threshold = dither_threshold(x, y, tone_mapping);
+            let [r, g, b] = unpremultiply(px).map(|v| (curve(v) * 255. + threshold).min(255.) as u8);
+            out.push(RGBA8::new(r, g, b, (px[3].clamp(0., 1.) * 255. + 0.5) as u8));
+        }
+    }
+    ImgVec::new(out, image.width(), image.height())
+}
+
+#[test]
+#[cfg(feature = "png")]
+fn reduces_16bit() {
+    let gradient: Vec<_> = (0..16u16).map(|i| RGBA16::new(0x1000 + i * 8, 0x1000, 0x1100, 0xFFFF)).collect();
+    let image = ImgVec::new(gradient, 4, 4);
+
+    let plain = to_8bit(image.as_ref(), ToneMapping { dithering: false, ..ToneMapping::default() });
+    assert!(plain.pixels().all(|px| px == RGBA8::new(16, 16, 17, 255)));
+
+    let normalized = to_8bit(image.as_ref(), ToneMapping { normalize: true, dithering: false, ..ToneMapping::default() });
+    assert_eq!(RGBA8::new(0, 0, 255, 255), normalized.buf()[0]);
+
+    // dithering keeps the average level
+    let dithered = to_8bit(ImgVec::new(vec![RGBA16::new(0x1080, 0, 0, 0xFFFF); 16], 4, 4).as_ref(), ToneMapping::default());
+    let sum: u32 = dithered.pixels().map(|px| u32::from(px.r)).sum();
+    assert_eq!(16 * 16 + 7, sum);
+}
+
+#[test]
+#[cfg(feature = "png")]
+fn decodes_16bit_png() {
+    // gray+alpha, big-endian samples
+    let data = lodepng::encode_memory(&[0x12u8, 0x34, 0xFF, 0xFF, 0xFF, 0xFF, 0x80, 0x00][..], 2, 1, lodepng::ColorType::GREY_ALPHA, 16).unwrap();
+    let image = decode_png(&data, ToneMapping { dithering: false, ..ToneMapping::default() }).unwrap();
+    assert_eq!(&[RGBA8::new(0x12, 0x12, 0x12, 255), RGBA8::new(255, 255, 255, 0x80)][..], image.buf());
+}
+
+#[test]
+#[cfg(feature = "exr")]
+fn maps_hdr() {
+    let image = ImgVec::new(vec![[0.5, 0.5, 4., 1.], [0., 0., 0., 0.]], 2, 1);
+    let exact = ToneMapping { dithering: false, ..ToneMapping::default() };
+    assert_eq!(&[RGBA8::new(188, 188, 255, 255), RGBA8::new(0, 0, 0, 0)][..], hdr_to_8bit(image.as_ref(), exact).buf());
+    let darker = hdr_to_8bit(image.as_ref(), ToneMapping { exposure: -3., hdr_curve: HdrCurve::Reinhard, ..exact });
+    assert!(darker.buf()[0].r < 100 && darker.buf()[0].b < 255);
+}
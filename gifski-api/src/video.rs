@@ -0,0 +1,253 @@
+//! Decoding frames from video files with ffmpeg
+//!
+//! Any format and codec supported by the linked ffmpeg (MP4, MOV, WebM, etc.) can be read.
+//! Frames are resized and converted to RGBA by ffmpeg, at a fixed frame rate.
+//!
+//! Frames decoded by your own ffmpeg code can be added with `Collector::add_avframe()`.
+
+use crate::error::*;
+use crate::yuv::{half_size, nv12_to_rgba, planar_to_rgba, YuvMatrix};
+use crate::Collector;
+use ffmpeg::format::Pixel;
+use ffmpeg::util::color;
+use ffmpeg::util::frame::Video;
+use ffmpeg::Rational;
+use imgref::*;
+use rgb::*;
+use std::path::Path;
+
+/// Reads frames of the best video track of a file
+pub struct VideoDecoder {
+    input_context: ffmpeg::format::context::Input,
+    duration: f64,
+    fps: f32,
+    speed: f32,
+}
+
+impl VideoDecoder {
+    /// Opens the file, and reads its headers
+    pub fn open(path: &Path) -> CatResult<Self> {
+        ffmpeg::init().map_err(|e| Error::Decode(format!("Unable to initialize ffmpeg: {}", e)))?;
+        let input_context = ffmpeg::format::input(&path)
+            .map_err(|e| Error::Decode(format!("Unable to open video file {}: {}", path.display(), e)))?;
+        let stream = input_context.streams().best(ffmpeg::media::Type::Video).ok_or_else(no_video)?;
+        let time_base = stream.time_base().numerator() as f64 / stream.time_base().denominator() as f64;
+        let duration = stream.duration() as f64 * time_base;
+        Ok(Self {
+            input_context,
+            duration,
+            fps: 20.,
+            speed: 1.,
+        })
+    }
+
+    /// Frame rate of the GIF. The video is resampled to it. The default is 20.
+    pub fn set_fps(&mut self, fps: f32) {
+        self.fps = fps;
+    }
+
+    /// Play the video this many times faster (skipping frames). The default is 1.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Estimated number of frames that will be added, based on the duration of the video
+    pub fn total_frames(&self) -> u64 {
+        (self.duration * f64::from(self.fps / self.speed)).ceil() as u64
+    }
+
+    /// Decodes all frames of the video, and adds them to the collector, starting from frame index 0.
+    ///
+    /// Frames are resized to the size set in `Settings` already by ffmpeg.
+    /// Returns the number of frames added.
+    pub fn add_frames_to(&mut self, dest: &Collector) -> CatResult<usize> {
+        let (stream_index, mut decoder, mut filter) = {
+            let filter_fps = self.fps / self.speed;
+            let stream = self.input_context.streams().best(ffmpeg::media::Type::Video).ok_or_else(no_video)?;
+
+            let decoder = stream.codec().decoder().video()
+                .map_err(|e| Error::Decode(format!("Unable to decode the codec used in the video: {}", e)))?;
+
+            let (dest_width, dest_height) = dest.geometry.scaled_size_in_decoder((decoder.width() as _, decoder.height() as _));
+
+            let buffer_args = format!("width={}:height={}:video_size={}x{}:pix_fmt={}:time_base={}:sar={}",
+                dest_width,
+                dest_height,
+                decoder.width(),
+                decoder.height(),
+                decoder.format().descriptor().ok_or_else(format_error)?.name(),
+                stream.time_base(),
+                (|sar: ffmpeg::util::rational::Rational| match sar.numerator() {
+                    0 => "1".to_string(),
+                    _ => format!("{}/{}", sar.numerator(), sar.denominator()),
+                })(decoder.aspect_ratio()),
+            );
+            let mut filter = ffmpeg::filter::Graph::new();
+            filter.add(&ffmpeg::filter::find("buffer").ok_or_else(format_error)?, "in", &buffer_args).map_err(ffmpeg_error)?;
+            filter.add(&ffmpeg::filter::find("buffersink").ok_or_else(format_error)?, "out", "").map_err(ffmpeg_error)?;
+            filter.output("in", 0).and_then(|f| f.input("out", 0)).and_then(|f| f.parse(&format!("fps=fps={},format=rgba", filter_fps)))
+                .map_err(ffmpeg_error)?;
+            filter.validate().map_err(ffmpeg_error)?;
+            (stream.index(), decoder, filter)
+        };
+
+        let add_frame = |rgba_frame: &Video, pts: f64, pos: usize| -> CatResult<()> {
+            dest.add_frame_rgba(pos, avframe_to_rgba(rgba_frame)?, pts)
+        };
+
+        let mut packets = self.input_context.packets();
+        let mut vid_frame = Video::empty();
+        let mut filt_frame = Video::empty();
+        let mut i = 0;
+        let mut pts_last_packet = 0;
+        let pts_frame_step = 1.0 / f64::from(self.fps);
+
+        loop {
+            let (packet, no_more_packets) = if let Some((s, packet)) = packets.next() {
+                if s.index() != stream_index {
+                    // ignore irrelevant streams
+                    continue;
+                }
+                pts_last_packet = packet.pts().ok_or_else(format_error)? + packet.duration();
+                (packet, false)
+            } else {
+                (ffmpeg::Packet::empty(), true)
+            };
+
+            let decoded = decoder.decode(&packet, &mut vid_frame).map_err(ffmpeg_error)?;
+            if decoded {
+                filter.get("in").ok_or_else(format_error)?.source().add(&vid_frame).map_err(ffmpeg_error)?;
+                let mut out = filter.get("out").ok_or_else(format_error)?;
+                let mut out = out.sink();
+                while let Ok(..) = out.frame(&mut filt_frame) {
+                    add_frame(&filt_frame, pts_frame_step * i as f64, i)?;
+                    i += 1;
+                }
+            }
+            // loop to flush decoder's buffer
+            if no_more_packets && !decoded {
+                break;
+            }
+        }
+
+        // now flush filter's buffer
+        filter.get("in").ok_or_else(format_error)?.source().close(pts_last_packet).map_err(ffmpeg_error)?;
+        let mut out = filter.get("out").ok_or_else(format_error)?;
+        let mut out = out.sink();
+        while let Ok(..) = out.frame(&mut filt_frame) {
+            add_frame(&filt_frame, pts_frame_step * i as f64, i)?;
+            i += 1;
+        }
+        Ok(i)
+    }
+}
+
+impl Collector {
+    /// Decode a video file with ffmpeg, and add its frames at `fps` frame rate, starting from frame index 0.
+    ///
+    /// Use `VideoDecoder` for more options. Returns the number of frames added.
+    pub fn add_frames_from_video(&self, path: &Path, fps: f32) -> CatResult<usize> {
+        if fps.is_nan() || fps <= 0. {
+            return Err(Error::InvalidInput(format!("Invalid frame rate {}", fps)));
+        }
+        let mut decoder = VideoDecoder::open(path)?;
+        decoder.set_fps(fps);
+        decoder.add_frames_to(self)
+    }
+
+    /// Add a frame decoded with `ffmpeg-next`. See `avframe_to_rgba()` for supported pixel formats.
+    ///
+    /// The presentation timestamp is taken from the frame, and converted to seconds using `time_base`
+    /// (usually the time base of the stream the frame was decoded from).
+    /// Subtract the stream's start time if the first frame doesn't have pts of 0.
+    pub fn add_avframe(&self, frame_index: usize, frame: &Video, time_base: Rational) -> CatResult<()> {
+        let pts = frame.timestamp().or_else(|| frame.pts())
+            .ok_or_else(|| Error::InvalidInput("Video frame has no timestamp".into()))?;
+        if time_base.denominator() == 0 {
+            return Err(Error::InvalidInput("Invalid time base".into()));
+        }
+        let pts = pts as f64 * f64::from(time_base.numerator()) / f64::from(time_base.denominator());
+        self.add_frame_rgba(frame_index, avframe_to_rgba(frame)?, pts)
+    }
+}
+
+/// Converts a decoded video frame to RGBA pixels.
+///
+/// RGB(A), BGRA, NV12 and planar YUV (4:2:0, 4:2:2, 4:4:4) frames are converted directly,
+/// using the frame's color space and range. Other pixel formats are converted with ffmpeg's swscale.
+///
+/// (It's a function rather than `TryFrom`, because neither of the types is defined in this crate).
+pub fn avframe_to_rgba(frame: &Video) -> CatResult<ImgVec<RGBA8>> {
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    if width == 0 || height == 0 {
+        return Err(Error::Decode("Empty video frame".into()));
+    }
+    let matrix = match frame.color_space() {
+        color::Space::BT709 => YuvMatrix::Bt709,
+        color::Space::BT470BG | color::Space::SMPTE170M => YuvMatrix::Bt601,
+        _ => YuvMatrix::for_size(width, height),
+    };
+    let full_range = frame.color_range() == color::Range::JPEG;
+
+    let pixels = match frame.format() {
+        Pixel::RGBA => packed_rows(frame, width * 4, height)?.flat_map(|row| row.as_rgba().iter().copied()).collect(),
+        Pixel::RGB24 => packed_rows(frame, width * 3, height)?.flat_map(|row| row.as_rgb().iter().map(|px| px.with_alpha(255))).collect(),
+        Pixel::BGRA => packed_rows(frame, width * 4, height)?.flat_map(|row| row.as_bgra().iter().map(|px| RGBA8::new(px.r, px.g, px.b, px.a))).collect(),
+        Pixel::NV12 => {
+            let luma = plane(frame, 0, width, height)?;
+            let chroma = plane(frame, 1, half_size(width) * 2, half_size(height))?;
+            return Ok(nv12_to_rgba(luma, chroma, matrix, full_range));
+        },
+        format @ (Pixel::YUV420P | Pixel::YUVJ420P | Pixel::YUV422P | Pixel::YUVJ422P | Pixel::YUV444P | Pixel::YUVJ444P) => {
+            let (chroma_width, chroma_height) = match format {
+                Pixel::YUV420P | Pixel::YUVJ420P => (half_size(width), half_size(height)),
+                Pixel::YUV422P | Pixel::YUVJ422P => (half_size(width), height),
+                _ => (width, height),
+            };
+            // the "J" formats are full range regardless of the color range field
+            let full_range = full_range || matches!(format, Pixel::YUVJ420P | Pixel::YUVJ422P | Pixel::YUVJ444P);
+            let luma = plane(frame, 0, width, height)?;
+            let u = plane(frame, 1, chroma_width, chroma_height)?;
+            let v = plane(frame, 2, chroma_width, chroma_height)?;
+            return Ok(planar_to_rgba(luma, u, v, matrix, full_range));
+        },
+        format => {
+            let mut scaler = ffmpeg::software::scaling::Context::get(format, frame.width(), frame.height(), Pixel::RGBA, frame.width(), frame.height(), ffmpeg::software::scaling::flag::Flags::BILINEAR)
+                .map_err(ffmpeg_error)?;
+            let mut rgba = Video::empty();
+            scaler.run(frame, &mut rgba).map_err(ffmpeg_error)?;
+            return avframe_to_rgba(&rgba);
+        },
+    };
+    Ok(ImgVec::new(pixels, width, height))
+}
+
+fn packed_rows(frame: &Video, row_bytes: usize, height: usize) -> CatResult<impl Iterator<Item = &[u8]>> {
+    let stride = plane(frame, 0, row_bytes, height)?.stride();
+    Ok(frame.data(0).chunks(stride).take(height).map(move |row| &row[..row_bytes]))
+}
+
+/// Width is in bytes
+fn plane(frame: &Video, index: usize, width: usize, height: usize) -> CatResult<ImgRef<'_, u8>> {
+    let data = frame.data(index);
+    let stride = frame.stride(index);
+    if stride < width || data.len() < stride * (height - 1) + width {
+        return Err(Error::Decode("incompatible video frame layout".into()));
+    }
+    Ok(ImgRef::new_stride(data, width, height, stride))
+}
+
+#[cold]
+fn no_video() -> Error {
+    Error::Decode("The file has no video tracks".into())
+}
+
+#[cold]
+fn format_error() -> Error {
+    Error::Decode("ffmpeg format error".into())
+}
+
+#[cold]
+fn ffmpeg_error(e: ffmpeg::Error) -> Error {
+    Error::Decode(e.to_string())
+}
@@ -0,0 +1,171 @@
+//! Frames from Direct3D 11 staging textures, as given by DXGI Desktop Duplication and Windows.Graphics.Capture
+
+use crate::capture::CopiedPixels;
+use crate::error::*;
+use crate::yuv::half_size;
+use crate::{Collector, FrameOptions};
+use imgref::*;
+use rgb::alt::BGRA8;
+use rgb::*;
+use std::os::raw::c_void;
+
+const FORMAT_R8G8B8A8_UNORM: u32 = 28;
+const FORMAT_R8G8B8A8_UNORM_SRGB: u32 = 29;
+const FORMAT_B8G8R8A8_UNORM: u32 = 87;
+const FORMAT_B8G8R8X8_UNORM: u32 = 88;
+const FORMAT_B8G8R8A8_UNORM_SRGB: u32 = 91;
+const FORMAT_B8G8R8X8_UNORM_SRGB: u32 = 93;
+const FORMAT_NV12: u32 = 103;
+
+const USAGE_STAGING: u32 = 3;
+const CPU_ACCESS_READ: u32 = 0x20000;
+const MAP_READ: u32 = 1;
+
+#[repr(C)]
+#[derive(Default)]
+struct SampleDesc {
+    count: u32,
+    quality: u32,
+}
+
+/// `D3D11_TEXTURE2D_DESC`
+#[repr(C)]
+#[derive(Default)]
+struct Texture2DDesc {
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+    array_size: u32,
+    format: u32,
+    sample_desc: SampleDesc,
+    usage: u32,
+    bind_flags: u32,
+    cpu_access_flags: u32,
+    misc_flags: u32,
+}
+
+/// `D3D11_MAPPED_SUBRESOURCE`
+#[repr(C)]
+struct MappedSubresource {
+    data: *const c_void,
+    row_pitch: u32,
+    depth_pitch: u32,
+}
+
+/// Only the `ID3D11DeviceContext` methods that are used here. The first 14 are from `IUnknown`, `ID3D11DeviceChild` and draw state setters.
+#[repr(C)]
+struct DeviceContextVtbl {
+    _inherited: [usize; 14],
+    map: unsafe extern "system" fn(this: *mut DeviceContext, resource: *mut c_void, subresource: u32, map_type: u32, flags: u32, mapped: *mut MappedSubresource) -> i32,
+    unmap: unsafe extern "system" fn(this: *mut DeviceContext, resource: *mut c_void, subresource: u32),
+}
+
+/// Only `GetDesc`. The first 10 methods are from `IUnknown`, `ID3D11DeviceChild` and `ID3D11Resource`.
+#[repr(C)]
+struct Texture2DVtbl {
+    _inherited: [usize; 10],
+    get_desc: unsafe extern "system" fn(this: *mut Texture2D, desc: *mut Texture2DDesc),
+}
+
+#[repr(C)]
+struct DeviceContext {
+    vtbl: *const DeviceContextVtbl,
+}
+
+#[repr(C)]
+struct Texture2D {
+    vtbl: *const Texture2DVtbl,
+}
+
+/// Unmaps the texture when dropped
+struct Mapped {
+    context: *mut DeviceContext,
+    texture: *mut Texture2D,
+}
+
+impl Drop for Mapped {
+    fn drop(&mut self) {
+        unsafe {
+            ((*(*self.context).vtbl).unmap)(self.context, self.texture.cast(), 0);
+        }
+    }
+}
+
+impl Collector {
+    /// Add a frame from an `ID3D11Texture2D` in BGRA, RGBA or NV12 format.
+    ///
+    /// The texture must have been created with `D3D11_USAGE_STAGING` and `D3D11_CPU_ACCESS_READ`,
+    /// so copy GPU textures (e.g. from `IDXGIOutputDuplication::AcquireNextFrame`) into one with `CopyResource` first.
+    ///
+    /// The texture is mapped, its pixels are copied (row pitch padding is skipped), and it's unmapped before this function returns.
+    /// Conversion to RGBA and resizing happen later on the encoder's thread, so the capture thread isn't held up.
+    /// Frame index starts at 0, and the presentation timestamp is in seconds.
+    /// NV12 textures are assumed to be in the video (limited) range.
+    ///
+    /// # Safety
+    ///
+    /// `context` must be a valid `ID3D11DeviceContext` of the device that owns `texture`, and `texture` must be a valid `ID3D11Texture2D`.
+    /// The context must not be used by another thread during this call.
+    pub unsafe fn add_frame_d3d11_texture(&self, frame_index: usize, context: *mut c_void, texture: *mut c_void, presentation_timestamp: f64) -> CatResult<()> {
+        if context.is_null() || texture.is_null() {
+            return Err(Error::InvalidInput("Null D3D11 texture or device context".into()));
+        }
+        let pixels = copy_pixels(context.cast(), texture.cast())?;
+        let frame = self.deferred_frame(move || pixels.into_rgba());
+        self.push(frame_index, frame, presentation_timestamp, FrameOptions::default())
+    }
+}
+
+unsafe fn copy_pixels(context: *mut DeviceContext, texture: *mut Texture2D) -> CatResult<CopiedPixels> {
+    let mut desc = Texture2DDesc::default();
+    ((*(*texture).vtbl).get_desc)(texture, &mut desc);
+    if desc.usage != USAGE_STAGING || desc.cpu_access_flags & CPU_ACCESS_READ == 0 {
+        return Err(Error::InvalidInput("D3D11 texture must be a staging texture with CPU read access".into()));
+    }
+
+    let mut mapped = MappedSubresource { data: std::ptr::null(), row_pitch: 0, depth_pitch: 0 };
+    let hr = ((*(*context).vtbl).map)(context, texture.cast(), 0, MAP_READ, 0, &mut mapped);
+    if hr < 0 {
+        return Err(Error::InvalidInput(format!("Can't map D3D11 texture (HRESULT 0x{:08X})", hr as u32)));
+    }
+    let _mapped = Mapped { context, texture };
+
+    let width = desc.width as usize;
+    let height = desc.height as usize;
+    let stride_bytes = mapped.row_pitch as usize;
+    let base = mapped.data as *const u8;
+    let bytes_per_pixel = if desc.format == FORMAT_NV12 { 1 } else { 4 };
+    if base.is_null() || width == 0 || height == 0 || stride_bytes % bytes_per_pixel != 0 || stride_bytes < width * bytes_per_pixel {
+        return Err(Error::InvalidInput("Unsupported D3D11 texture layout".into()));
+    }
+
+    match desc.format {
+        format @ (FORMAT_B8G8R8A8_UNORM | FORMAT_B8G8R8A8_UNORM_SRGB | FORMAT_B8G8R8X8_UNORM | FORMAT_B8G8R8X8_UNORM_SRGB) => {
+            let data = std::slice::from_raw_parts(base, stride_bytes * (height - 1) + width * 4);
+            let bgra = ImgRef::new_stride(data.as_bgra(), width, height, stride_bytes / 4);
+            let pixels = if format == FORMAT_B8G8R8X8_UNORM || format == FORMAT_B8G8R8X8_UNORM_SRGB {
+                // the X channel is undefined
+                bgra.pixels().map(|px| BGRA8 { a: 255, ..px }).collect()
+            } else {
+                bgra.pixels().collect()
+            };
+            Ok(CopiedPixels::Bgra(ImgVec::new(pixels, width, height)))
+        },
+        FORMAT_R8G8B8A8_UNORM | FORMAT_R8G8B8A8_UNORM_SRGB => {
+            let data = std::slice::from_raw_parts(base, stride_bytes * (height - 1) + width * 4);
+            let rgba = ImgRef::new_stride(data.as_rgba(), width, height, stride_bytes / 4);
+            Ok(CopiedPixels::Rgba(ImgVec::new(rgba.pixels().collect(), width, height)))
+        },
+        FORMAT_NV12 => {
+            // the chroma plane follows the luma plane, with the same pitch
+            let chroma_height = half_size(height);
+            let chroma_width = half_size(width) * 2;
+            let data = std::slice::from_raw_parts(base, stride_bytes * (height + chroma_height - 1) + chroma_width);
+            let (luma, chroma) = data.split_at(stride_bytes * height);
+            let luma = ImgRef::new_stride(luma, width, height, stride_bytes);
+            let chroma = ImgRef::new_stride(chroma, chroma_width, chroma_height, stride_bytes);
+            Ok(CopiedPixels::nv12(luma, chroma, false))
+        },
+        other => Err(Error::InvalidInput(format!("Unsupported D3D11 texture format (DXGI_FORMAT {})", other))),
+    }
+}
@@ -0,0 +1,159 @@
+//! Reading YUV4MPEG2 (`.y4m`) streams
+//!
+//! It's the simplest uncompressed video format that ffmpeg can pipe out:
+//! `ffmpeg -i video.mp4 -f yuv4mpegpipe -`. Only 8-bit color spaces are supported.
+
+use crate::error::*;
+use crate::yuv::*;
+use crate::Collector;
+use imgref::*;
+use std::io::{self, BufRead, Read};
+
+/// Larger frames are surely an error in the stream, and would make GIFs that no browser would open anyway
+const MAX_PIXELS: usize = 1 << 28;
+
+impl Collector {
+    /// Add all frames of a YUV4MPEG2 stream, such as ffmpeg's `-f yuv4mpegpipe` output.
+    ///
+    /// Timestamps are computed from the frame rate in the stream header.
+    /// Frames are added starting from frame index 0. Returns the number of frames added.
+    pub fn add_frames_from_y4m(&self, mut reader: impl BufRead) -> CatResult<usize> {
+        let header = StreamHeader::read(&mut reader)?;
+        let mut frame_index = 0;
+        while let Some(image) = header.read_frame(&mut reader)? {
+            self.add_frame_rgba(frame_index, image, frame_index as f64 / header.fps)?;
+            frame_index += 1;
+        }
+        Ok(frame_index)
+    }
+}
+
+struct StreamHeader {
+    width: usize,
+    height: usize,
+    fps: f64,
+    /// Whether chroma planes are half the width and half the height, or `None` if there's no chroma
+    subsampling: Option<(bool, bool)>,
+    full_range: bool,
+}
+
+impl StreamHeader {
+    fn read(reader: &mut impl BufRead) -> CatResult<Self> {
+        let line = read_line(reader)?.ok_or_else(|| invalid("The stream is empty"))?;
+        let mut params = line.split(' ');
+        if params.next() != Some("YUV4MPEG2") {
+            return Err(invalid("Not a YUV4MPEG2 stream"));
+        }
+
+        let mut width: Option<usize> = None;
+        let mut height = None;
+        let mut fps = None;
+        let mut subsampling = Some((true, true));
+        let mut full_range = false;
+        for param in params.filter(|p| !p.is_empty()) {
+            let (tag, value) = param.split_at(1);
+            match tag {
+                "W" => width = value.parse().ok(),
+                "H" => height = value.parse().ok(),
+                "F" => fps = parse_ratio(value),
+                "C" => subsampling = match value {
+                    "420" | "420jpeg" | "420paldv" | "420mpeg2" => Some((true, true)),
+                    "422" => Some((true, false)),
+                    "444" => Some((false, false)),
+                    "mono" => None,
+                    _ => return Err(Error::Decode(format!("Unsupported YUV4MPEG2 color space {} (only 8-bit ones are supported)", value))),
+                },
+                "X" if value == "COLORRANGE=FULL" => full_range = true,
+                _ => {},
+            }
+        }
+
+        let (width, height) = match (width, height) {
+            (Some(w), Some(h)) if w > 0 && h > 0 && w.checked_mul(h).map_or(false, |px| px <= MAX_PIXELS) => (w, h),
+            _ => return Err(invalid("Invalid frame size in the YUV4MPEG2 header")),
+        };
+        Ok(Self {
+            width,
+            height,
+            fps: fps.filter(|&f| f > 0. && f.is_finite()).ok_or_else(|| invalid("Invalid frame rate in the YUV4MPEG2 header"))?,
+            subsampling,
+            full_range,
+        })
+    }
+
+    fn read_frame(&self, reader: &mut impl BufRead) -> CatResult<Option<ImgVec<rgb::RGBA8>>> {
+        match read_line(reader)? {
+            None => return Ok(None),
+            Some(line) if line == "FRAME" || line.starts_with("FRAME ") => {},
+            Some(_) => return Err(invalid("Invalid YUV4MPEG2 frame header")),
+        }
+        let (width, height) = (self.width, self.height);
+        let mut luma = vec![0; width * height];
+        reader.read_exact(&mut luma)?;
+        let luma = ImgVec::new(luma, width, height);
+        let matrix = YuvMatrix::for_size(width, height);
+
+        Ok(Some(match self.subsampling {
+            Some((half_width, half_height)) => {
+                let half = |n: usize, is_half: bool| if is_half { half_size(n) } else { n };
+                let (cw, ch) = (half(width, half_width), half(height, half_height));
+                let mut chroma = vec![0; cw * ch * 2];
+                reader.read_exact(&mut chroma)?;
+                let (u, v) = chroma.split_at(cw * ch);
+                planar_to_rgba(luma.as_ref(), ImgRef::new(u, cw, ch), ImgRef::new(v, cw, ch), matrix, self.full_range)
+            },
+            None => {
+                let gray = ImgVec::new(vec![128; 1], 1, 1);
+                planar_to_rgba(luma.as_ref(), gray.as_ref(), gray.as_ref(), matrix, self.full_range)
+            },
+        }))
+    }
+}
+
+/// `None` at the end of the stream
+fn read_line(reader: &mut impl BufRead) -> CatResult<Option<String>> {
+    let mut line = Vec::new();
+    // headers are short, so a missing newline means it's not a Y4M stream
+    reader.by_ref().take(1024).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    String::from_utf8(line).map(Some).map_err(|_| invalid("Invalid YUV4MPEG2 header"))
+}
+
+fn parse_ratio(value: &str) -> Option<f64> {
+    let mut parts = value.split(':');
+    let num: f64 = parts.next()?.parse().ok()?;
+    let den: f64 = parts.next()?.parse().ok()?;
+    Some(num / den)
+}
+
+#[cold]
+fn invalid(msg: &str) -> Error {
+    Error::Decode(msg.into())
+}
+
+#[test]
+fn reads_y4m_stream() {
+    let mut data = b"YUV4MPEG2 W3 H2 F25:1 Ip A1:1 C420jpeg XYSCSS=420JPEG\n".to_vec();
+    for _ in 0..2 {
+        data.extend_from_slice(b"FRAME\n");
+        data.extend_from_slice(&[16, 16, 16, 235, 235, 235]);
+        data.extend_from_slice(&[128, 128, 128, 128]);
+    }
+
+    let mut reader = &data[..];
+    let header = StreamHeader::read(&mut reader).unwrap();
+    assert_eq!((3, 2, 25.), (header.width, header.height, header.fps));
+    let frame = header.read_frame(&mut reader).unwrap().unwrap();
+    assert_eq!(rgb::RGBA8::new(0, 0, 0, 255), frame[(2usize, 0usize)]);
+    assert_eq!(rgb::RGBA8::new(255, 255, 255, 255), frame[(0usize, 1usize)]);
+    assert!(header.read_frame(&mut reader).unwrap().is_some());
+    assert!(header.read_frame(&mut reader).unwrap().is_none());
+
+    assert!(StreamHeader::read(&mut &b"YUV4MPEG2 W3 H2 F25:1 C420p10\n"[..]).is_err());
+    assert!(StreamHeader::read(&mut &b"YUV4MPEG2 W4294967296 H4294967296 F25:1\n"[..]).is_err());
+}
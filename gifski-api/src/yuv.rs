@@ -0,0 +1,179 @@
+//! Conversion of YUV (Y′CbCr) frames to RGB
+
+use crate::error::*;
+use crate::{Collector, FrameOptions};
+use imgref::*;
+use rgb::*;
+
+/// Coefficients for converting YUV to RGB
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum YuvMatrix {
+    /// Standard definition video
+    Bt601,
+    /// HD video
+    Bt709,
+}
+
+impl YuvMatrix {
+    /// Guess used when the video doesn't say: BT.709 for HD sizes, BT.601 for smaller ones
+    pub fn for_size(width: usize, height: usize) -> Self {
+        if width >= 1280 || height > 576 { Self::Bt709 } else { Self::Bt601 }
+    }
+
+    /// (Kr, Kb)
+    fn coefficients(self) -> (f32, f32) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// How YUV values of a frame map to RGB
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub struct YuvColorSpace {
+    /// If `None`, it's guessed from the frame size
+    pub matrix: Option<YuvMatrix>,
+    /// JPEG-style 0-255 values, instead of video's 16-235 (16-240 for chroma)
+    pub full_range: bool,
+}
+
+impl Collector {
+    /// Add a frame from planar YUV 4:2:0 (I420) data, as decoded by most video decoders.
+    ///
+    /// The `u` and `v` planes must be half the size of `y`, rounded up. Use `ImgRef::new_stride()` for planes with padding.
+    /// The pixels are converted to RGB right away, so the planes can be reused after this call.
+    pub fn add_frame_yuv420(&self, frame_index: usize, y: ImgRef<'_, u8>, u: ImgRef<'_, u8>, v: ImgRef<'_, u8>, colors: YuvColorSpace, presentation_timestamp: f64) -> CatResult<()> {
+        let chroma_size = (half_size(y.width()), half_size(y.height()));
+        if (u.width(), u.height()) != chroma_size || (v.width(), v.height()) != chroma_size {
+            return Err(Error::InvalidInput(format!("YUV 4:2:0 chroma planes of a {}×{} frame must be {}×{}", y.width(), y.height(), chroma_size.0, chroma_size.1)));
+        }
+        let matrix = colors.matrix.unwrap_or_else(|| YuvMatrix::for_size(y.width(), y.height()));
+        self.add_frame_rgba(frame_index, planar_to_rgba(y, u, v, matrix, colors.full_range), presentation_timestamp)
+    }
+}
+
+impl Collector {
+    /// Add a frame from NV12 data: a Y plane, and a plane of interleaved U and V samples at half resolution.
+    /// It's the usual output of hardware video decoders.
+    ///
+    /// Width of `uv` is in bytes (twice the number of U/V pairs per row). Use `ImgRef::new_stride()` for planes with padding.
+    ///
+    /// The planes are only copied here. Conversion to RGB (and resizing) happens on the encoder's thread.
+    pub fn add_frame_nv12(&self, frame_index: usize, y: ImgRef<'_, u8>, uv: ImgRef<'_, u8>, colors: YuvColorSpace, presentation_timestamp: f64) -> CatResult<()> {
+        let chroma_size = (half_size(y.width()) * 2, half_size(y.height()));
+        if (uv.width(), uv.height()) != chroma_size {
+            return Err(Error::InvalidInput(format!("NV12 chroma plane of a {}×{} frame must be {}×{} bytes", y.width(), y.height(), chroma_size.0, chroma_size.1)));
+        }
+        let luma = ImgVec::new(y.pixels().collect(), y.width(), y.height());
+        let chroma = ImgVec::new(uv.pixels().collect(), uv.width(), uv.height());
+        let matrix = colors.matrix.unwrap_or_else(|| YuvMatrix::for_size(y.width(), y.height()));
+        let frame = self.deferred_frame(move || {
+            nv12_to_rgba(luma.as_ref(), chroma.as_ref(), matrix, colors.full_range)
+        });
+        self.push(frame_index, frame, presentation_timestamp, FrameOptions::default())
+    }
+}
+
+/// Size of subsampled chroma, rounded up
+pub(crate) fn half_size(n: usize) -> usize {
+    n / 2 + n % 2
+}
+
+/// Converts a single pixel. `full_range` is for JPEG-style 0-255 values, instead of video's 16-235.
+#[inline]
+pub(crate) fn yuv_to_rgb(y: u8, u: u8, v: u8, matrix: YuvMatrix, full_range: bool) -> RGBA8 {
+    let (kr, kb) = matrix.coefficients();
+    let kg = 1. - kr - kb;
+    let (y, u, v) = if full_range {
+        (y as f32 / 255., (u as f32 - 128.) / 255., (v as f32 - 128.) / 255.)
+    } else {
+        ((y as f32 - 16.) / 219., (u as f32 - 128.) / 224., (v as f32 - 128.) / 224.)
+    };
+    let r = y + 2. * (1. - kr) * v;
+    let b = y + 2. * (1. - kb) * u;
+    let g = (y - kr * r - kb * b) / kg;
+    let to_u8 = |c: f32| (c * 255.).round().clamp(0., 255.) as u8;
+    RGBA8::new(to_u8(r), to_u8(g), to_u8(b), 255)
+}
+
+/// Y plane followed by a plane of interleaved U and V at half resolution.
+///
+/// Width of `chroma` is in bytes, i.e. twice the number of chroma samples per row.
+pub(crate) fn nv12_to_rgba(luma: ImgRef<'_, u8>, chroma: ImgRef<'_, u8>, matrix: YuvMatrix, full_range: bool) -> ImgVec<RGBA8> {
+    let mut out = Vec::with_capacity(luma.width() * luma.height());
+    for (y, row) in luma.rows().enumerate() {
+        let chroma_row = &chroma[(y / 2).min(chroma.height() - 1)];
+        for (x, &l) in row.iter().enumerate() {
+            let c = (x / 2).min(chroma_row.len() / 2 - 1) * 2;
+            let (u, v) = (chroma_row[c], chroma_row[c + 1]);
+            out.push(yuv_to_rgb(l, u, v, matrix, full_range));
+        }
+    }
+    ImgVec::new(out, luma.width(), luma.height())
+}
+
+/// Three separate planes. Chroma planes can be subsampled by any factor (4:2:0, 4:2:2 and 4:4:4 are the common ones),
+/// which is found from their size.
+pub(crate) fn planar_to_rgba(luma: ImgRef<'_, u8>, u: ImgRef<'_, u8>, v: ImgRef<'_, u8>, matrix: YuvMatrix, full_range: bool) -> ImgVec<RGBA8> {
+    let (width, height) = (luma.width(), luma.height());
+    let mut out = Vec::with_capacity(width * height);
+    for (y, row) in luma.rows().enumerate() {
+        let cy = y * u.height() / height;
+        let (u_row, v_row) = (&u[cy], &v[cy]);
+        for (x, &l) in row.iter().enumerate() {
+            let cx = x * u.width() / width;
+            out.push(yuv_to_rgb(l, u_row[cx], v_row[cx], matrix, full_range));
+        }
+    }
+    ImgVec::new(out, width, height)
+}
+
+#[test]
+fn converts_grays_and_colors() {
+    assert_eq!(RGBA8::new(0, 0, 0, 255), yuv_to_rgb(16, 128, 128, YuvMatrix::Bt709, false));
+    assert_eq!(RGBA8::new(255, 255, 255, 255), yuv_to_rgb(235, 128, 128, YuvMatrix::Bt709, false));
+    assert_eq!(RGBA8::new(128, 128, 128, 255), yuv_to_rgb(128, 128, 128, YuvMatrix::Bt601, true));
+    // BT.601 full-range red
+    let red = yuv_to_rgb(76, 85, 255, YuvMatrix::Bt601, true);
+    assert!(red.r > 250 && red.g < 5 && red.b < 5, "{:?}", red);
+
+    let luma = ImgVec::new(vec![16, 235, 16, 235], 2, 2);
+    let chroma = ImgVec::new(vec![128, 128], 2, 1);
+    let rgb = nv12_to_rgba(luma.as_ref(), chroma.as_ref(), YuvMatrix::for_size(2, 2), false);
+    assert_eq!(255, rgb[(1usize, 1usize)].g);
+    assert_eq!(0, rgb[(0usize, 1usize)].g);
+
+    let luma = ImgVec::new(vec![235; 3 * 3], 3, 3);
+    let u = ImgVec::new(vec![128, 128, 128, 128], 2, 2);
+    let v = ImgVec::new(vec![128, 128, 128, 240], 2, 2);
+    let rgb = planar_to_rgba(luma.as_ref(), u.as_ref(), v.as_ref(), YuvMatrix::Bt601, false);
+    assert_eq!(RGBA8::new(255, 255, 255, 255), rgb[(1usize, 1usize)]);
+    assert_eq!(255, rgb[(2usize, 2usize)].r);
+    assert!(rgb[(2usize, 2usize)].g < 200);
+}
+
+#[test]
+fn adds_nv12_frames() {
+    let (collector, writer) = crate::new(crate::Settings { width: Some(2), ..crate::Settings::default() }).unwrap();
+    let y = ImgVec::new(vec![235; 4 * 4], 4, 4);
+    let uv = ImgVec::new(vec![128; 4 * 2], 4, 2);
+    collector.add_frame_nv12(0, y.as_ref(), uv.as_ref(), YuvColorSpace::default(), 0.).unwrap();
+    collector.add_frame_nv12(1, y.as_ref(), uv.as_ref(), YuvColorSpace::default(), 0.1).unwrap();
+    assert!(collector.add_frame_nv12(2, y.as_ref(), y.as_ref(), YuvColorSpace::default(), 0.2).is_err());
+    drop(collector);
+    let mut out = Vec::new();
+    writer.write(&mut out, &mut crate::progress::NoProgress {}).unwrap();
+    let mut decoder = gif::DecodeOptions::new().read_info(&out[..]).unwrap();
+    assert_eq!((2, 2), (decoder.width(), decoder.height()));
+    assert!(decoder.read_next_frame().unwrap().is_some());
+}
+
+#[test]
+fn checks_yuv420_plane_sizes() {
+    let (collector, _writer) = crate::new(crate::Settings::default()).unwrap();
+    let y = ImgVec::new(vec![16; 3 * 3], 3, 3);
+    let small = ImgVec::new(vec![128; 1], 1, 1);
+    let err = collector.add_frame_yuv420(0, y.as_ref(), small.as_ref(), small.as_ref(), YuvColorSpace::default(), 0.);
+    assert!(matches!(err, Err(Error::InvalidInput(_))));
+}